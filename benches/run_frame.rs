@@ -0,0 +1,71 @@
+//Headless throughput. Each iteration of run_frame is one frame, so criterion's
+//time per iteration converts directly into frames per second. Compare trees with
+//--save-baseline and --baseline. Runs of the same tree can differ by 10% or more
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nesemu::cpu6502::{Cpu, FlatRam};
+use nesemu::prelude::*;
+
+//Turns on NMI and background rendering, then spins through a loop of loads, stores,
+//read-modify-writes and branches. The NMI handler does a little work of its own
+const PROGRAM: [u8; 35] = [
+    0x78, //      SEI
+    0xA9, 0x80, //LDA #$80
+    0x8D, 0x00, 0x20, //STA $2000
+    0xA9, 0x08, //LDA #$08
+    0x8D, 0x01, 0x20, //STA $2001
+    0xA2, 0x00, //loop: LDX #$00
+    0xB5, 0x00, //inner: LDA $00,X
+    0x69, 0x01, //ADC #$01
+    0x9D, 0x00, 0x03, //STA $0300,X
+    0x1E, 0x00, 0x03, //ASL $0300,X
+    0xE8, //      INX
+    0xD0, 0xF3, //BNE inner
+    0x4C, 0x0B, 0xC0, //JMP loop
+    //NMI handler at 0xC01D
+    0xE6, 0x10, //INC $10
+    0xAD, 0x02, 0x20, //LDA $2002
+    0x40, //      RTI
+];
+
+//NROM-128 with the program at 0xC000 and the vectors pointing into it
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    prg[0x3FFA..].copy_from_slice(&[0x1D, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+    rom
+}
+
+fn run_frame(c: &mut Criterion) {
+    let mut emu = Emulator::new(rom());
+    let mut group = c.benchmark_group("emulator");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("run_frame", |b| b.iter(|| emu.run_frame()));
+    group.finish();
+}
+
+//The cpu without the ppu, one frame's worth of cycles
+fn cpu_only(c: &mut Criterion) {
+    let mut ram = FlatRam::new();
+    ram.load(0xC000, &PROGRAM);
+    ram.load(0xFFFC, &[0x00, 0xC0]);
+    let mut cpu = Cpu::new(ram);
+    cpu.power_on();
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("frame_of_cycles", |b| {
+        b.iter(|| {
+            for _ in 0..29781 {
+                cpu.step_cycle();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, run_frame, cpu_only);
+criterion_main!(benches);
@@ -3,6 +3,7 @@ use clap::{App, Arg};
 use minifb::{Key, Window, WindowOptions};
 use nesemu::prelude::*;
 use image::{RgbImage, Rgb, ImageBuffer};
+use std::io::Write;
 
 fn main() {
     let matches = App::new("rust-nes")
@@ -17,6 +18,44 @@ fn main() {
                 .help("ROM file to load")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .value_name("FILE")
+                .help("Labels from an ld65 .dbg, FCEUX .nl or Mesen .mlb file")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("cdl")
+                .long("cdl")
+                .value_name("FILE")
+                .help("Code/data log to write on exit, merged with the file if it exists")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("power-on")
+                .long("power-on")
+                .value_name("STATE")
+                .help("Memory at power on: zeros, ff, pattern, random or random:SEED")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cheats")
+                .long("cheats")
+                .value_name("FILE")
+                .help("Cheats from a libretro or FCEUX .cht file")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("cheat")
+                .long("cheat")
+                .value_name("CODE")
+                .help("Game Genie code or RAM freeze (AAAA:VV)")
+                .takes_value(true)
+                .multiple(true),
+        )
         .get_matches();
 
     let rom_path = match matches.value_of("exec") {
@@ -30,7 +69,45 @@ fn main() {
     println!("Recived argument {}", rom_path);
     let data = std::fs::read(rom_path).expect("Failed to read file");
 
-    let mut emu = Emulator::new(data);
+    let power_on_state = match matches.value_of("power-on") {
+        Some(text) => text.parse().unwrap_or_else(|e| panic!("{}", e)),
+        None => PowerOnState::default(),
+    };
+    //Pass this back in with --power-on to repeat the run
+    println!("Power on state: {}", power_on_state);
+    let mut emu = Emulator::with_power_on_state(data, power_on_state);
+
+    for path in matches.values_of("symbols").into_iter().flatten() {
+        emu.debugger()
+            .symbols()
+            .load_file(std::path::Path::new(path))
+            .unwrap_or_else(|e| panic!("Can't load symbols from {}: {}", path, e));
+    }
+
+    for path in matches.values_of("cheats").into_iter().flatten() {
+        let text = std::fs::read_to_string(path).expect("Failed to read cheat file");
+        let cheats = nesemu::cheats::parse(&text)
+            .unwrap_or_else(|e| panic!("Can't load cheats from {}: {}", path, e));
+        for cheat in cheats {
+            emu.add_cheat(cheat);
+        }
+    }
+    for code in matches.values_of("cheat").into_iter().flatten() {
+        let cheat = nesemu::cheats::Cheat::new(code, code)
+            .unwrap_or_else(|e| panic!("Bad cheat: {}", e));
+        emu.add_cheat(cheat);
+    }
+
+    emu.debugger().set_crash_reporting(true);
+
+    let cdl_path = matches.value_of("cdl");
+    if let Some(path) = cdl_path {
+        emu.start_code_data_log();
+        if let Ok(previous) = std::fs::read(path) {
+            emu.load_code_data_log(&previous)
+                .unwrap_or_else(|e| panic!("Can't merge {}: {}", path, e));
+        }
+    }
 
     let mut window =
         Window::new("NES Emulator", 256 * 3, 240 * 3, WindowOptions::default()).unwrap();
@@ -38,7 +115,9 @@ fn main() {
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
     'game_loop: loop {
-        emu.run_frame();
+        if let StopReason::Jammed(_) = emu.run_frame() {
+            eprintln!("{}", emu.crash_report());
+        }
         //Render frame
         window.update_with_buffer(emu.buffer(), 256, 240).unwrap();
 
@@ -60,6 +139,15 @@ fn main() {
             keys.contains(&Key::RightShift),
         ));
 
+        //Reset button and power switch
+        if window.is_key_released(Key::R) {
+            emu.reset();
+        }
+
+        if window.is_key_released(Key::P) {
+            emu.power_cycle();
+        }
+
         //Save image of nametable
         if window.is_key_released(Key::N) {
             println!("Saving nametable...");
@@ -72,5 +160,19 @@ fn main() {
             }
             img.save("nametable.png");
         }
+
+        //Dump every memory domain
+        if window.is_key_released(Key::M) {
+            println!("Saving memory...");
+            let mut file = std::fs::File::create("memory.txt").expect("Failed to create memory.txt");
+            for domain in Domain::ALL.iter() {
+                writeln!(file, "{}", domain).expect("Failed to write memory.txt");
+                emu.hex_dump(*domain, &mut file).expect("Failed to write memory.txt");
+            }
+        }
+    }
+
+    if let (Some(path), Some(log)) = (cdl_path, emu.stop_code_data_log()) {
+        std::fs::write(path, log.to_fceux()).expect("Failed to write code/data log");
     }
 }
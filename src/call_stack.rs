@@ -0,0 +1,163 @@
+//Shadow call stack, kept from the instructions the cpu runs. JSR, BRK and interrupts
+//push a frame, RTS and RTI pop one. Games that juggle the stack themselves (RTS jump
+//tables, handlers that never return) can leave it out of step, it's a debugging aid
+
+use std::fmt;
+
+//Frames beyond this drop the oldest, so a game that never returns can't grow it forever
+const MAX_DEPTH: usize = 256;
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Subroutine,
+    Brk,
+    Nmi,
+    Irq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    pub caller: u16, //The JSR or BRK, or the instruction an interrupt came in before
+    pub target: u16, //First instruction of the subroutine or handler
+    pub stack_pointer: u8, //S before the return address was pushed
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            FrameKind::Subroutine => "JSR",
+            FrameKind::Brk => "BRK",
+            FrameKind::Nmi => "NMI",
+            FrameKind::Irq => "IRQ",
+        };
+        write!(
+            f,
+            "{} ${:04X} from ${:04X} (SP:{:02X})",
+            kind, self.target, self.caller, self.stack_pointer
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    entering: bool, //The top frame's target is the next instruction to run
+}
+
+impl CallStack {
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.entering = false;
+    }
+
+    //Called before each instruction runs, with nmi_vector to tell NMIs from IRQs
+    pub(crate) fn instruction(&mut self, pc: u16, opcode: u8, s: u8, nmi_vector: u16) {
+        self.enter(pc, nmi_vector);
+        match opcode {
+            JSR => self.push(FrameKind::Subroutine, pc, s),
+            BRK => self.push(FrameKind::Brk, pc, s),
+            RTS | RTI => {
+                self.frames.pop();
+            }
+            _ => (),
+        }
+    }
+
+    //Called before an interrupt sequence starts, pc is where it will return to
+    pub(crate) fn interrupt(&mut self, pc: u16, s: u8, nmi_vector: u16) {
+        self.enter(pc, nmi_vector);
+        //Which one it is only shows once the handler is reached, NMI can hijack an IRQ
+        self.push(FrameKind::Irq, pc, s);
+    }
+
+    //Fills in the target of a frame just pushed, once pc is known. instruction and
+    //interrupt do this themselves
+    pub(crate) fn enter(&mut self, pc: u16, nmi_vector: u16) {
+        if !self.entering {
+            return;
+        }
+        self.entering = false;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.target = pc;
+            if frame.kind == FrameKind::Irq && pc == nmi_vector {
+                frame.kind = FrameKind::Nmi;
+            }
+        }
+    }
+
+    fn push(&mut self, kind: FrameKind, caller: u16, stack_pointer: u8) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(CallFrame {
+            kind,
+            caller,
+            target: caller,
+            stack_pointer,
+        });
+        self.entering = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subroutines() {
+        let mut stack = CallStack::default();
+        stack.instruction(0xC000, JSR, 0xFD, 0x9000);
+        stack.instruction(0xC100, JSR, 0xFB, 0x9000);
+        stack.instruction(0xC200, 0xEA, 0xF9, 0x9000);
+
+        let frames = stack.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].caller, frames[0].target), (0xC000, 0xC100));
+        assert_eq!((frames[1].caller, frames[1].target), (0xC100, 0xC200));
+
+        stack.instruction(0xC201, RTS, 0xF9, 0x9000);
+        assert_eq!(stack.depth(), 1);
+    }
+
+    #[test]
+    fn test_interrupt_kinds() {
+        let mut stack = CallStack::default();
+        stack.interrupt(0xC000, 0xFD, 0x9000);
+        stack.instruction(0x9000, 0xEA, 0xFA, 0x9000);
+        stack.interrupt(0x9001, 0xFA, 0x9000);
+        stack.instruction(0xA000, 0xEA, 0xF7, 0x9000);
+
+        let kinds: Vec<FrameKind> = stack.frames().iter().map(|frame| frame.kind).collect();
+        assert_eq!(kinds, vec![FrameKind::Nmi, FrameKind::Irq]);
+        assert_eq!(
+            stack.frames()[1].to_string(),
+            "IRQ $A000 from $9001 (SP:FA)"
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_returns() {
+        let mut stack = CallStack::default();
+        stack.instruction(0xC000, RTS, 0xFD, 0x9000);
+        assert_eq!(stack.depth(), 0);
+
+        for _ in 0..MAX_DEPTH + 10 {
+            stack.instruction(0xC000, JSR, 0xFD, 0x9000);
+        }
+        assert_eq!(stack.depth(), MAX_DEPTH);
+    }
+}
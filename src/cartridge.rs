@@ -1,4 +1,5 @@
-use crate::memory;
+use crate::cdl;
+use crate::memory::{self, AddressSpace};
 use std::fs;
 
 #[derive(Debug, Clone, Copy)]
@@ -8,9 +9,11 @@ pub enum MirrorMode {
 }
 
 pub struct Cartridge {
+    #[allow(dead_code)]
     pub trainer_present: bool,
     pub prg_rom_data: Option<Vec<u8>>,
     pub chr_rom_data: Option<Vec<u8>>,
+    pub prg_ram_size: usize,
     pub mirror_mode: MirrorMode,
     pub mapper: u32,
 }
@@ -28,12 +31,14 @@ impl Cartridge {
             map_mirror_mode(header[6] & 0b00000001).expect("Unsupported mirror mode!");
         let trainer_present = (header[6] & 0b00000100) >> 2 == 1;
         let mapper = (header[7] & 0b11110000) + ((header[6] & 0b11110000) >> 4);
+        //Battery flag, or a PRG-RAM size in byte 8. Without a mapper to bank it only 8k fits
+        let has_prg_ram = header[6] & 0b00000010 != 0 || header[8] != 0;
         let _char_mirror_text = String::new();
 
-        let prg_start = 16 as usize;
+        let prg_start = 16_usize;
         let prg_end = prg_start + (header[4] as usize * 16384);
-        let chr_start = prg_end as usize;
-        let chr_end = chr_start + (header[5] as usize * 8192) as usize;
+        let chr_start = prg_end;
+        let chr_end = chr_start + (header[5] as usize * 8192);
 
         // for val in data[prg_start..prg_end].to_vec() {
         //     print!("{:#X} ", val);
@@ -43,6 +48,7 @@ impl Cartridge {
             trainer_present,
             prg_rom_data: Some(data[prg_start..prg_end].to_vec()),
             chr_rom_data: Some(data[chr_start..chr_end].to_vec()),
+            prg_ram_size: if has_prg_ram { PRG_RAM_SIZE } else { 0 },
             mirror_mode: char_mirror,
             mapper: mapper as u32,
         }
@@ -51,6 +57,7 @@ impl Cartridge {
     pub fn take_program_data(&mut self) -> ProgramData {
         ProgramData {
             data: self.prg_rom_data.take().unwrap(),
+            ram: vec![0; self.prg_ram_size],
         }
     }
 
@@ -58,6 +65,7 @@ impl Cartridge {
         CharacterData {
             data: self.chr_rom_data.take().unwrap(),
             mirror: self.mirror_mode,
+            log: None,
         }
     }
 
@@ -65,6 +73,7 @@ impl Cartridge {
     pub fn print_stats(&self) {
         println!("Mapper: {}", self.mapper);
         println!("Character Mirroring: {:?}", self.mirror_mode);
+        println!("Program RAM size: {} bytes", self.prg_ram_size);
         match &self.prg_rom_data {
             Some(d) => println!("Program ROM size: {} bytes", d.len()),
             None => println!("Unable to read program rom data. Already taken"),
@@ -79,40 +88,95 @@ impl Cartridge {
 
 pub struct ProgramData {
     data: Vec<u8>,
+    ram: Vec<u8>, //PRG-RAM at $6000-$7FFF, empty when the cartridge has none
+}
+
+//PRG-ROM comes in 16k banks
+pub const PRG_BANK_SIZE: usize = 0x4000;
+
+pub const PRG_RAM_SIZE: usize = 0x2000;
+
+impl ProgramData {
+    //Offset into PRG-ROM of a cpu address. A single 16k bank is mirrored at 0xC000
+    pub fn rom_offset(&self, ptr: u16) -> Option<usize> {
+        match ptr {
+            0x8000..=0xFFFF if !self.data.is_empty() => {
+                Some((ptr as usize - 0x8000) % self.data.len())
+            }
+            _ => None,
+        }
+    }
+
+    //Offset into PRG-RAM of a cpu address
+    pub fn ram_offset(&self, ptr: u16) -> Option<usize> {
+        match ptr {
+            0x6000..=0x7FFF if !self.ram.is_empty() => {
+                Some((ptr as usize - 0x6000) % self.ram.len())
+            }
+            _ => None,
+        }
+    }
+
+    //PRG bank the cpu address is in
+    pub fn bank(&self, ptr: u16) -> Option<usize> {
+        self.rom_offset(ptr).map(|offset| offset / PRG_BANK_SIZE)
+    }
+
+    //Whether the cartridge answers reads of a cpu address. Anything else is open bus
+    pub fn drives(&self, ptr: u16) -> bool {
+        self.rom_offset(ptr).is_some() || self.ram_offset(ptr).is_some()
+    }
+
+    //Every cpu address the PRG-ROM offset shows up at, the inverse of rom_offset
+    pub fn cpu_addresses(&self, offset: usize) -> Vec<u16> {
+        if offset >= self.data.len() {
+            return Vec::new();
+        }
+        (0x8000 + offset..=0xFFFF)
+            .step_by(self.data.len())
+            .map(|address| address as u16)
+            .collect()
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 impl memory::AddressSpace for ProgramData {
     fn peek(&mut self, ptr: u16) -> u8 {
-        match self.data.len() {
-            l if l < 17000 => match ptr {
-                0x8000..=0xBFFF => self.data[((ptr - 0x8000) as usize)],
-                0xC000..=0xFFFF => self.data[((ptr - 0xC000) as usize)],
-                _ => 0x00,
-            },
-            _ => match ptr {
-                0x8000..=0xBFFF => self.data[((ptr - 0x8000) as usize)],
-                0xC000..=0xFFFF => self.data[((ptr - 0x8000) as usize)],
-                _ => 0x00,
-            },
+        self.debug_peek(ptr)
+    }
+
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        match (self.rom_offset(ptr), self.ram_offset(ptr)) {
+            (Some(offset), _) => self.data[offset],
+            (_, Some(offset)) => self.ram[offset],
+            _ => 0x00,
         }
     }
 
     fn poke(&mut self, ptr: u16, byte: u8) {
-        match self.data.len() {
-            l if l < 17000 => {
-                match ptr {
-                    0x8000..=0xBFFF => self.data[((ptr - 0x8000) as usize)] = byte,
-                    0xC000..=0xFFFF => self.data[((ptr - 0xC000) as usize)] = byte,
-                    _ => (),
-                };
-            }
-            _ => {
-                match ptr {
-                    0x8000..=0xBFFF => self.data[((ptr - 0x8000) as usize)] = byte,
-                    0xC000..=0xFFFF => self.data[((ptr - 0x8000) as usize)] = byte,
-                    _ => (),
-                };
-            }
+        if let Some(offset) = self.rom_offset(ptr) {
+            self.data[offset] = byte;
+        } else if let Some(offset) = self.ram_offset(ptr) {
+            self.ram[offset] = byte;
         }
     }
 }
@@ -120,13 +184,66 @@ impl memory::AddressSpace for ProgramData {
 pub struct CharacterData {
     data: Vec<u8>,
     pub mirror: MirrorMode,
+    log: Option<Vec<u8>>, //Code/data log flags of each byte, while logging
+}
+
+impl CharacterData {
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    //Flags each byte as it's drawn or read by the cpu, until stop_log
+    pub fn start_log(&mut self) {
+        if self.log.is_none() {
+            self.log = Some(vec![0; self.data.len()]);
+        }
+    }
+
+    pub fn stop_log(&mut self) -> Option<Vec<u8>> {
+        self.log.take()
+    }
+
+    //Puts back flags taken with stop_log
+    pub fn restore_log(&mut self, log: Option<Vec<u8>>) {
+        self.log = log;
+    }
+
+    pub fn log(&self) -> Option<&[u8]> {
+        self.log.as_deref()
+    }
+
+    //Read by the cpu through PPUDATA. Rendering goes through peek
+    pub fn read(&mut self, ptr: u16) -> u8 {
+        self.flag(ptr, cdl::READ);
+        self.debug_peek(ptr)
+    }
+
+    fn flag(&mut self, ptr: u16, flag: u8) {
+        if let Some(flags) = self.log.as_mut().and_then(|log| log.get_mut(ptr as usize)) {
+            *flags |= flag;
+        }
+    }
 }
 
 impl memory::AddressSpace for CharacterData {
     fn peek(&mut self, ptr: u16) -> u8 {
+        self.flag(ptr, cdl::RENDERED);
         self.data[ptr as usize]
     }
 
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        //Carts with CHR-RAM have no data here
+        self.data.get(ptr as usize).copied().unwrap_or(0)
+    }
+
     fn poke(&mut self, ptr: u16, byte: u8) {
         self.data[ptr as usize] = byte
     }
@@ -140,9 +257,9 @@ pub fn print_ines_header(path: &str) {
 }
 
 fn map_mirror_mode(mode_num: u8) -> Option<MirrorMode> {
-    let _mode = match mode_num {
-        0 => return Some(MirrorMode::Horizontal),
-        1 => return Some(MirrorMode::Vertical),
-        _ => return None,
-    };
+    match mode_num {
+        0 => Some(MirrorMode::Horizontal),
+        1 => Some(MirrorMode::Vertical),
+        _ => None,
+    }
 }
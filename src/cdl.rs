@@ -0,0 +1,331 @@
+//Code/data logger. Flags every PRG-ROM byte by how the cpu used it and every CHR byte
+//by whether it was drawn or read through PPUDATA, and saves them as a .cdl file in
+//the FCEUX layout (which Mesen reads too): one byte per PRG-ROM byte, then one per
+//CHR-ROM byte
+//http://fceux.com/web/help/CodeDataLogger.html
+
+use crate::cpu::{self, Access, Cpu, Registers};
+use crate::disasm::{self, AddressingMode};
+use crate::instruction::Instruction;
+use crate::memory::{AddressSpace, Bus};
+use std::fmt;
+
+//PRG flags. The low byte is what goes in the file
+pub const CODE: u16 = 0x01;
+pub const DATA: u16 = 0x02;
+pub const INDIRECT_CODE: u16 = 0x10; //Reached through JMP (ind)
+pub const INDIRECT_DATA: u16 = 0x20; //Read through a pointer, LDA ($10),Y and friends
+
+//Only kept in memory, the file has no room for them
+pub const OPCODE: u16 = 0x100;
+pub const OPERAND: u16 = 0x200;
+pub const JUMP_TARGET: u16 = 0x400; //Reached by a jump, branch, call, return or interrupt
+
+//Bits 2 and 3 of the file byte say which 8k window of 0x8000-0xFFFF the byte was in
+const WINDOW_SHIFT: u16 = 2;
+
+//CHR flags
+pub const RENDERED: u8 = 0x01;
+pub const READ: u8 = 0x02; //Read by the cpu through PPUDATA
+
+//A .cdl file has to match the cartridge it was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CDL file is {} bytes, this cartridge needs {}",
+            self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for SizeMismatch {}
+
+pub struct CodeDataLog {
+    prg: Vec<u16>,
+    chr: Vec<u8>,
+    next_pc: Option<u16>, //Where the last instruction falls through to, None if it jumped
+    indirect_jump: bool,  //The last instruction was JMP (ind) or JMP (abs,X)
+}
+
+impl CodeDataLog {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+            next_pc: None,
+            indirect_jump: false,
+        }
+    }
+
+    //Flags of each PRG-ROM byte
+    pub fn prg(&self) -> &[u16] {
+        &self.prg
+    }
+
+    //Flags of each CHR-ROM byte
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    //The .cdl file contents
+    pub fn to_fceux(&self) -> Vec<u8> {
+        self.prg
+            .iter()
+            .map(|&flags| flags as u8)
+            .chain(self.chr.iter().copied())
+            .collect()
+    }
+
+    //Adds the flags from a .cdl file to the ones logged so far
+    pub fn merge_fceux(&mut self, data: &[u8]) -> Result<(), SizeMismatch> {
+        let expected = self.prg.len() + self.chr.len();
+        if data.len() != expected {
+            return Err(SizeMismatch {
+                expected,
+                found: data.len(),
+            });
+        }
+
+        let (prg, chr) = data.split_at(self.prg.len());
+        for (flags, &byte) in self.prg.iter_mut().zip(prg) {
+            *flags |= byte as u16;
+        }
+        self.merge_chr(chr);
+        Ok(())
+    }
+
+    pub(crate) fn merge_chr(&mut self, chr: &[u8]) {
+        for (flags, &byte) in self.chr.iter_mut().zip(chr) {
+            *flags |= byte;
+        }
+    }
+
+    //Called before an interrupt sequence starts, the handler is a jump target
+    pub(crate) fn interrupt(&mut self) {
+        self.next_pc = None;
+        self.indirect_jump = false;
+    }
+
+    //Called before each instruction runs
+    pub(crate) fn instruction(&mut self, cpu: &Cpu<Bus>) {
+        let bus = &cpu.bus;
+        let regs = cpu.registers();
+        let pc = regs.pc;
+        let operation = disasm::lookup(bus.debug_peek(pc), cpu.variant());
+        let mode = operation.addressing_mode;
+        let length = disasm::operation_length(mode);
+
+        if self.next_pc != Some(pc) {
+            let flags = if self.indirect_jump {
+                JUMP_TARGET | INDIRECT_CODE
+            } else {
+                JUMP_TARGET
+            };
+            self.mark(bus, pc, flags);
+        }
+        for i in 0..length {
+            let part = if i == 0 { OPCODE } else { OPERAND };
+            self.mark(bus, pc.wrapping_add(i), CODE | part);
+        }
+        //Branches only jump when taken, the rest always do, even to the next instruction
+        self.next_pc = match operation.instruction {
+            Instruction::JMP
+            | Instruction::JSR
+            | Instruction::RTS
+            | Instruction::RTI
+            | Instruction::BRK
+            | Instruction::BRA => None,
+            _ => Some(pc.wrapping_add(length)),
+        };
+
+        let operands = [
+            bus.debug_peek(pc.wrapping_add(1)),
+            bus.debug_peek(pc.wrapping_add(2)),
+        ];
+        self.indirect_jump = matches!(
+            mode,
+            AddressingMode::Indirect | AddressingMode::AbsoluteIndexedIndirect
+        );
+        if self.indirect_jump {
+            //The pointer itself is data
+            let pointer = match mode {
+                AddressingMode::Indirect => u16::from_le_bytes(operands),
+                _ => u16::from_le_bytes(operands).wrapping_add(regs.x as u16),
+            };
+            self.mark(bus, pointer, DATA);
+            self.mark(bus, pointer.wrapping_add(1), DATA);
+            return;
+        }
+
+        let reads = match operation.instruction {
+            Instruction::JMP | Instruction::JSR => false,
+            instruction => cpu::access_kind(instruction) != Access::Write,
+        };
+        if reads {
+            if let Some((address, indirect)) = data_address(bus, &regs, mode, operands) {
+                let flags = if indirect { DATA | INDIRECT_DATA } else { DATA };
+                self.mark(bus, address, flags);
+            }
+        }
+    }
+
+    fn mark(&mut self, bus: &Bus, address: u16, flags: u16) {
+        if let Some(offset) = bus.cartridge.rom_offset(address) {
+            let window = ((address - 0x8000) >> 13) << WINDOW_SHIFT;
+            self.prg[offset] |= flags | window;
+        }
+    }
+}
+
+//Address an instruction takes its data from, and whether it went through a pointer
+fn data_address(
+    bus: &Bus,
+    regs: &Registers,
+    mode: AddressingMode,
+    operands: [u8; 2],
+) -> Option<(u16, bool)> {
+    let absolute = u16::from_le_bytes(operands);
+    let zero_page_16 = |ptr: u8| {
+        u16::from_le_bytes([
+            bus.debug_peek(ptr as u16),
+            bus.debug_peek(ptr.wrapping_add(1) as u16),
+        ])
+    };
+
+    match mode {
+        AddressingMode::Absolute => Some((absolute, false)),
+        AddressingMode::AbsoluteX => Some((absolute.wrapping_add(regs.x as u16), false)),
+        AddressingMode::AbsoluteY => Some((absolute.wrapping_add(regs.y as u16), false)),
+        AddressingMode::IndirectX => Some((zero_page_16(operands[0].wrapping_add(regs.x)), true)),
+        AddressingMode::IndirectY => {
+            Some((zero_page_16(operands[0]).wrapping_add(regs.y as u16), true))
+        }
+        AddressingMode::ZeroPageIndirect => Some((zero_page_16(operands[0]), true)),
+        //Zero page never reaches the cartridge
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+
+    //C000 LDA $C020
+    //C003 LDX #$00
+    //C005 LDA $C021,X
+    //C008 LDA #$20
+    //C00A STA $10
+    //C00C LDA #$C0
+    //C00E STA $11
+    //C010 LDY #$02
+    //C012 LDA ($10),Y
+    //C014 JMP ($C024)
+    //C017 JMP $C017
+    //C020 11 22 33 44
+    //C024 17 C0
+    const PROGRAM: [u8; 0x26] = [
+        0xAD, 0x20, 0xC0, 0xA2, 0x00, 0xBD, 0x21, 0xC0, 0xA9, 0x20, 0x85, 0x10, 0xA9, 0xC0, 0x85,
+        0x11, 0xA0, 0x02, 0xB1, 0x10, 0x6C, 0x24, 0xC0, 0x4C, 0x17, 0xC0, 0xEA, 0xEA, 0xEA, 0xEA,
+        0xEA, 0xEA, 0x11, 0x22, 0x33, 0x44, 0x17, 0xC0,
+    ];
+
+    //0xC000-0xDFFF is the third 8k window
+    const WINDOW: u16 = 0x08;
+
+    fn logged() -> Emulator {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        emu.start_code_data_log();
+        emu.run_frame();
+        emu
+    }
+
+    #[test]
+    fn test_code_flags() {
+        let mut emu = logged();
+        let prg = emu.code_data_log().unwrap().prg();
+
+        //The reset vector points at the first instruction
+        assert_eq!(prg[0x00], CODE | OPCODE | JUMP_TARGET | WINDOW);
+        assert_eq!(prg[0x01], CODE | OPERAND | WINDOW);
+        assert_eq!(prg[0x02], CODE | OPERAND | WINDOW);
+        assert_eq!(prg[0x03], CODE | OPCODE | WINDOW);
+        assert_eq!(
+            prg[0x17] & (JUMP_TARGET | INDIRECT_CODE),
+            JUMP_TARGET | INDIRECT_CODE
+        );
+        //Never ran
+        assert_eq!(prg[0x1A], 0);
+    }
+
+    #[test]
+    fn test_data_flags() {
+        let mut emu = logged();
+        let prg = emu.code_data_log().unwrap().prg();
+
+        assert_eq!(prg[0x20], DATA | WINDOW);
+        assert_eq!(prg[0x21], DATA | WINDOW);
+        assert_eq!(prg[0x22], DATA | INDIRECT_DATA | WINDOW);
+        assert_eq!(prg[0x23], 0);
+        //The JMP pointer
+        assert_eq!(prg[0x24], DATA | WINDOW);
+        assert_eq!(prg[0x25], DATA | WINDOW);
+    }
+
+    #[test]
+    fn test_fceux_file_round_trip() {
+        let mut emu = logged();
+        let file = emu.code_data_log().unwrap().to_fceux();
+        assert_eq!(file.len(), 0x4000 + 0x2000);
+        assert_eq!(file[0], (CODE | WINDOW) as u8);
+
+        let mut fresh = Emulator::new(test_rom(&PROGRAM));
+        fresh.load_code_data_log(&file).unwrap();
+        let merged = fresh.code_data_log().unwrap();
+        assert_eq!(merged.to_fceux(), file);
+        //Only what the file can hold comes back
+        assert_eq!(merged.prg()[0], CODE | WINDOW);
+
+        assert_eq!(
+            fresh.load_code_data_log(&file[1..]),
+            Err(SizeMismatch {
+                expected: 0x6000,
+                found: 0x5FFF
+            })
+        );
+    }
+
+    #[test]
+    fn test_chr_flags() {
+        let mut log = CodeDataLog::new(1, 2);
+        log.merge_chr(&[RENDERED, 0]);
+        log.merge_chr(&[READ, READ]);
+        assert_eq!(log.chr(), &[RENDERED | READ, READ]);
+        assert_eq!(log.to_fceux(), vec![0, RENDERED | READ, READ]);
+    }
+
+    #[test]
+    fn test_chr_read_through_ppudata() {
+        //PPUADDR = $0010, then LDA $2007 in a loop
+        let program = [
+            0xA9, 0x00, 0x8D, 0x06, 0x20, 0xA9, 0x10, 0x8D, 0x06, 0x20, 0xAD, 0x07, 0x20, 0x4C,
+            0x0D, 0xC0,
+        ];
+        let mut emu = Emulator::new(test_rom(&program));
+        emu.start_code_data_log();
+        emu.run_frame();
+
+        let log = emu.stop_code_data_log().unwrap();
+        assert_eq!(log.chr()[0x10] & READ, READ);
+        assert_eq!(log.chr()[0x11] & READ, 0);
+        assert!(emu.code_data_log().is_none());
+    }
+}
@@ -0,0 +1,602 @@
+//Cheats. Game Genie codes substitute what the cpu reads from the cartridge, RAM freezes
+//(Pro Action Replay style) write a byte of RAM at the end of every frame. Lists load and
+//save as libretro .cht files and FCEUX cheat files
+//http://tuxnes.sourceforge.net/gamegenie.html
+
+use crate::hooks::{self, BusEvent, BusHooks, HookId};
+use crate::memory::{Bus, Domain};
+use std::collections::BTreeMap;
+use std::fmt;
+
+//Each letter is a 4 bit value, A = 0 up to N = 15
+const GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    BadCode(String),
+    Syntax { line: usize, message: String }, //Line is 1-based
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheatError::BadCode(code) => write!(f, "{} isn't a cheat code", code),
+            CheatError::Syntax { line, message } => write!(f, "{} on line {}", message, line),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    //Reads of a cartridge address return value, only when the ROM holds compare if given
+    Substitute {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    //Written to RAM every frame
+    Freeze {
+        address: u16,
+        value: u8,
+    },
+}
+
+impl Code {
+    //A Game Genie code, AAAA:VV, or AAAA?CC:VV. The address decides between a
+    //substitution ($8000-$FFFF) and a RAM freeze ($0000-$1FFF)
+    pub fn parse(text: &str) -> Result<Code, CheatError> {
+        let text = text.trim().to_ascii_uppercase();
+        let bad = || CheatError::BadCode(text.clone());
+
+        if let Some(code) = decode_genie(&text) {
+            return Ok(code);
+        }
+
+        let (target, value) = text.split_once(':').ok_or_else(bad)?;
+        let (address, compare) = match target.split_once('?') {
+            Some((address, compare)) => (address, Some(compare)),
+            None => (target, None),
+        };
+        let address = u16::from_str_radix(address, 16).map_err(|_| bad())?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| bad())?;
+        let compare = match compare {
+            Some(compare) => Some(u8::from_str_radix(compare, 16).map_err(|_| bad())?),
+            None => None,
+        };
+
+        match address {
+            0x8000..=0xFFFF => Ok(Code::Substitute {
+                address,
+                value,
+                compare,
+            }),
+            0x0000..=0x1FFF if compare.is_none() => Ok(Code::Freeze { address, value }),
+            _ => Err(bad()),
+        }
+    }
+}
+
+//Substitutions show as Game Genie codes, freezes as AAAA:VV
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Code::Substitute {
+                address,
+                value,
+                compare,
+            } => match (encode_genie(address, value, compare), compare) {
+                (Ok(code), _) => f.write_str(&code),
+                //Built by hand outside the cartridge, no Game Genie code for it
+                (Err(_), Some(compare)) => {
+                    write!(f, "{:04X}?{:02X}:{:02X}", address, compare, value)
+                }
+                (Err(_), None) => write!(f, "{:04X}:{:02X}", address, value),
+            },
+            Code::Freeze { address, value } => write!(f, "{:04X}:{:02X}", address, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub description: String,
+    pub codes: Vec<Code>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    //Several codes can go together joined by +, the way .cht files write them
+    pub fn new(description: &str, codes: &str) -> Result<Cheat, CheatError> {
+        Ok(Cheat {
+            description: description.to_string(),
+            codes: codes
+                .split('+')
+                .map(Code::parse)
+                .collect::<Result<_, _>>()?,
+            enabled: true,
+        })
+    }
+
+    pub fn code_text(&self) -> String {
+        let codes: Vec<String> = self.codes.iter().map(Code::to_string).collect();
+        codes.join("+")
+    }
+}
+
+//The bit shuffle is the same for 6 and 8 letter codes, the 8 letter ones add a compare
+//value. Bit 3 of the third letter only tells the Game Genie how many letters to expect
+fn decode_genie(text: &str) -> Option<Code> {
+    if text.len() != 6 && text.len() != 8 {
+        return None;
+    }
+    let n: Vec<u16> = text
+        .bytes()
+        .map(|letter| GENIE_LETTERS.iter().position(|&l| l == letter))
+        .collect::<Option<Vec<usize>>>()?
+        .into_iter()
+        .map(|n| n as u16)
+        .collect();
+
+    let address = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let data = |low: u16, high: u16, top: u16| {
+        (((high & 7) << 4) | ((low & 8) << 4) | (low & 7) | (top & 8)) as u8
+    };
+
+    Some(if n.len() == 6 {
+        Code::Substitute {
+            address,
+            value: data(n[0], n[1], n[5]),
+            compare: None,
+        }
+    } else {
+        Code::Substitute {
+            address,
+            value: data(n[0], n[1], n[7]),
+            compare: Some(data(n[6], n[7], n[5])),
+        }
+    })
+}
+
+//The Game Genie code for a substitution. Codes have no bit 15, they only reach $8000-$FFFF
+pub fn encode_genie(address: u16, value: u8, compare: Option<u8>) -> Result<String, CheatError> {
+    if address < 0x8000 {
+        return Err(CheatError::BadCode(format!(
+            "{:04X}:{:02X}",
+            address, value
+        )));
+    }
+    let address = address as usize;
+    let value = value as usize;
+    let compare_bits = compare.unwrap_or(0) as usize;
+
+    let mut n = vec![
+        (value & 7) | ((value >> 4) & 8),
+        ((value >> 4) & 7) | ((address >> 4) & 8),
+        (address >> 4) & 7,
+        ((address >> 12) & 7) | (address & 8),
+        (address & 7) | ((address >> 8) & 8),
+        (address >> 8) & 7,
+    ];
+    match compare {
+        None => n[5] |= value & 8,
+        Some(_) => {
+            n[2] |= 8;
+            n[5] |= compare_bits & 8;
+            n.push((compare_bits & 7) | ((compare_bits >> 4) & 8));
+            n.push(((compare_bits >> 4) & 7) | (value & 8));
+        }
+    }
+    Ok(n.into_iter().map(|n| GENIE_LETTERS[n] as char).collect())
+}
+
+//Either kind of file. Both use .cht, the libretro ones are those with cheatN_ keys
+pub fn parse(text: &str) -> Result<Vec<Cheat>, CheatError> {
+    if text
+        .lines()
+        .any(|line| line.trim_start().starts_with("cheat"))
+    {
+        parse_cht(text)
+    } else {
+        parse_fceux(text)
+    }
+}
+
+//libretro .cht. cheatN_desc, cheatN_code and cheatN_enable for N from 0 up to the
+//cheats count, other keys are ignored
+pub fn parse_cht(text: &str) -> Result<Vec<Cheat>, CheatError> {
+    #[derive(Default)]
+    struct Entry<'a> {
+        description: &'a str,
+        code: Option<(usize, &'a str)>,
+        enabled: bool,
+    }
+    let mut entries: BTreeMap<usize, Entry> = BTreeMap::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = index + 1;
+        let (key, value) = line.split_once('=').ok_or_else(|| CheatError::Syntax {
+            line: number,
+            message: "Expected key = value".to_string(),
+        })?;
+        let value = value.trim().trim_matches('"');
+
+        let field = key
+            .trim()
+            .strip_prefix("cheat")
+            .and_then(|rest| rest.split_once('_'))
+            .and_then(|(cheat, field)| Some((cheat.parse::<usize>().ok()?, field)));
+        if let Some((cheat, field)) = field {
+            let entry = entries.entry(cheat).or_default();
+            match field {
+                "desc" => entry.description = value,
+                "code" => entry.code = Some((number, value)),
+                "enable" => entry.enabled = value == "true",
+                _ => (),
+            }
+        }
+    }
+
+    let mut cheats = Vec::new();
+    for entry in entries.values() {
+        //Some tools leave empty slots
+        let (line, code) = match entry.code {
+            Some((_, "")) | None => continue,
+            Some(code) => code,
+        };
+        let mut cheat =
+            Cheat::new(entry.description, code).map_err(|error| CheatError::Syntax {
+                line,
+                message: error.to_string(),
+            })?;
+        cheat.enabled = entry.enabled;
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+//.cht values have no escapes, so double quotes in descriptions become single quotes
+pub fn to_cht(cheats: &[Cheat]) -> String {
+    let mut text = format!("cheats = {}\n", cheats.len());
+    for (index, cheat) in cheats.iter().enumerate() {
+        text += &format!(
+            "\ncheat{0}_desc = \"{1}\"\ncheat{0}_code = \"{2}\"\ncheat{0}_enable = {3}\n",
+            index,
+            cheat.description.replace('"', "'"),
+            cheat.code_text(),
+            cheat.enabled
+        );
+    }
+    text
+}
+
+//FCEUX cheat file, a code a line: [S][C][:]AAAA:VV[:CC]:name. S makes it a substitution
+//rather than a RAM write, C means there's a compare value and : means it's disabled
+pub fn parse_fceux(text: &str) -> Result<Vec<Cheat>, CheatError> {
+    let mut cheats = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let syntax = |message: &str| CheatError::Syntax {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let mut rest = line.trim_start();
+        let substitute = strip(&mut rest, 'S');
+        let has_compare = strip(&mut rest, 'C');
+        let enabled = !strip(&mut rest, ':');
+
+        let fields: Vec<&str> = rest.splitn(if has_compare { 4 } else { 3 }, ':').collect();
+        if fields.len() < if has_compare { 4 } else { 3 } {
+            return Err(syntax("Expected address:value:name"));
+        }
+        let address = u16::from_str_radix(fields[0], 16).map_err(|_| syntax("Bad address"))?;
+        let value = u8::from_str_radix(fields[1], 16).map_err(|_| syntax("Bad value"))?;
+        let compare = match has_compare {
+            true => Some(u8::from_str_radix(fields[2], 16).map_err(|_| syntax("Bad compare"))?),
+            false => None,
+        };
+
+        let code = match (substitute, address) {
+            (true, 0x8000..=0xFFFF) => Code::Substitute {
+                address,
+                value,
+                compare,
+            },
+            (false, 0x0000..=0x1FFF) => Code::Freeze { address, value },
+            (true, _) => return Err(syntax("Substitutions only work on $8000-$FFFF")),
+            (false, _) => return Err(syntax("RAM writes only work on $0000-$1FFF")),
+        };
+        cheats.push(Cheat {
+            description: fields[fields.len() - 1].to_string(),
+            codes: vec![code],
+            enabled,
+        });
+    }
+    Ok(cheats)
+}
+
+//Cheats with several codes become a line for each
+pub fn to_fceux(cheats: &[Cheat]) -> String {
+    let mut text = String::new();
+    for cheat in cheats {
+        let disabled = if cheat.enabled { "" } else { ":" };
+        for code in &cheat.codes {
+            text += &match *code {
+                Code::Substitute {
+                    address,
+                    value,
+                    compare: Some(compare),
+                } => format!(
+                    "SC{}{:04x}:{:02x}:{:02x}:{}\n",
+                    disabled, address, value, compare, cheat.description
+                ),
+                Code::Substitute { address, value, .. } => format!(
+                    "S{}{:04x}:{:02x}:{}\n",
+                    disabled, address, value, cheat.description
+                ),
+                Code::Freeze { address, value } => format!(
+                    "{}{:04x}:{:02x}:{}\n",
+                    disabled, address, value, cheat.description
+                ),
+            };
+        }
+    }
+    text
+}
+
+fn strip(text: &mut &str, prefix: char) -> bool {
+    match text.strip_prefix(prefix) {
+        Some(rest) => {
+            *text = rest;
+            true
+        }
+        None => false,
+    }
+}
+
+//The cheat list, and the bus hooks that apply its substitutions
+#[derive(Default)]
+pub(crate) struct CheatEngine {
+    cheats: Vec<Cheat>,
+    hooks: Vec<HookId>,
+}
+
+impl CheatEngine {
+    pub(crate) fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    //Call install after changing the list
+    pub(crate) fn cheats_mut(&mut self) -> &mut Vec<Cheat> {
+        &mut self.cheats
+    }
+
+    //Hooks the substitutions of the enabled cheats, replacing the ones hooked before
+    pub(crate) fn install(&mut self, bus_hooks: &mut BusHooks) {
+        for id in self.hooks.drain(..) {
+            bus_hooks.remove(id);
+        }
+        let codes: Vec<Code> = self.enabled_codes().collect();
+        for code in codes {
+            if let Code::Substitute {
+                address,
+                value,
+                compare,
+            } = code
+            {
+                let substitute = move |event: &BusEvent| match compare {
+                    Some(compare) if compare != event.value => None,
+                    _ => Some(value),
+                };
+                let id = bus_hooks.add(
+                    hooks::READ | hooks::EXECUTE,
+                    address..=address,
+                    Box::new(substitute),
+                );
+                self.hooks.push(id);
+            }
+        }
+    }
+
+    //Called at the end of every frame
+    pub(crate) fn freeze(&self, bus: &mut Bus) {
+        for code in self.enabled_codes() {
+            if let Code::Freeze { address, value } = code {
+                bus.write_domain(Domain::SystemRam, address as usize & 0x07FF, value);
+            }
+        }
+    }
+
+    fn enabled_codes(&self) -> impl Iterator<Item = Code> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .flat_map(|cheat| cheat.codes.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+
+    #[test]
+    fn test_decode_genie() {
+        assert_eq!(
+            Code::parse("SXIOPO"),
+            Ok(Code::Substitute {
+                address: 0x91D9,
+                value: 0xAD,
+                compare: None
+            })
+        );
+        //Bit 3 of the third letter doesn't change anything
+        assert_eq!(
+            Code::parse("gossip"),
+            Ok(Code::Substitute {
+                address: 0xD1DD,
+                value: 0x14,
+                compare: None
+            })
+        );
+        assert!(Code::parse("SXIOP").is_err());
+        assert!(Code::parse("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn test_encode_genie() {
+        assert_eq!(encode_genie(0x91D9, 0xAD, None).unwrap(), "SXIOPO");
+        for compare in [0x00, 0x37, 0xFF] {
+            let code = encode_genie(0xD1DD, 0x94, Some(compare)).unwrap();
+            assert_eq!(code.len(), 8);
+            assert_eq!(
+                Code::parse(&code),
+                Ok(Code::Substitute {
+                    address: 0xD1DD,
+                    value: 0x94,
+                    compare: Some(compare)
+                })
+            );
+        }
+        assert_eq!(
+            encode_genie(0x1234, 0x94, None),
+            Err(CheatError::BadCode("1234:94".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_codes() {
+        assert_eq!(
+            Code::parse("0776:09"),
+            Ok(Code::Freeze {
+                address: 0x0776,
+                value: 0x09
+            })
+        );
+        assert_eq!(
+            Code::parse("C010?EA:A9"),
+            Ok(Code::Substitute {
+                address: 0xC010,
+                value: 0xA9,
+                compare: Some(0xEA)
+            })
+        );
+        //No RAM or ROM there
+        assert!(Code::parse("4016:01").is_err());
+    }
+
+    //LDA $C010, STA $0300, JMP $C003
+    const PROGRAM: [u8; 9] = [0xAD, 0x10, 0xC0, 0x8D, 0x00, 0x03, 0x4C, 0x03, 0xC0];
+
+    #[test]
+    fn test_substitution_and_compare() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        let wrong = encode_genie(0xC010, 0x42, Some(0x00)).unwrap();
+        let right = encode_genie(0xC010, 0x42, Some(0xEA)).unwrap();
+        emu.add_cheat(Cheat::new("Wrong compare", &wrong).unwrap());
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0xEA);
+
+        let index = emu.add_cheat(Cheat::new("Right compare", &right).unwrap());
+        emu.cpu.set_pc(0xC000);
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x42);
+
+        emu.set_cheat_enabled(index, false);
+        emu.cpu.set_pc(0xC000);
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0xEA);
+    }
+
+    #[test]
+    fn test_ram_freeze() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        //$0B00 is a mirror of $0300
+        emu.add_cheat(Cheat::new("Lives", "0B00:09+0301:05").unwrap());
+        emu.run_frame();
+        assert_eq!(emu.read_domain(Domain::SystemRam, 0x0300), 0x09);
+        assert_eq!(emu.read_domain(Domain::SystemRam, 0x0301), 0x05);
+        //The game can still write it in between
+        emu.step_instruction();
+        emu.step_instruction();
+        assert_eq!(emu.read_domain(Domain::SystemRam, 0x0300), 0xEA);
+
+        let cheat = emu.remove_cheat(0).unwrap();
+        assert_eq!(cheat.code_text(), "0B00:09+0301:05");
+        assert!(emu.cheats().is_empty());
+    }
+
+    #[test]
+    fn test_cht_round_trip() {
+        let text = "cheats = 3\n\n\
+                    cheat0_desc = \"Infinite lives\"\n\
+                    cheat0_code = \"SXIOPO\"\n\
+                    cheat0_enable = true\n\n\
+                    cheat1_desc = \"Start with 9\"\n\
+                    cheat1_code = \"075A:08+AAEAAAAA\"\n\
+                    cheat1_enable = false\n\n\
+                    cheat2_desc = \"\"\n\
+                    cheat2_code = \"\"\n";
+        let cheats = parse_cht(text).unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].description, "Infinite lives");
+        assert!(cheats[0].enabled);
+        assert!(!cheats[1].enabled);
+        assert_eq!(cheats[1].code_text(), "075A:08+AAEAAAAA");
+
+        assert_eq!(parse_cht(&to_cht(&cheats)).unwrap(), cheats);
+        assert_eq!(parse(&to_cht(&cheats)).unwrap(), cheats);
+
+        let quoted = [Cheat::new("The \"big\" jump", "SXIOPO").unwrap()];
+        let text = to_cht(&quoted);
+        assert!(text.contains("cheat0_desc = \"The 'big' jump\"\n"));
+        let read = parse_cht(&text).unwrap();
+        assert_eq!(read[0].description, "The 'big' jump");
+        assert_eq!(read[0].codes, quoted[0].codes);
+        assert_eq!(
+            parse_cht("cheat0_code = \"XYZ\""),
+            Err(CheatError::Syntax {
+                line: 1,
+                message: "XYZ isn't a cheat code".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_fceux_round_trip() {
+        let text = "S91d9:ad:Infinite lives\n\
+                    SC:c010:a9:ea:Disabled: with a colon\n\
+                    075a:08:Start with 9\n";
+        let cheats = parse_fceux(text).unwrap();
+        assert_eq!(cheats.len(), 3);
+        assert_eq!(cheats[0].code_text(), "SXIOPO");
+        assert!(!cheats[1].enabled);
+        assert_eq!(cheats[1].description, "Disabled: with a colon");
+        assert_eq!(
+            cheats[2].codes,
+            [Code::Freeze {
+                address: 0x075A,
+                value: 0x08
+            }]
+        );
+        assert_eq!(to_fceux(&cheats), text);
+        assert_eq!(parse(text).unwrap(), cheats);
+        assert!(parse_fceux("S0300:01:RAM substitution").is_err());
+    }
+}
@@ -26,6 +26,7 @@ impl ControllerState {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         up: bool,
         down: bool,
@@ -99,6 +100,18 @@ impl AddressSpace for Controller {
         }
     }
 
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        if ptr == 0x4016 {
+            if self.polls > 7 {
+                2
+            } else {
+                self.state.as_byte().get_bit(self.polls as usize) as u8
+            }
+        } else {
+            3
+        }
+    }
+
     fn poke(&mut self, _ptr: u16, byte: u8) {
         if byte > 0 {
             self.strobe = true;
@@ -1,72 +1,258 @@
-use crate::instruction::{AddressingMode, Instruction, Operation, OPCODES};
+use crate::instruction::{AddressingMode, Instruction, Operation, CMOS_OPCODES, OPCODES};
 use crate::memory::*;
 use bit_field::BitField;
 
-pub enum Operand {
-    Constant { value: u8 },
-    Address { location: u16 },
-    Accumulator,
-    None,
+//Used by the unstable XAA and LXA opcodes. The real value depends on the chip and temperature
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+//Devices sharing the IRQ line. The line is level triggered and stays asserted
+//for as long as any source holds it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+    FrameCounter = 0x01,
+    Dmc = 0x02,
+    Mapper = 0x04,
+    External = 0x08,
+}
+
+//Chips the core can run as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Ricoh2A03, //NES and Famicom. An NMOS 6502 with decimal mode cut out
+    Nmos6502,  //Original 6502, ADC and SBC honour the D flag
+    Wdc65C02,  //CMOS 6502 with the extra instructions and the old bugs fixed
+}
+
+//Snapshot of the programmer visible registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub pc: u16,
+    pub s: u8,
+    pub p: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+}
+
+//What an instruction does with the address its addressing mode produces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+    Modify,
 }
 
 //http://nesdev.com/6502_cpu.txt
+//Every call to step_cycle does exactly one bus access, including the dummy reads and
+//writes the real chip does while it works out addresses
 pub struct Cpu<T: AddressSpace> {
     pub bus: T,
+    variant: Variant,
     //Registers
-    pc: u16, //Program counter
-    s: u8,   //Stack pointer
-    p: u8,   //Processor status
-    a: u8,   //Accumulator
-    x: u8,   //Index X
-    y: u8,   //Index Y
-    operation_progress: u8,
+    pc: u16,              //Program counter
+    s: u8,                //Stack pointer
+    p: u8,                //Processor status
+    a: u8,                //Accumulator
+    x: u8,                //Index X
+    y: u8,                //Index Y
+    opcode: u8,           //Opcode of the operation currently executing
+    operation: Operation, //Decoded opcode
+    step: u8,             //Cycle within the operation, 0 fetches the next opcode
+    address: u16,         //Effective address, built up a byte at a time
+    pointer: u8,          //Zero page pointer of the indirect modes
+    value: u8,            //Data latched between cycles
+    page_crossed: bool,   //Indexing carried into the high byte of the address
+    interrupt: bool,      //Servicing an NMI or IRQ instead of an opcode
+    cycles: u64,          //Total cycles since power on
+    jammed: bool,         //Set by the JAM opcodes and STP, only a reset recovers
+    waiting: bool,        //Set by WAI until an interrupt comes in
+    irq_line: u8,         //IrqSource bits currently holding the line
+    nmi_pending: bool,    //Latched by fire_nmi until the interrupt sequence fetches its vector
+    interrupt_seen: bool, //Poll result at the end of the last cycle
+    interrupt_due: bool,  //Poll result a cycle earlier, decides what the next operation is
+    oam_dma: Option<OamDma>,
+    dmc_dma: Option<u16>, //Address of the sample byte the APU asked for
+    dmc_wait: u8,         //Halt and dummy cycles the DMC DMA still has to sit through
+    dma_halted: bool,     //The halt cycle has run and the DMA unit owns the bus
+}
+
+//Copies a page to $2004. Even counts read the source, odd counts write OAM
+#[derive(Debug, Clone, Copy)]
+struct OamDma {
+    page: u8,
+    count: u16,
+    value: u8,
 }
 
 impl<T: AddressSpace> Cpu<T> {
     pub fn new(bus: T) -> Cpu<T> {
+        Self::with_variant(bus, Variant::Ricoh2A03)
+    }
+
+    pub fn with_variant(bus: T, variant: Variant) -> Cpu<T> {
         Cpu {
             bus,
+            variant,
             pc: 0,
             s: 0x0FD,
             p: 0x24,
             a: 0,
             x: 0,
             y: 0,
-            operation_progress: 0,
+            opcode: 0xEA,
+            operation: OPCODES[0xEA],
+            step: 0,
+            address: 0,
+            pointer: 0,
+            value: 0,
+            page_crossed: false,
+            interrupt: false,
+            cycles: 0,
+            jammed: false,
+            waiting: false,
+            irq_line: 0,
+            nmi_pending: false,
+            interrupt_seen: false,
+            interrupt_due: false,
+            oam_dma: None,
+            dmc_dma: None,
+            dmc_wait: 0,
+            dma_halted: false,
         }
     }
 
+    //Reset line. Runs the interrupt sequence with the pushes turned into reads, so S goes down
+    //by 3 and nothing is written. A, X, Y and the other flags are left alone
     pub fn reset(&mut self) {
-        self.pc = self.bus.peek_16(0xFFFC); //0xC000 for nestest
-        self.p = 0x24;
+        self.s = self.s.wrapping_sub(3);
+        self.set_i(true);
+        self.pc = self.bus.peek_16(0xFFFC);
+        self.step = 0;
+        self.jammed = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+        self.interrupt_seen = false;
+        self.interrupt_due = false;
+        self.oam_dma = None;
+        self.dmc_dma = None;
+        self.dma_halted = false;
+        self.cycles += 7; //The reset sequence takes 7 cycles before the first fetch
+    }
+
+    //Registers start out cleared and the reset sequence brings S down to 0xFD
+    pub fn power_on(&mut self) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.s = 0;
+        self.p = 0x20;
+        self.cycles = 0;
+        self.reset();
+    }
+
+    //Used to start nestest at 0xC000 in automation mode
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.s = registers.s;
+        self.p = registers.p;
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            s: self.s,
+            p: self.p,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+        }
     }
 
-    fn push(&mut self, value: u8) {
-        //println!("-->push {:#X}", value);
-        self.bus.poke(self.s as u16 + 0x100, value);
-        self.s -= 1;
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
-    fn pop(&mut self) -> u8 {
-        self.s += 1;
-        let value = self.bus.peek(self.s as u16 + 0x100);
-        //println!("-->pop {:#X}", value);
+    //True when the next cycle will fetch a new opcode or start an interrupt
+    pub fn instruction_finished(&self) -> bool {
+        self.step == 0 && !self.dma_active()
+    }
+
+    //The DMA unit has the cpu halted, or will halt it before the next instruction
+    pub fn dma_active(&self) -> bool {
+        self.oam_dma.is_some() || self.dmc_dma.is_some()
+    }
+
+    //Starts a DMC sample fetch, the byte goes back through AddressSpace::dmc_dma_complete.
+    //Buses with an APU ask through AddressSpace::dmc_dma_request instead
+    pub fn request_dmc_dma(&mut self, address: u16) {
+        self.dmc_dma = Some(address);
+        self.dmc_wait = 2;
+    }
+
+    //True when the next operation is an interrupt rather than the opcode at pc
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_due
+    }
+
+    //Stopped by JAM, STP or WAI, so no instructions are running
+    pub fn halted(&self) -> bool {
+        self.jammed || self.waiting
+    }
+
+    //Only a reset gets the cpu going again
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
+
+    fn read(&mut self, address: u16) -> u8 {
+        self.bus.peek(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.bus.poke(address, value);
+    }
+
+    //Reads that only keep the bus busy
+    fn dummy_read(&mut self, address: u16) {
+        self.bus.peek_dummy(address);
+    }
+
+    //Reads the byte at pc and moves past it
+    fn fetch(&mut self) -> u8 {
+        let value = self.bus.peek_operand(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         value
     }
 
-    fn push_16(&mut self, value: u16) {
-        //println!("push16 {:#X}", value);
-        let bytes = value.to_le_bytes();
-        self.push(bytes[1]);
-        self.push(bytes[0]);
+    //Single byte instructions still read the following byte, and throw it away
+    fn dummy_fetch(&mut self) {
+        self.dummy_read(self.pc);
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(self.s as u16 + 0x100, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pop(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(self.s as u16 + 0x100)
     }
 
-    fn pop_16(&mut self) -> u16 {
-        let byte1 = self.pop();
-        let byte2 = self.pop();
-        let num = u16::from_le_bytes([byte1, byte2]);
-        //println!("pop16 {:#X}", num);
-        num
+    //Reads the top of the stack while S is being adjusted
+    fn dummy_pop(&mut self) {
+        self.dummy_read(self.s as u16 + 0x100);
     }
 
     fn set_z(&mut self, val: bool) {
@@ -85,14 +271,14 @@ impl<T: AddressSpace> Cpu<T> {
         self.p.get_bit(7)
     }
 
-    fn set_b(&mut self, val: bool) {
-        self.p.set_bit(4, val);
-    }
-
     fn set_i(&mut self, val: bool) {
         self.p.set_bit(2, val);
     }
 
+    fn get_i(&self) -> bool {
+        self.p.get_bit(2)
+    }
+
     fn set_c(&mut self, val: bool) {
         self.p.set_bit(0, val);
     }
@@ -113,765 +299,1901 @@ impl<T: AddressSpace> Cpu<T> {
         self.p.set_bit(3, val);
     }
 
+    fn get_d(&self) -> bool {
+        self.p.get_bit(3)
+    }
+
+    fn is_cmos(&self) -> bool {
+        self.variant == Variant::Wdc65C02
+    }
+
+    //Called on the falling edge of the nmi line. The nmi is taken after the current instruction,
+    //or after the next one when it arrives during the last cycle
     pub fn fire_nmi(&mut self) {
-        let addr = self.bus.peek_16(0xFFFA);
-        self.push_16(self.pc);
-        self.push(self.p);
-        self.pc = addr;
+        self.nmi_pending = true;
+    }
+
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq_line |= source as u8;
+    }
+
+    pub fn release_irq(&mut self, source: IrqSource) {
+        self.irq_line &= !(source as u8);
+    }
+
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_line != 0
     }
 
     pub fn step_cycle(&mut self) {
-        //skip cycle if instruction is still in progress
-        if self.operation_progress > 0 {
-            self.operation_progress -= 1;
+        self.cycles += 1;
+
+        if self.jammed {
+            return;
+        }
+
+        //DMA halts the cpu before its next read. Every write is the last cycle of its
+        //instruction, so for OAM DMA that's the next opcode fetch. A DMC fetch can land on
+        //any read on real hardware, here it waits for the end of the instruction too
+        if self.step == 0 && self.dma_active() {
+            self.dma_cycle();
+            self.poll_interrupts();
+            self.poll_dma();
             return;
         }
 
-        let _inst_pc = self.pc;
+        //WAI sleeps until an interrupt is due, or until IRQ is pulled while masked
+        if self.waiting {
+            let wake = self.interrupt_due || (self.irq_asserted() && self.get_i());
+            if !wake {
+                self.poll_interrupts();
+                self.poll_dma();
+                return;
+            }
+            self.waiting = false;
+        }
+
+        if self.step == 0 {
+            self.start_operation();
+        } else {
+            self.execute_step();
+            //finish() resets the step once the last cycle has run
+            if self.step != 0 {
+                self.step += 1;
+            }
+        }
+
+        self.poll_interrupts();
+        self.poll_dma();
+    }
+
+    //Picks up DMA requests made on the bus this cycle
+    fn poll_dma(&mut self) {
+        if let Some(page) = self.bus.oam_dma_request() {
+            self.oam_dma = Some(OamDma {
+                page,
+                count: 0,
+                value: 0,
+            });
+        }
+        if let Some(address) = self.bus.dmc_dma_request() {
+            self.request_dmc_dma(address);
+        }
+    }
 
-        let operation = self.consume_next_operation();
+    //One cycle with the DMA unit driving the bus. The cycles alternate between get, which
+    //can read, and put, which can write. The first cycle halts the cpu, then OAM DMA needs
+    //a get to start on, so it takes 513 cycles or 514 when it has to wait one out.
+    //A DMC fetch needs a halt and a dummy cycle before it can take a get, but OAM DMA
+    //cycles count for both, so during OAM DMA it usually steals just two cycles
+    //https://www.nesdev.org/wiki/DMA
+    fn dma_cycle(&mut self) {
+        let get = self.cycles % 2 == 1;
+        let dmc_ready = self.dmc_dma.is_some() && self.dmc_wait == 0;
+        self.dmc_wait = self.dmc_wait.saturating_sub(1);
+
+        if !self.dma_halted {
+            //The cpu's read goes ahead and gets repeated once it's released
+            self.dma_halted = true;
+            self.dummy_fetch();
+            return;
+        }
 
-        //Set and subtract one from operation length to count the current cycle
-        self.operation_progress = operation.base_cycle_count - 1;
+        match (get, self.dmc_dma, self.oam_dma) {
+            (true, Some(address), _) if dmc_ready => {
+                let value = self.bus.peek_dma(address);
+                self.bus.dmc_dma_complete(value);
+                self.dmc_dma = None;
+            }
+            (true, _, Some(mut dma)) => {
+                let address = u16::from_le_bytes([(dma.count / 2) as u8, dma.page]);
+                dma.value = self.bus.peek_dma(address);
+                dma.count += 1;
+                self.oam_dma = Some(dma);
+            }
+            (false, _, Some(mut dma)) if dma.count % 2 == 1 => {
+                self.write(0x2004, dma.value);
+                dma.count += 1;
+                self.oam_dma = if dma.count == 0x200 { None } else { Some(dma) };
+            }
+            //Halted, waiting on the DMC or lining up with a get
+            _ => self.dummy_fetch(),
+        }
 
-        let operand = self.fetch_operand(&operation);
+        if !self.dma_active() {
+            self.dma_halted = false;
+        }
+    }
 
-        let _operand_value = match &operand {
-            Operand::Constant { value } => *value as u16,
-            Operand::Address { location } => *location,
-            Operand::Accumulator => self.a as u16,
-            Operand::None => 0,
-        };
+    //The lines are sampled at the end of every cycle. An instruction acts on the sample from its
+    //second to last cycle, which is why CLI, SEI and PLP only take effect one instruction later
+    fn poll_interrupts(&mut self) {
+        self.interrupt_due = self.interrupt_seen;
+        self.interrupt_seen = self.nmi_pending || (self.irq_asserted() && !self.get_i());
+    }
 
-        // let op_text = match &operand {
-        //     Operand::Constant { value } => {
-        //         format!("{:?} #${:02X}", operation.instruction, value.clone())
-        //     }
-        //     Operand::Address { location } => match &operation.addressing_mode {
-        //         AddressingMode::Relative | AddressingMode::Absolute => {
-        //             format!("{:?} ${:04X}", operation.instruction, location.clone())
-        //         }
-        //         _ => format!(
-        //             "{:?} ${:02X} = {:02X}",
-        //             operation.instruction,
-        //             location.clone(),
-        //             self.bus.peek(location.clone())
-        //         ),
-        //     },
-        //     _ => format!("{:?}", operation.instruction),
-        // };
-
-        //Logging formatting
-        // self.log.write_all(
-        //     format!(
-        //         "{:04X} {:31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\n",
-        //         inst_PC, op_text, self.A, self.X, self.Y, self.P, self.S
-        //     )
-        //     .as_bytes(),
-        // );
-
-        let extra_cycles = match operation.instruction {
-            Instruction::ADC => self.adc(&operand),
-            Instruction::AND => self.and(&operand),
-            Instruction::ASL => self.asl(&operand),
-            Instruction::BCC => self.bcc(&operand),
-            Instruction::BCS => self.bcs(&operand),
-            Instruction::BEQ => self.beq(&operand),
-            Instruction::BIT => self.bit(&operand),
-            Instruction::BMI => self.bmi(&operand),
-            Instruction::BNE => self.bne(&operand),
-            Instruction::BPL => self.bpl(&operand),
-            Instruction::BRK => self.brk(&operand),
-            Instruction::BVC => self.bvc(&operand),
-            Instruction::BVS => self.bvs(&operand),
-            Instruction::CLC => self.clc(&operand),
-            Instruction::CLD => self.cld(&operand),
-            Instruction::CLI => self.cli(&operand),
-            Instruction::CLV => self.clv(&operand),
-            Instruction::CMP => self.cmp(&operand),
-            Instruction::CPX => self.cpx(&operand),
-            Instruction::CPY => self.cpy(&operand),
-            Instruction::DEC => self.dec(&operand),
-            Instruction::DEX => self.dex(&operand),
-            Instruction::DEY => self.dey(&operand),
-            Instruction::EOR => self.eor(&operand),
-            Instruction::INC => self.inc(&operand),
-            Instruction::INX => self.inx(&operand),
-            Instruction::INY => self.iny(&operand),
-            Instruction::JMP => self.jmp(&operand),
-            Instruction::JSR => self.jsr(&operand),
-            Instruction::LDA => self.lda(&operand),
-            Instruction::LDX => self.ldx(&operand),
-            Instruction::LDY => self.ldy(&operand),
-            Instruction::LSR => self.lsr(&operand),
-            Instruction::NOP => self.nop(&operand),
-            Instruction::ORA => self.ora(&operand),
-            Instruction::PHA => self.pha(&operand),
-            Instruction::PHP => self.php(&operand),
-            Instruction::PLA => self.pla(&operand),
-            Instruction::PLP => self.plp(&operand),
-            Instruction::ROL => self.rol(&operand),
-            Instruction::ROR => self.ror(&operand),
-            Instruction::RTI => self.rti(&operand),
-            Instruction::RTS => self.rts(&operand),
-            Instruction::SBC => self.sbc(&operand),
-            Instruction::SEC => self.sec(&operand),
-            Instruction::SED => self.sed(&operand),
-            Instruction::SEI => self.sei(&operand),
-            Instruction::STA => self.sta(&operand),
-            Instruction::STX => self.stx(&operand),
-            Instruction::STY => self.sty(&operand),
-            Instruction::TAX => self.tax(&operand),
-            Instruction::TAY => self.tay(&operand),
-            Instruction::TSX => self.tsx(&operand),
-            Instruction::TXA => self.txa(&operand),
-            Instruction::TXS => self.txs(&operand),
-            Instruction::TYA => self.tya(&operand),
-        };
+    //First cycle of every operation. Interrupts still do the opcode fetch, but throw it away
+    //and leave pc where it is
+    fn start_operation(&mut self) {
+        self.page_crossed = false;
+        self.step = 1;
+        self.interrupt = self.interrupt_due;
 
-        //Add extra cycles to the op length if the executing the instruction caused it
-        if let Some(cycles) = extra_cycles {
-            self.operation_progress += cycles;
+        let table = match self.variant {
+            Variant::Wdc65C02 => &CMOS_OPCODES,
+            _ => &OPCODES,
         };
-        //println!("{:#X}", self.bus.controller.status);
-    }
-    //(operation.data[0] / 255) as u16 != (self.PC / 255) as u16
-    pub fn fetch_operand(&mut self, operation: &Operation) -> Operand {
-        match operation.addressing_mode {
-            AddressingMode::Accumulator => Operand::Accumulator,
-            AddressingMode::Immediate => Operand::Constant {
-                value: operation.data[0],
-            },
-            AddressingMode::Implied => Operand::None,
-            AddressingMode::Relative => Operand::Address {
-                location: relative_address(operation.data[0], self.pc),
-            },
-            AddressingMode::Absolute => Operand::Address {
-                location: absolute_address(operation.data[0], operation.data[1]),
-            },
-            AddressingMode::ZeroPage => Operand::Address {
-                location: zero_page_address(operation.data[0]),
-            },
-            AddressingMode::Indirect => {
-                let addr_location = u16::from_le_bytes([operation.data[0], operation.data[1]]);
-                let byte1 = self.bus.peek(addr_location);
-                let byte2 = match addr_location.to_le_bytes() {
-                    [0xFF, hbyte] => {
-                        let lbyte = self.bus.peek(0x00);
-                        self.bus.peek(u16::from_le_bytes([lbyte, hbyte]))
-                    }
-                    _ => self.bus.peek(addr_location + 1),
-                };
-                Operand::Address {
-                    location: u16::from_le_bytes([byte1, byte2]),
-                }
+
+        if self.interrupt {
+            self.dummy_fetch();
+            self.opcode = 0x00;
+        } else {
+            self.opcode = self.bus.peek_opcode(self.pc);
+            self.pc = self.pc.wrapping_add(1);
+        }
+        self.operation = table[self.opcode as usize];
+
+        //The 65C02's unused opcodes are single cycle NOPs
+        if self.operation.base_cycle_count == 1 {
+            self.finish();
+        }
+    }
+
+    fn finish(&mut self) {
+        self.step = 0;
+    }
+
+    fn execute_step(&mut self) {
+        match self.operation.instruction {
+            Instruction::BRK => self.brk_step(),
+            Instruction::JMP => self.jmp_step(),
+            Instruction::JSR => self.jsr_step(),
+            Instruction::RTI => self.rti_step(),
+            Instruction::RTS => self.rts_step(),
+            Instruction::PHA | Instruction::PHP | Instruction::PHX | Instruction::PHY => {
+                self.push_step()
             }
-            AddressingMode::AbsoluteX => Operand::Address {
-                location: u16::from_le_bytes([operation.data[0], operation.data[1]])
-                    + self.x as u16,
-            },
-            AddressingMode::AbsoluteY => Operand::Address {
-                location: u16::from_le_bytes([operation.data[0], operation.data[1]])
-                    .wrapping_add(self.y as u16),
+            Instruction::PLA | Instruction::PLP | Instruction::PLX | Instruction::PLY => {
+                self.pull_step()
+            }
+            Instruction::JAM => self.jam_step(),
+            Instruction::STP | Instruction::WAI => self.halt_step(),
+            Instruction::BBR | Instruction::BBS => self.bit_branch_step(),
+            Instruction::NOP if self.opcode == 0x5C && self.is_cmos() => self.long_nop_step(),
+            _ => match self.operation.addressing_mode {
+                AddressingMode::Implied | AddressingMode::Accumulator => self.implied_step(),
+                AddressingMode::Immediate => self.immediate_step(),
+                AddressingMode::Relative => self.branch_step(),
+                AddressingMode::ZeroPage => self.zero_page_step(),
+                AddressingMode::ZeroPageX => self.zero_page_indexed_step(self.x),
+                AddressingMode::ZeroPageY => self.zero_page_indexed_step(self.y),
+                AddressingMode::Absolute => self.absolute_step(),
+                AddressingMode::AbsoluteX => self.absolute_indexed_step(self.x),
+                AddressingMode::AbsoluteY => self.absolute_indexed_step(self.y),
+                AddressingMode::IndirectX => self.indexed_indirect_step(),
+                AddressingMode::IndirectY => self.indirect_indexed_step(),
+                AddressingMode::ZeroPageIndirect => self.zero_page_indirect_step(),
+                AddressingMode::Indirect
+                | AddressingMode::AbsoluteIndexedIndirect
+                | AddressingMode::ZeroPageRelative => {
+                    unreachable!("{:?} has its own step function", self.operation.instruction)
+                }
             },
+        }
+    }
 
-            AddressingMode::ZeroPageX => Operand::Address {
-                location: operation.data[0].wrapping_add(self.x) as u16,
-            },
-            AddressingMode::ZeroPageY => Operand::Address {
-                location: operation.data[0].wrapping_add(self.y) as u16,
-            },
+    //Addressing modes. Each one builds self.address and hands over to access()
 
-            AddressingMode::IndirectX => {
-                let address_location = operation.data[0].wrapping_add(self.x);
-                let byte1 = self.bus.peek(address_location as u16);
-                let byte2 = match address_location {
-                    0xFF => self.bus.peek(0),
-                    _ => self.bus.peek((address_location + 1) as u16),
-                };
-                Operand::Address {
-                    location: u16::from_le_bytes([byte1, byte2]),
+    fn implied_step(&mut self) {
+        self.dummy_fetch();
+        if self.operation.addressing_mode == AddressingMode::Accumulator {
+            self.a = self.execute_modify(self.a);
+        } else {
+            self.execute_implied();
+        }
+        self.finish();
+    }
+
+    fn immediate_step(&mut self) {
+        match self.step {
+            1 => {
+                let value = self.fetch();
+                self.execute_read(value);
+                if !self.decimal_cycle() {
+                    self.finish();
                 }
             }
+            _ => {
+                self.dummy_fetch();
+                self.finish();
+            }
+        }
+    }
 
-            AddressingMode::IndirectY => {
-                let address_location = operation.data[0];
-                let byte1 = self.bus.peek(address_location as u16);
-                let byte2 = match address_location {
-                    0xFF => self.bus.peek(0),
-                    _ => self.bus.peek((address_location + 1) as u16),
-                };
-                let address = u16::from_le_bytes([byte1, byte2]);
+    fn zero_page_step(&mut self) {
+        match self.step {
+            1 => self.address = zero_page_address(self.fetch()),
+            step => self.access(step - 2),
+        }
+    }
 
-                Operand::Address {
-                    location: address.wrapping_add(self.y as u16),
-                }
+    //The unindexed address is read while the index is added, which never leaves page zero
+    fn zero_page_indexed_step(&mut self, index: u8) {
+        match self.step {
+            1 => self.address = zero_page_address(self.fetch()),
+            2 => {
+                self.dummy_read(self.address);
+                self.address = (self.address as u8).wrapping_add(index) as u16;
             }
+            step => self.access(step - 3),
         }
     }
 
-    fn consume_next_operation(&mut self) -> Operation {
-        let opcode_byte = self.bus.peek(self.pc);
-        //println!("PC: {:#X} : {:#X}", self.PC, opcode_byte);
-        self.pc += 1;
-        let mut operation = OPCODES[opcode_byte as usize]
-            .clone()
-            .unwrap_or_else(|| panic!("Unknown opcode {:#X}", opcode_byte));
-        let extra_bytes: u16 = match operation.addressing_mode {
-            AddressingMode::Absolute => 2,
-            AddressingMode::AbsoluteX => 2,
-            AddressingMode::AbsoluteY => 2,
-            AddressingMode::Indirect => 2,
-            AddressingMode::Implied => 0,
-            AddressingMode::Accumulator => 0,
-            _ => 1,
-        };
-        for i in 0..extra_bytes {
-            operation.data.push(self.bus.peek(self.pc + i));
+    fn absolute_step(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => {
+                let high = self.fetch();
+                self.address = absolute_address(self.address as u8, high);
+            }
+            step => self.access(step - 3),
         }
-        self.pc += extra_bytes;
-        operation
     }
 
-    //CPU functions
+    fn absolute_indexed_step(&mut self, index: u8) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => {
+                let high = self.fetch();
+                self.add_index(high, index);
+            }
+            3 => self.fix_high_byte(),
+            step => self.access(step - 4),
+        }
+    }
 
-    fn adc(&mut self, operand: &Operand) -> Option<u8> {
-        let val = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            _ => 0,
-        };
-        let old_c = self.get_c() as u8;
-        let old_n = self.a.get_bit(7);
-        self.set_c(self.a.checked_add(val.saturating_add(old_c)) == None);
-        self.a = self.a.wrapping_add(val.wrapping_add(old_c));
-        self.set_n(self.a.get_bit(7));
-        self.set_v(old_n == val.get_bit(7) && old_n != self.a.get_bit(7));
-        self.set_z(self.a == 0);
-        None
-    }
-
-    fn and(&mut self, operand: &Operand) -> Option<u8> {
-        let op = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            _ => 0,
-        };
-        self.a &= op;
-        self.set_standard_flags(&self.a.clone());
-        None
-    }
-
-    fn asl(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => {
-                let val = self.bus.peek(*location);
-                let shifted_val = val << 1;
-                self.set_c(val.get_bit(7));
-                self.set_n(shifted_val.get_bit(7));
-                self.set_z(shifted_val == 0);
-                self.bus.poke(*location, shifted_val);
-            }
-            Operand::Accumulator => {
-                let val = self.a;
-                let shifted_val = val << 1;
-                self.set_c(val.get_bit(7));
-                self.set_n(shifted_val.get_bit(7));
-                self.set_z(shifted_val == 0);
-                self.a = shifted_val;
-                //self.PC -= 1;
+    //(zp,X)
+    fn indexed_indirect_step(&mut self) {
+        match self.step {
+            1 => self.pointer = self.fetch(),
+            2 => {
+                self.dummy_read(self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(self.x);
             }
-            _ => (),
+            3 => self.address = self.read(self.pointer as u16) as u16,
+            4 => {
+                let high = self.read(self.pointer.wrapping_add(1) as u16);
+                self.address |= (high as u16) << 8;
+            }
+            step => self.access(step - 5),
         }
+    }
 
-        None
+    //(zp),Y
+    fn indirect_indexed_step(&mut self) {
+        match self.step {
+            1 => self.pointer = self.fetch(),
+            2 => self.address = self.read(self.pointer as u16) as u16,
+            3 => {
+                let high = self.read(self.pointer.wrapping_add(1) as u16);
+                self.add_index(high, self.y);
+            }
+            4 => self.fix_high_byte(),
+            step => self.access(step - 5),
+        }
     }
 
-    fn bcc(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if !self.get_c() {
-            self.pc = addr;
-            Some(2)
-        } else {
-            None
+    //(zp), 65C02 only
+    fn zero_page_indirect_step(&mut self) {
+        match self.step {
+            1 => self.pointer = self.fetch(),
+            2 => self.address = self.read(self.pointer as u16) as u16,
+            3 => {
+                let high = self.read(self.pointer.wrapping_add(1) as u16);
+                self.address |= (high as u16) << 8;
+            }
+            step => self.access(step - 4),
         }
     }
 
-    fn bcs(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if self.get_c() {
-            self.pc = addr;
-            Some(2)
+    //Adds the index to the low byte in self.address. The carry into the high byte takes
+    //another cycle
+    fn add_index(&mut self, high: u8, index: u8) {
+        let (low, crossed) = (self.address as u8).overflowing_add(index);
+        self.address = u16::from_le_bytes([low, high]);
+        self.page_crossed = crossed;
+    }
+
+    //Reads from the address before its high byte is fixed. Reads that didn't cross a page
+    //are done at this point, everything else reads again from the fixed address.
+    //The 65C02 re-reads the last operand byte instead, and its shifts and rotates don't
+    //wait for the fix up either
+    fn fix_high_byte(&mut self) {
+        let early = match access_kind(self.operation.instruction) {
+            Access::Read => true,
+            Access::Modify => {
+                self.is_cmos()
+                    && self.operation.addressing_mode == AddressingMode::AbsoluteX
+                    && !matches!(
+                        self.operation.instruction,
+                        Instruction::INC | Instruction::DEC
+                    )
+            }
+            Access::Write => false,
+        };
+        if early && !self.page_crossed {
+            self.access(0);
+            //The following access cycles move up by one
+            if self.step != 0 {
+                self.step += 1;
+            }
+            return;
+        }
+
+        if self.is_cmos() {
+            self.dummy_read(self.pc.wrapping_sub(1));
         } else {
-            None
+            self.dummy_read(self.address);
+        }
+        if self.page_crossed {
+            self.address = self.address.wrapping_add(0x100);
         }
     }
 
-    fn beq(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if self.get_z() {
-            self.pc = addr;
-            Some(2)
-        } else {
-            None
+    //Cycles after the effective address is known. Read-modify-write instructions write the
+    //unmodified value back while the new one is calculated, the 65C02 reads it again instead
+    fn access(&mut self, cycle: u8) {
+        match (access_kind(self.operation.instruction), cycle) {
+            (Access::Read, 0) => {
+                let value = self.read(self.address);
+                self.execute_read(value);
+                if !self.decimal_cycle() {
+                    self.finish();
+                }
+            }
+            (Access::Read, _) => {
+                self.dummy_fetch();
+                self.finish();
+            }
+            (Access::Write, _) => {
+                self.execute_write();
+                self.finish();
+            }
+            (Access::Modify, 0) => self.value = self.read(self.address),
+            (Access::Modify, 1) => {
+                if self.is_cmos() {
+                    self.dummy_read(self.address);
+                } else {
+                    self.write(self.address, self.value);
+                }
+                self.value = self.execute_modify(self.value);
+            }
+            (Access::Modify, _) => {
+                self.write(self.address, self.value);
+                self.finish();
+            }
         }
     }
 
-    //11100100 nes
-    //10100100 mine
+    //Instructions with their own cycle layout
 
-    fn bit(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        let val = self.bus.peek(addr);
-        self.set_n(val.get_bit(7));
-        self.set_v(val.get_bit(6));
-        self.set_z(val & self.a == 0);
-        None
+    fn branch_step(&mut self) {
+        match self.step {
+            1 => {
+                let offset = self.fetch();
+                let taken = self.branch_taken();
+                self.branch_to(offset, taken);
+            }
+            step => self.taken_branch(step - 2),
+        }
     }
 
-    fn bmi(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if self.get_n() {
-            self.pc = addr;
-            Some(2)
-        } else {
-            None
+    //BBR and BBS test a bit in zero page, then branch like the others
+    fn bit_branch_step(&mut self) {
+        match self.step {
+            1 => self.address = zero_page_address(self.fetch()),
+            2 => self.value = self.read(self.address),
+            3 => {
+                self.dummy_read(self.address);
+            }
+            4 => {
+                let offset = self.fetch();
+                let set = self.value.get_bit(bit_number(self.opcode));
+                let taken = match self.operation.instruction {
+                    Instruction::BBS => set,
+                    _ => !set,
+                };
+                self.branch_to(offset, taken);
+            }
+            step => self.taken_branch(step - 5),
         }
     }
 
-    fn bne(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if !self.get_z() {
-            self.pc = addr;
-            Some(2)
+    fn branch_to(&mut self, offset: u8, taken: bool) {
+        if taken {
+            self.address = relative_address(offset, self.pc);
         } else {
-            None
+            self.finish();
         }
     }
 
-    fn bpl(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if !self.get_n() {
-            self.pc = addr;
-        };
-        None
+    fn taken_branch(&mut self, cycle: u8) {
+        match cycle {
+            //Taken branches cost one more cycle, or two when the target is on another page
+            0 => {
+                self.dummy_fetch();
+                if (self.pc ^ self.address) & 0xFF00 == 0 {
+                    self.pc = self.address;
+                    //Taken branches that stay on the page skip the poll in this cycle, so an
+                    //interrupt arriving during the branch waits for the next instruction
+                    self.interrupt_seen = self.interrupt_due;
+                    self.finish();
+                } else {
+                    self.pc = (self.pc & 0xFF00) | (self.address & 0x00FF);
+                }
+            }
+            _ => {
+                self.dummy_fetch();
+                self.pc = self.address;
+                self.finish();
+            }
+        }
     }
 
-    fn brk(&mut self, _operand: &Operand) -> Option<u8> {
-        self.pc += 1;
-        self.set_i(true);
-        self.set_b(true);
-        let vec = self.bus.peek_16(0xFFFE);
-        self.push_16(self.pc);
-        self.pc = vec;
-        Some(5)
+    //Also runs NMI and IRQ, which push P with B clear and don't skip the padding byte.
+    //An nmi that arrives before the vector is fetched hijacks the sequence, BRK included
+    fn brk_step(&mut self) {
+        match self.step {
+            1 => {
+                if self.interrupt {
+                    self.dummy_fetch();
+                } else {
+                    self.fetch();
+                }
+            }
+            2 => self.push((self.pc >> 8) as u8),
+            3 => self.push(self.pc as u8),
+            4 => {
+                if self.interrupt {
+                    self.push((self.p | 0x20) & !0x10);
+                } else {
+                    self.push(self.p | 0x30);
+                }
+            }
+            5 => {
+                self.address = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    0xFFFA
+                } else {
+                    0xFFFE
+                };
+                self.value = self.read(self.address);
+                self.set_i(true);
+                if self.is_cmos() {
+                    self.set_d(false);
+                }
+            }
+            _ => {
+                let high = self.read(self.address.wrapping_add(1));
+                self.pc = absolute_address(self.value, high);
+                self.finish();
+            }
+        }
     }
 
-    fn bvc(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if !self.get_v() {
-            self.pc = addr;
-        };
-        None
+    //The 65C02 spends an extra cycle on the indirect forms, fixing the page wrap bug and
+    //adding X for JMP (abs,X)
+    fn jmp_step(&mut self) {
+        let extra = self.is_cmos() as u8;
+        match (self.operation.addressing_mode, self.step) {
+            (_, 1) => self.address = self.fetch() as u16,
+            (AddressingMode::Absolute, _) => {
+                let high = self.fetch();
+                self.pc = absolute_address(self.address as u8, high);
+                self.finish();
+            }
+            (_, 2) => {
+                let high = self.fetch();
+                self.address = absolute_address(self.address as u8, high);
+            }
+            (mode, 3) if extra == 1 => {
+                self.dummy_read(self.pc.wrapping_sub(1));
+                if mode == AddressingMode::AbsoluteIndexedIndirect {
+                    self.address = self.address.wrapping_add(self.x as u16);
+                }
+            }
+            (_, step) if step == 3 + extra => self.value = self.read(self.address),
+            //The NMOS chips fetch the high byte without carrying into the page
+            _ => {
+                let high_address = if self.is_cmos() {
+                    self.address.wrapping_add(1)
+                } else {
+                    (self.address & 0xFF00) | (self.address.wrapping_add(1) & 0x00FF)
+                };
+                let high = self.read(high_address);
+                self.pc = absolute_address(self.value, high);
+                self.finish();
+            }
+        }
     }
 
-    fn bvs(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        if self.get_v() {
-            self.pc = addr;
-        };
-        None
+    //Pushes the address of its last byte, RTS adds one on the way back
+    fn jsr_step(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => self.dummy_pop(),
+            3 => self.push((self.pc >> 8) as u8),
+            4 => self.push(self.pc as u8),
+            _ => {
+                let high = self.bus.peek_operand(self.pc);
+                self.pc = absolute_address(self.address as u8, high);
+                self.finish();
+            }
+        }
     }
 
-    fn clc(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_c(false);
-        None
+    fn rti_step(&mut self) {
+        match self.step {
+            1 => self.dummy_fetch(),
+            2 => self.dummy_pop(),
+            3 => {
+                self.p = self.pop();
+                self.p.set_bit(4, false);
+                self.p.set_bit(5, true);
+            }
+            4 => self.address = self.pop() as u16,
+            _ => {
+                let high = self.pop();
+                self.pc = absolute_address(self.address as u8, high);
+                self.finish();
+            }
+        }
     }
 
-    fn cld(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_d(false);
-        None
+    fn rts_step(&mut self) {
+        match self.step {
+            1 => self.dummy_fetch(),
+            2 => self.dummy_pop(),
+            3 => self.address = self.pop() as u16,
+            4 => {
+                let high = self.pop();
+                self.pc = absolute_address(self.address as u8, high);
+            }
+            _ => {
+                self.fetch();
+                self.finish();
+            }
+        }
     }
 
-    fn cli(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_i(false);
-        None
+    fn push_step(&mut self) {
+        match self.step {
+            1 => self.dummy_fetch(),
+            _ => {
+                match self.operation.instruction {
+                    Instruction::PHP => self.push(self.p | 0x10),
+                    Instruction::PHX => self.push(self.x),
+                    Instruction::PHY => self.push(self.y),
+                    _ => self.push(self.a),
+                }
+                self.finish();
+            }
+        }
     }
 
-    fn clv(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_v(false);
-        None
+    fn pull_step(&mut self) {
+        match self.step {
+            1 => self.dummy_fetch(),
+            2 => self.dummy_pop(),
+            _ => {
+                let value = self.pop();
+                match self.operation.instruction {
+                    Instruction::PLP => {
+                        self.p = value;
+                        self.p.set_bit(4, false);
+                        self.p.set_bit(5, true);
+                    }
+                    Instruction::PLX => self.x = self.transfer(value),
+                    Instruction::PLY => self.y = self.transfer(value),
+                    _ => self.a = self.transfer(value),
+                }
+                self.finish();
+            }
+        }
     }
 
-    fn cmp(&mut self, operand: &Operand) -> Option<u8> {
-        let value = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            Operand::Accumulator => self.a,
-            Operand::None => 0,
-        };
-        self.set_c(value <= self.a);
-        self.set_n((self.a.wrapping_sub(value)).get_bit(7));
-        self.set_z(value == self.a);
-        None
-    }
-
-    fn cpx(&mut self, operand: &Operand) -> Option<u8> {
-        let value = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            Operand::Accumulator => self.a,
-            Operand::None => 0,
-        };
-        self.set_c(value <= self.x);
-        self.set_n((self.x.wrapping_sub(value)).get_bit(7));
-        self.set_z(value == self.x);
-        None
-    }
-
-    fn cpy(&mut self, operand: &Operand) -> Option<u8> {
-        let value = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            Operand::Accumulator => self.a,
-            Operand::None => 0,
-        };
-        self.set_c(value <= self.y);
-        self.set_n((self.y.wrapping_sub(value)).get_bit(7));
-        self.set_z(value == self.y);
-        None
+    //Locks up the cpu until the next reset
+    fn jam_step(&mut self) {
+        self.dummy_fetch();
+        self.pc = self.pc.wrapping_sub(1);
+        self.jammed = true;
+        self.step = 0;
     }
 
-    fn dec(&mut self, operand: &Operand) -> Option<u8> {
-        let address = unpack_address(operand);
-        let val = self.bus.peek(address).wrapping_sub(1);
-        self.bus.poke(address, val);
-        self.set_z(val == 0);
-        self.set_n(val.get_bit(7));
-        Some(2)
+    //STP stops the clock until a reset, WAI until an interrupt
+    fn halt_step(&mut self) {
+        self.dummy_fetch();
+        if self.step == 2 {
+            match self.operation.instruction {
+                Instruction::STP => self.jammed = true,
+                _ => self.waiting = true,
+            }
+            self.finish();
+        }
     }
 
-    fn dex(&mut self, _operand: &Operand) -> Option<u8> {
-        self.x = self.x.wrapping_sub(1);
-        self.set_standard_flags(&self.x.clone());
-        None
+    //65C02 opcode 0x5C. Reads its operand and then spends five more cycles on the bus
+    fn long_nop_step(&mut self) {
+        match self.step {
+            1 => self.address = self.fetch() as u16,
+            2 => {
+                self.fetch();
+                self.address |= 0xFF00;
+            }
+            step => {
+                self.dummy_read(self.address);
+                if step == 7 {
+                    self.finish();
+                }
+            }
+        }
     }
 
-    fn dey(&mut self, _operand: &Operand) -> Option<u8> {
-        self.y = self.y.wrapping_sub(1);
-        self.set_standard_flags(&self.y.clone());
-        None
-    }
+    //CPU functions
 
-    fn eor(&mut self, operand: &Operand) -> Option<u8> {
-        let op = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            _ => 0,
-        };
-        self.a ^= op;
-        self.set_standard_flags(&self.a.clone());
-        None
+    fn execute_implied(&mut self) {
+        match self.operation.instruction {
+            Instruction::CLC => self.set_c(false),
+            Instruction::CLD => self.set_d(false),
+            Instruction::CLI => self.set_i(false),
+            Instruction::CLV => self.set_v(false),
+            Instruction::SEC => self.set_c(true),
+            Instruction::SED => self.set_d(true),
+            Instruction::SEI => self.set_i(true),
+            Instruction::DEX => self.x = self.decrement(self.x),
+            Instruction::DEY => self.y = self.decrement(self.y),
+            Instruction::INX => self.x = self.increment(self.x),
+            Instruction::INY => self.y = self.increment(self.y),
+            Instruction::TAX => self.x = self.transfer(self.a),
+            Instruction::TAY => self.y = self.transfer(self.a),
+            Instruction::TSX => self.x = self.transfer(self.s),
+            Instruction::TXA => self.a = self.transfer(self.x),
+            Instruction::TXS => self.s = self.x,
+            Instruction::TYA => self.a = self.transfer(self.y),
+            _ => (),
+        }
     }
 
-    fn inc(&mut self, operand: &Operand) -> Option<u8> {
-        let address = unpack_address(operand);
-        let val = self.bus.peek(address).wrapping_add(1);
-        self.bus.poke(address, val);
-        self.set_z(val == 0);
-        self.set_n(val.get_bit(7));
-        Some(2)
+    fn execute_read(&mut self, val: u8) {
+        match self.operation.instruction {
+            Instruction::ADC => self.adc(val),
+            Instruction::AND => self.a = self.transfer(self.a & val),
+            Instruction::BIT => self.bit(val),
+            Instruction::CMP => self.compare(self.a, val),
+            Instruction::CPX => self.compare(self.x, val),
+            Instruction::CPY => self.compare(self.y, val),
+            Instruction::EOR => self.a = self.transfer(self.a ^ val),
+            Instruction::LDA => self.a = self.transfer(val),
+            Instruction::LDX => self.x = self.transfer(val),
+            Instruction::LDY => self.y = self.transfer(val),
+            Instruction::ORA => self.a = self.transfer(self.a | val),
+            Instruction::SBC => self.sbc(val),
+
+            Instruction::ALR => self.alr(val),
+            Instruction::ANC => self.anc(val),
+            Instruction::ARR => self.arr(val),
+            Instruction::AXS => self.axs(val),
+            Instruction::LAS => self.las(val),
+            Instruction::LAX => self.lax(val),
+            Instruction::XAA => self.xaa(val),
+            _ => (),
+        }
     }
 
-    fn inx(&mut self, _operand: &Operand) -> Option<u8> {
-        self.x = self.x.wrapping_add(1);
-        self.set_standard_flags(&self.x.clone());
-        None
+    //Returns the value written back
+    fn execute_modify(&mut self, val: u8) -> u8 {
+        match self.operation.instruction {
+            Instruction::ASL => self.shift_left(val),
+            Instruction::DEC => self.decrement(val),
+            Instruction::INC => self.increment(val),
+            Instruction::LSR => self.shift_right(val),
+            Instruction::ROL => self.rotate_left(val),
+            Instruction::ROR => self.rotate_right(val),
+
+            Instruction::DCP => self.dcp(val),
+            Instruction::ISB => self.isb(val),
+            Instruction::RLA => self.rla(val),
+            Instruction::RRA => self.rra(val),
+            Instruction::SLO => self.slo(val),
+            Instruction::SRE => self.sre(val),
+
+            Instruction::RMB => val & !(1 << bit_number(self.opcode)),
+            Instruction::SMB => val | (1 << bit_number(self.opcode)),
+            Instruction::TRB => self.trb(val),
+            Instruction::TSB => self.tsb(val),
+            _ => val,
+        }
     }
 
-    fn iny(&mut self, _operand: &Operand) -> Option<u8> {
-        self.y = self.y.wrapping_add(1);
-        self.set_standard_flags(&self.y.clone());
-        None
+    fn execute_write(&mut self) {
+        match self.operation.instruction {
+            Instruction::STA => self.write(self.address, self.a),
+            Instruction::STX => self.write(self.address, self.x),
+            Instruction::STY => self.write(self.address, self.y),
+
+            Instruction::SAX => self.write(self.address, self.a & self.x),
+            Instruction::SHA => self.store_and_high(self.a & self.x),
+            Instruction::SHX => self.store_and_high(self.x),
+            Instruction::SHY => self.store_and_high(self.y),
+            Instruction::TAS => {
+                self.s = self.a & self.x;
+                self.store_and_high(self.s);
+            }
+
+            Instruction::STZ => self.write(self.address, 0),
+            _ => (),
+        }
     }
 
-    fn jmp(&mut self, operand: &Operand) -> Option<u8> {
-        let addr = unpack_address(operand);
-        self.pc = addr;
-        None
+    fn branch_taken(&self) -> bool {
+        match self.operation.instruction {
+            Instruction::BCC => !self.get_c(),
+            Instruction::BCS => self.get_c(),
+            Instruction::BEQ => self.get_z(),
+            Instruction::BMI => self.get_n(),
+            Instruction::BNE => !self.get_z(),
+            Instruction::BPL => !self.get_n(),
+            Instruction::BVC => !self.get_v(),
+            Instruction::BVS => self.get_v(),
+            Instruction::BRA => true,
+            _ => false,
+        }
     }
 
-    fn jsr(&mut self, operand: &Operand) -> Option<u8> {
-        self.push_16(self.pc - 1);
-        self.pc = unpack_address(operand);
-        Some(4)
+    //BIT #imm on the 65C02 only sets Z
+    fn bit(&mut self, val: u8) {
+        if self.operation.addressing_mode != AddressingMode::Immediate {
+            self.set_n(val.get_bit(7));
+            self.set_v(val.get_bit(6));
+        }
+        self.set_z(val & self.a == 0);
     }
 
-    fn lda(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Constant { value } => self.a = *value,
-            Operand::Address { location } => self.a = self.bus.peek(*location),
-            _ => (),
-        };
-        self.set_n(self.a.get_bit(7));
-        self.set_z(self.a == 0);
-        None
+    fn adc(&mut self, val: u8) {
+        if self.decimal_mode() {
+            self.add_decimal(val);
+        } else {
+            self.add_with_carry(val);
+        }
     }
 
-    fn ldx(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Constant { value } => self.x = *value,
-            Operand::Address { location } => self.x = self.bus.peek(*location),
-            Operand::Accumulator => self.x = self.a,
-            Operand::None => (),
+    fn sbc(&mut self, val: u8) {
+        if self.decimal_mode() {
+            self.subtract_decimal(val);
+        } else {
+            self.add_with_carry(!val);
         }
+    }
 
-        self.set_n(self.x.get_bit(7));
-        self.set_z(self.x == 0);
+    //Unofficial opcodes
+    //https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+    //http://www.oxyron.de/html/opcodes02.html
 
-        None
+    fn alr(&mut self, val: u8) {
+        self.a = self.shift_right(self.a & val);
     }
 
-    fn ldy(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Constant { value } => self.y = *value,
-            Operand::Address { location } => self.y = self.bus.peek(*location),
-            Operand::Accumulator => self.y = self.a,
-            Operand::None => (),
-        }
+    fn anc(&mut self, val: u8) {
+        self.a = self.transfer(self.a & val);
+        self.set_c(self.a.get_bit(7));
+    }
 
-        self.set_n(self.y.get_bit(7));
-        self.set_z(self.y == 0);
+    fn arr(&mut self, val: u8) {
+        let val = self.a & val;
+        if self.decimal_mode() {
+            return self.arr_decimal(val);
+        }
+        self.a = self.transfer((val >> 1) | ((self.get_c() as u8) << 7));
+        self.set_c(self.a.get_bit(6));
+        self.set_v(self.a.get_bit(6) != self.a.get_bit(5));
+    }
+
+    //http://www.zimmers.net/anonftp/pub/cbm/documents/chipdata/64doc
+    //N, Z and V come from the rotated value, then each nibble gets a BCD fix up
+    fn arr_decimal(&mut self, val: u8) {
+        let rotated = (val >> 1) | ((self.get_c() as u8) << 7);
+        self.set_n(self.get_c());
+        self.set_z(rotated == 0);
+        self.set_v((val ^ rotated) & 0x40 != 0);
+
+        let (low, high) = (val & 0x0F, val >> 4);
+        let mut result = rotated;
+        if low + (low & 1) > 5 {
+            result = (result & 0xF0) | (result.wrapping_add(6) & 0x0F);
+        }
+        let carry = high + (high & 1) > 5;
+        if carry {
+            result = result.wrapping_add(0x60);
+        }
+        self.set_c(carry);
+        self.a = result;
+    }
 
-        None
+    fn axs(&mut self, val: u8) {
+        let masked = self.a & self.x;
+        self.set_c(masked >= val);
+        self.x = self.transfer(masked.wrapping_sub(val));
     }
 
-    fn lsr(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => {
-                let val = self.bus.peek(*location);
-                let shifted_val = val >> 1;
-                self.set_c(val.get_bit(0));
-                self.set_n(false);
-                self.set_z(shifted_val == 0);
-                self.bus.poke(*location, shifted_val);
-            }
-            Operand::Accumulator => {
-                let val = self.a;
-                let shifted_val = val >> 1;
-                self.set_c(val.get_bit(0));
-                self.set_n(false);
-                self.set_z(shifted_val == 0);
-                self.a = shifted_val;
-                //self.PC -= 1;
-            }
-            _ => (),
-        }
+    //DCP and ISB only set flags through the compare or subtract that follows
+    fn dcp(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_sub(1);
+        self.compare(self.a, result);
+        result
+    }
 
-        None
+    fn isb(&mut self, val: u8) -> u8 {
+        let result = val.wrapping_add(1);
+        self.sbc(result);
+        result
     }
 
-    fn nop(&mut self, _operand: &Operand) -> Option<u8> {
-        None
+    fn las(&mut self, val: u8) {
+        let val = self.transfer(val & self.s);
+        self.a = val;
+        self.x = val;
+        self.s = val;
     }
 
-    fn ora(&mut self, operand: &Operand) -> Option<u8> {
-        let op = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            _ => 0,
+    fn lax(&mut self, val: u8) {
+        let val = match self.operation.addressing_mode {
+            //LXA, unstable. Uses the same magic constant as XAA
+            AddressingMode::Immediate => (self.a | UNSTABLE_MAGIC) & val,
+            _ => val,
         };
-        self.a |= op;
-        self.set_standard_flags(&self.a.clone());
-        None
+        self.a = self.transfer(val);
+        self.x = val;
     }
 
-    fn pha(&mut self, _operand: &Operand) -> Option<u8> {
-        self.push(self.a);
-        Some(1)
+    fn rla(&mut self, val: u8) -> u8 {
+        let result = self.rotate_left(val);
+        self.a = self.transfer(self.a & result);
+        result
     }
 
-    fn php(&mut self, _operand: &Operand) -> Option<u8> {
-        self.push(self.p | 0x10);
-        Some(1)
+    fn rra(&mut self, val: u8) -> u8 {
+        let result = self.rotate_right(val);
+        self.adc(result);
+        result
     }
 
-    fn pla(&mut self, _operand: &Operand) -> Option<u8> {
-        self.a = self.pop();
-        self.set_standard_flags(&self.a.clone());
-        Some(2)
+    fn slo(&mut self, val: u8) -> u8 {
+        let result = self.shift_left(val);
+        self.a = self.transfer(self.a | result);
+        result
     }
 
-    fn plp(&mut self, _operand: &Operand) -> Option<u8> {
-        self.p = self.pop();
-        self.p.set_bit(4, false);
-        self.p.set_bit(5, true);
-        Some(2)
+    fn sre(&mut self, val: u8) -> u8 {
+        let result = self.shift_right(val);
+        self.a = self.transfer(self.a ^ result);
+        result
     }
 
-    fn rol(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => {
-                let val = self.bus.peek(*location);
-                let mut rotated_val = val << 1;
-                rotated_val.set_bit(0, self.get_c());
-                self.set_c(val.get_bit(7));
-                self.set_n(rotated_val.get_bit(7));
-                self.set_z(rotated_val == 0);
-                self.bus.poke(*location, rotated_val);
-            }
-            Operand::Accumulator => {
-                let val = self.a;
-                let mut rotated_val = val << 1;
-                rotated_val.set_bit(0, self.get_c());
-                self.set_c(val.get_bit(7));
-                self.set_n(rotated_val.get_bit(7));
-                self.set_z(rotated_val == 0);
-                self.a = rotated_val;
-            }
-            _ => (),
+    fn xaa(&mut self, val: u8) {
+        self.a = self.transfer((self.a | UNSTABLE_MAGIC) & self.x & val);
+    }
+
+    //65C02
+
+    fn trb(&mut self, val: u8) -> u8 {
+        self.set_z(val & self.a == 0);
+        val & !self.a
+    }
+
+    fn tsb(&mut self, val: u8) -> u8 {
+        self.set_z(val & self.a == 0);
+        val | self.a
+    }
+
+    //Shared helpers
+
+    //SHA, SHX, SHY and TAS store the value ANDed with the high byte of the base address + 1.
+    //When indexing crosses a page the stored value also replaces the high byte of the address
+    fn store_and_high(&mut self, val: u8) {
+        let high = (self.address >> 8) as u8;
+        let base_high = if self.page_crossed {
+            high.wrapping_sub(1)
+        } else {
+            high
+        };
+        let result = val & base_high.wrapping_add(1);
+        let address = if self.page_crossed {
+            (self.address & 0x00FF) | ((result as u16) << 8)
+        } else {
+            self.address
+        };
+        self.write(address, result);
+    }
+
+    fn add_with_carry(&mut self, val: u8) {
+        let sum = self.a as u16 + val as u16 + self.get_c() as u16;
+        let result = sum as u8;
+        self.set_c(sum > 0xFF);
+        self.set_v((self.a ^ result) & (val ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_standard_flags(&result);
+    }
+
+    //The 2A03 has the decimal mode circuitry disconnected
+    fn decimal_mode(&self) -> bool {
+        self.get_d() && self.variant != Variant::Ricoh2A03
+    }
+
+    //The 65C02 takes a cycle longer for ADC and SBC in decimal mode
+    fn decimal_cycle(&self) -> bool {
+        self.is_cmos()
+            && self.get_d()
+            && matches!(
+                self.operation.instruction,
+                Instruction::ADC | Instruction::SBC
+            )
+    }
+
+    //http://www.6502.org/tutorials/decimal_mode.html
+    //The NMOS chips set N, V and Z part way through the adjustment. The 65C02 sets N and Z
+    //from the result
+    fn add_decimal(&mut self, val: u8) {
+        let carry = self.get_c() as u16;
+        let mut low = (self.a & 0x0F) as u16 + (val & 0x0F) as u16 + carry;
+        let mut high = (self.a & 0xF0) as u16 + (val & 0xF0) as u16;
+        self.set_z((self.a as u16 + val as u16 + carry) & 0xFF == 0);
+        if low > 0x09 {
+            low += 0x06;
+            high += 0x10;
         }
-        None
-    }
-
-    fn ror(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => {
-                let val = self.bus.peek(*location);
-                let mut rotated_val = val >> 1;
-                rotated_val.set_bit(7, self.get_c());
-                self.set_c(val.get_bit(0));
-                self.set_n(rotated_val.get_bit(7));
-                self.set_z(rotated_val == 0);
-                self.bus.poke(*location, rotated_val);
-            }
-            Operand::Accumulator => {
-                let val = self.a;
-                let mut rotated_val = val >> 1;
-                rotated_val.set_bit(7, self.get_c());
-                self.set_c(val.get_bit(0));
-                self.set_n(rotated_val.get_bit(7));
-                self.set_z(rotated_val == 0);
-                self.a = rotated_val;
-                //self.PC -= 1;
-            }
-            _ => (),
+        self.set_n(high & 0x80 != 0);
+        self.set_v(!(self.a ^ val) & (self.a ^ high as u8) & 0x80 != 0);
+        if high > 0x90 {
+            high += 0x60;
+        }
+        self.set_c(high > 0xFF);
+        self.a = (low as u8 & 0x0F) | (high as u8 & 0xF0);
+        if self.is_cmos() {
+            self.set_standard_flags(&self.a.clone());
         }
+    }
 
-        None
+    //Flags come from the binary subtraction, apart from N and Z on the 65C02
+    fn subtract_decimal(&mut self, val: u8) {
+        let borrow = !self.get_c() as i16;
+        let difference = self.a as i16 - val as i16 - borrow;
+        let mut low = (self.a & 0x0F) as i16 - (val & 0x0F) as i16 - borrow;
+        let mut high = (self.a >> 4) as i16 - (val >> 4) as i16;
+        if low < 0 {
+            low -= 6;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 6;
+        }
+        self.set_c(difference >= 0);
+        self.set_v((self.a ^ val) & (self.a ^ difference as u8) & 0x80 != 0);
+        self.set_standard_flags(&(difference as u8));
+        self.a = ((high as u8) << 4) | (low as u8 & 0x0F);
+        if self.is_cmos() {
+            self.set_standard_flags(&self.a.clone());
+        }
     }
 
-    fn rti(&mut self, _operand: &Operand) -> Option<u8> {
-        self.p = self.pop();
-        self.pc = self.pop_16();
-        self.p.set_bit(4, false);
-        self.p.set_bit(5, true);
-        None
+    fn compare(&mut self, register: u8, val: u8) {
+        self.set_c(val <= register);
+        self.set_standard_flags(&register.wrapping_sub(val));
     }
 
-    fn rts(&mut self, _operand: &Operand) -> Option<u8> {
-        self.pc = self.pop_16().wrapping_add(1);
-        None
+    fn shift_left(&mut self, val: u8) -> u8 {
+        let result = val << 1;
+        self.set_c(val.get_bit(7));
+        self.set_standard_flags(&result);
+        result
     }
 
-    fn sbc(&mut self, operand: &Operand) -> Option<u8> {
-        let val = match operand {
-            Operand::Constant { value } => *value,
-            Operand::Address { location } => self.bus.peek(*location),
-            _ => 0,
-        };
-        let carry = !self.get_c() as u8;
-        let old_n = !self.a.get_bit(7);
-        self.set_c(self.a.checked_sub(val.saturating_add(carry)) != None);
-        self.a = self.a.wrapping_sub(val.wrapping_add(carry));
-        self.set_n(self.a.get_bit(7));
-        self.set_v(old_n == val.get_bit(7) && old_n == self.a.get_bit(7));
-        self.set_z(self.a == 0);
-        None
+    fn shift_right(&mut self, val: u8) -> u8 {
+        let result = val >> 1;
+        self.set_c(val.get_bit(0));
+        self.set_standard_flags(&result);
+        result
     }
 
-    fn sec(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_c(true);
-        None
+    fn rotate_left(&mut self, val: u8) -> u8 {
+        let mut result = val << 1;
+        result.set_bit(0, self.get_c());
+        self.set_c(val.get_bit(7));
+        self.set_standard_flags(&result);
+        result
     }
 
-    fn sed(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_d(true);
-        None
+    fn rotate_right(&mut self, val: u8) -> u8 {
+        let mut result = val >> 1;
+        result.set_bit(7, self.get_c());
+        self.set_c(val.get_bit(0));
+        self.set_standard_flags(&result);
+        result
     }
 
-    fn sei(&mut self, _operand: &Operand) -> Option<u8> {
-        self.set_i(true);
-        None
+    fn increment(&mut self, val: u8) -> u8 {
+        self.transfer(val.wrapping_add(1))
     }
 
-    fn sta(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => self.bus.poke(*location, self.a),
-            _ => {}
-        }
-        None
+    fn decrement(&mut self, val: u8) -> u8 {
+        self.transfer(val.wrapping_sub(1))
     }
 
-    fn stx(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => self.bus.poke(*location, self.x),
-            _ => {}
-        }
-        None
+    //Loads and transfers just set N and Z on the way through
+    fn transfer(&mut self, val: u8) -> u8 {
+        self.set_standard_flags(&val);
+        val
+    }
+
+    fn set_standard_flags(&mut self, val: &u8) {
+        self.set_z(*val == 0);
+        self.set_n(val.get_bit(7));
+    }
+}
+
+//RMB, SMB, BBR and BBS keep the bit number in the high nibble of the opcode
+fn bit_number(opcode: u8) -> usize {
+    ((opcode >> 4) & 0x07) as usize
+}
+
+pub(crate) fn access_kind(instruction: Instruction) -> Access {
+    match instruction {
+        Instruction::STA
+        | Instruction::STX
+        | Instruction::STY
+        | Instruction::SAX
+        | Instruction::SHA
+        | Instruction::SHX
+        | Instruction::SHY
+        | Instruction::TAS
+        | Instruction::STZ => Access::Write,
+        Instruction::ASL
+        | Instruction::DEC
+        | Instruction::INC
+        | Instruction::LSR
+        | Instruction::ROL
+        | Instruction::ROR
+        | Instruction::DCP
+        | Instruction::ISB
+        | Instruction::RLA
+        | Instruction::RRA
+        | Instruction::SLO
+        | Instruction::SRE
+        | Instruction::RMB
+        | Instruction::SMB
+        | Instruction::TRB
+        | Instruction::TSB => Access::Modify,
+        _ => Access::Read,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum BusCycle {
+        Read(u16, u8),
+        Write(u16, u8),
     }
 
-    fn sty(&mut self, operand: &Operand) -> Option<u8> {
-        match operand {
-            Operand::Address { location } => self.bus.poke(*location, self.y),
-            _ => {}
+    //64k of ram that remembers every access, with $4014 starting OAM DMA
+    struct FlatBus {
+        data: Vec<u8>,
+        log: Vec<BusCycle>,
+        oam_dma: Option<u8>,
+        sample: Option<u8>, //Last byte fetched by DMC DMA
+    }
+
+    impl FlatBus {
+        fn new(data: Vec<u8>) -> Self {
+            Self {
+                data,
+                log: vec![],
+                oam_dma: None,
+                sample: None,
+            }
         }
-        None
     }
 
-    fn tax(&mut self, _operand: &Operand) -> Option<u8> {
-        self.x = self.a;
-        self.set_standard_flags(&self.x.clone());
-        None
+    impl AddressSpace for FlatBus {
+        fn peek(&mut self, ptr: u16) -> u8 {
+            let value = self.data[ptr as usize];
+            self.log.push(BusCycle::Read(ptr, value));
+            value
+        }
+
+        fn poke(&mut self, ptr: u16, byte: u8) {
+            self.log.push(BusCycle::Write(ptr, byte));
+            self.data[ptr as usize] = byte;
+            if ptr == 0x4014 {
+                self.oam_dma = Some(byte);
+            }
+        }
+
+        fn debug_peek(&self, ptr: u16) -> u8 {
+            self.data[ptr as usize]
+        }
+
+        fn oam_dma_request(&mut self) -> Option<u8> {
+            self.oam_dma.take()
+        }
+
+        fn dmc_dma_complete(&mut self, byte: u8) {
+            self.sample = Some(byte);
+        }
     }
 
-    fn tay(&mut self, _operand: &Operand) -> Option<u8> {
-        self.y = self.a;
-        self.set_standard_flags(&self.y.clone());
-        None
+    //Loads the program at 0x8000 and runs its first instruction
+    fn execute(program: &[u8], setup: impl FnOnce(&mut Cpu<FlatBus>)) -> Cpu<FlatBus> {
+        execute_on(Variant::Ricoh2A03, program, setup)
+    }
+
+    fn execute_on(
+        variant: Variant,
+        program: &[u8],
+        setup: impl FnOnce(&mut Cpu<FlatBus>),
+    ) -> Cpu<FlatBus> {
+        let mut data = vec![0; 0x10000];
+        data[0x8000..0x8000 + program.len()].copy_from_slice(program);
+        let mut cpu = Cpu::with_variant(FlatBus::new(data), variant);
+        cpu.set_pc(0x8000);
+        setup(&mut cpu);
+        cpu.step_cycle();
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_adc_carry_with_ff() {
+        let cpu = execute(&[0x69, 0xFF], |cpu| {
+            cpu.a = 0x00;
+            cpu.set_c(true);
+        });
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_c());
+        assert!(cpu.get_z());
+    }
+
+    #[test]
+    fn test_dcp() {
+        let cpu = execute(&[0xC7, 0x10], |cpu| {
+            cpu.bus.data[0x10] = 0x41;
+            cpu.a = 0x40;
+        });
+        assert_eq!(cpu.bus.data[0x10], 0x40);
+        assert!(cpu.get_z());
+        assert!(cpu.get_c());
+    }
+
+    #[test]
+    fn test_arr() {
+        let cpu = execute(&[0x6B, 0xFF], |cpu| {
+            cpu.a = 0xC0;
+            cpu.set_c(true);
+        });
+        assert_eq!(cpu.a, 0xE0);
+        assert!(cpu.get_c());
+        assert!(!cpu.get_v());
+        assert!(cpu.get_n());
+    }
+
+    #[test]
+    fn test_axs() {
+        let cpu = execute(&[0xCB, 0x02], |cpu| {
+            cpu.a = 0x0F;
+            cpu.x = 0x3C;
+        });
+        assert_eq!(cpu.x, 0x0A);
+        assert!(cpu.get_c());
+    }
+
+    #[test]
+    fn test_shx_page_cross() {
+        let cpu = execute(&[0x9E, 0xF0, 0x12], |cpu| {
+            cpu.x = 0x05;
+            cpu.y = 0x20;
+        });
+        //0x12F0 + 0x20 crosses into 0x1310, the stored 0x05 & 0x13 also becomes the high byte
+        assert_eq!(cpu.bus.data[0x0110], 0x01);
+        assert_eq!(cpu.bus.data[0x1310], 0x00);
+    }
+
+    #[test]
+    fn test_las() {
+        let cpu = execute(&[0xBB, 0x00, 0x02], |cpu| {
+            cpu.bus.data[0x0201] = 0xF3;
+            cpu.s = 0x3F;
+            cpu.y = 0x01;
+        });
+        assert_eq!(cpu.a, 0x33);
+        assert_eq!(cpu.x, 0x33);
+        assert_eq!(cpu.s, 0x33);
+    }
+
+    #[test]
+    fn test_cycles_page_cross_read() {
+        assert_eq!(execute(&[0xBD, 0x10, 0x02], |cpu| cpu.x = 0x01).cycles, 4);
+        assert_eq!(execute(&[0xBD, 0xFF, 0x02], |cpu| cpu.x = 0x01).cycles, 5);
+        //Stores always pay for the fix up
+        assert_eq!(execute(&[0x9D, 0x10, 0x02], |cpu| cpu.x = 0x01).cycles, 5);
+    }
+
+    #[test]
+    fn test_cycles_branch() {
+        assert_eq!(execute(&[0xF0, 0x10], |cpu| cpu.set_z(false)).cycles, 2);
+        assert_eq!(execute(&[0xF0, 0x10], |cpu| cpu.set_z(true)).cycles, 3);
+        assert_eq!(execute(&[0xF0, 0x80], |cpu| cpu.set_z(true)).cycles, 4);
+    }
+
+    #[test]
+    fn test_cycles_fixed() {
+        assert_eq!(execute(&[0x20, 0x00, 0x90], |_| {}).cycles, 6);
+        assert_eq!(execute(&[0x60], |_| {}).cycles, 6);
+        assert_eq!(execute(&[0xFE, 0x00, 0x02], |_| {}).cycles, 7);
+        assert_eq!(execute(&[0x48], |_| {}).cycles, 3);
+        assert_eq!(execute(&[0x68], |_| {}).cycles, 4);
+    }
+
+    //$0210,X $0210,Y and ($10),Y all index into page 3
+    fn cross_page(cpu: &mut Cpu<FlatBus>) {
+        cpu.x = 0xFF;
+        cpu.y = 0xFF;
+        cpu.bus.data[0x10] = 0x80;
+        cpu.bus.data[0x11] = 0x02;
+    }
+
+    #[test]
+    fn test_cycles_match_table() {
+        for (opcode, operation) in OPCODES.iter().enumerate() {
+            //Branch timing depends on the flags, see test_cycles_branch
+            if operation.addressing_mode == AddressingMode::Relative
+                || matches!(operation.instruction, Instruction::JAM)
+            {
+                continue;
+            }
+            let cpu = execute(&[opcode as u8, 0x10, 0x02], |_| {});
+            assert_eq!(
+                cpu.cycles, operation.base_cycle_count as u64,
+                "opcode {:#04X}",
+                opcode
+            );
+            assert_eq!(
+                cpu.bus.log.len() as u64,
+                cpu.cycles,
+                "opcode {:#04X}",
+                opcode
+            );
+
+            let cpu = execute(&[opcode as u8, 0x10, 0x02], cross_page);
+            assert_eq!(
+                cpu.cycles,
+                operation.base_cycle_count as u64 + operation.page_cross_penalty as u64,
+                "opcode {:#04X} crossing a page",
+                opcode
+            );
+        }
     }
 
-    fn tsx(&mut self, _operand: &Operand) -> Option<u8> {
-        self.x = self.s;
-        self.set_standard_flags(&self.x.clone());
-        None
+    #[test]
+    fn test_absolute_x_dummy_read() {
+        use BusCycle::*;
+        let cpu = execute(&[0xBD, 0xFF, 0x02], |cpu| {
+            cpu.x = 0x01;
+            cpu.bus.data[0x0200] = 0x11;
+            cpu.bus.data[0x0300] = 0x22;
+        });
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Read(0x8000, 0xBD),
+                Read(0x8001, 0xFF),
+                Read(0x8002, 0x02),
+                Read(0x0200, 0x11),
+                Read(0x0300, 0x22),
+            ]
+        );
+        assert_eq!(cpu.a, 0x22);
+    }
+
+    #[test]
+    fn test_store_dummy_read_without_page_cross() {
+        use BusCycle::*;
+        let cpu = execute(&[0x99, 0x00, 0x02], |cpu| {
+            cpu.a = 0x42;
+            cpu.y = 0x01;
+        });
+        assert_eq!(cpu.bus.log[3..], [Read(0x0201, 0x00), Write(0x0201, 0x42)]);
+    }
+
+    #[test]
+    fn test_zero_page_indexed_dummy_read() {
+        use BusCycle::*;
+        let cpu = execute(&[0xB5, 0xFF], |cpu| {
+            cpu.x = 0x02;
+            cpu.bus.data[0x01] = 0x33;
+        });
+        //The index wraps inside page zero
+        assert_eq!(cpu.bus.log[2..], [Read(0x00FF, 0x00), Read(0x0001, 0x33)]);
+    }
+
+    #[test]
+    fn test_read_modify_write_double_write() {
+        use BusCycle::*;
+        let cpu = execute(&[0xFE, 0x00, 0x02], |cpu| {
+            cpu.x = 0x10;
+            cpu.bus.data[0x0210] = 0x41;
+        });
+        assert_eq!(
+            cpu.bus.log[3..],
+            [
+                Read(0x0210, 0x41),
+                Read(0x0210, 0x41),
+                Write(0x0210, 0x41),
+                Write(0x0210, 0x42),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_implied_dummy_fetch() {
+        use BusCycle::*;
+        let cpu = execute(&[0xE8, 0x77], |_| {});
+        assert_eq!(cpu.bus.log, vec![Read(0x8000, 0xE8), Read(0x8001, 0x77)]);
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+    #[test]
+    fn test_jsr_rts_bus_cycles() {
+        use BusCycle::*;
+        let mut cpu = execute(&[0x20, 0x00, 0x90], |cpu| cpu.bus.data[0x9000] = 0x60);
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Read(0x8000, 0x20),
+                Read(0x8001, 0x00),
+                Read(0x01FD, 0x00),
+                Write(0x01FD, 0x80),
+                Write(0x01FC, 0x02),
+                Read(0x8002, 0x90),
+            ]
+        );
+
+        cpu.bus.log.clear();
+        cpu.step_cycle();
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
+        assert_eq!(
+            cpu.bus.log,
+            vec![
+                Read(0x9000, 0x60),
+                Read(0x9001, 0x00),
+                Read(0x01FB, 0x00),
+                Read(0x01FC, 0x02),
+                Read(0x01FD, 0x80),
+                Read(0x8002, 0x90),
+            ]
+        );
+        assert_eq!(cpu.pc, 0x8003);
+    }
+
+    #[test]
+    fn test_branch_page_cross_dummy_reads() {
+        use BusCycle::*;
+        let cpu = execute(&[0xD0, 0x80], |cpu| cpu.set_z(false));
+        //0x8002 - 0x80 is 0x7F82, read first with the old high byte
+        assert_eq!(cpu.bus.log[2..], [Read(0x8002, 0x00), Read(0x8082, 0x00)]);
+        assert_eq!(cpu.pc, 0x7F82);
+    }
+
+    //Runs instructions until the cpu gets to the given address or gives up
+    fn run_until(cpu: &mut Cpu<FlatBus>, pc: u16) -> bool {
+        for _ in 0..100 {
+            cpu.step_cycle();
+            if cpu.instruction_finished() && cpu.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn irq_program(program: &[u8]) -> Cpu<FlatBus> {
+        let mut data = vec![0xEA; 0x10000];
+        data[0x8000..0x8000 + program.len()].copy_from_slice(program);
+        data[0xFFFA] = 0x00;
+        data[0xFFFB] = 0xA0;
+        data[0xFFFE] = 0x00;
+        data[0xFFFF] = 0x90;
+        let mut cpu = Cpu::new(FlatBus::new(data));
+        cpu.set_pc(0x8000);
+        cpu
+    }
+
+    #[test]
+    fn test_irq_masked() {
+        let mut cpu = irq_program(&[0xEA, 0xEA, 0xEA]);
+        cpu.assert_irq(IrqSource::External);
+        assert!(!run_until(&mut cpu, 0x9000));
+    }
+
+    #[test]
+    fn test_irq_after_cli_is_delayed() {
+        //CLI, NOP. The IRQ is taken after the NOP, not right after CLI
+        let mut cpu = irq_program(&[0x58, 0xEA, 0xEA]);
+        cpu.assert_irq(IrqSource::Mapper);
+        assert!(run_until(&mut cpu, 0x9000));
+        let pushed_pc = u16::from_le_bytes([cpu.bus.data[0x1FC], cpu.bus.data[0x1FD]]);
+        assert_eq!(pushed_pc, 0x8002);
+        //B clear, unused bit set
+        assert_eq!(cpu.bus.data[0x1FB] & 0x30, 0x20);
+        assert!(cpu.get_i());
+    }
+
+    #[test]
+    fn test_irq_after_sei_still_taken() {
+        let mut cpu = irq_program(&[0x78, 0xEA]);
+        cpu.set_i(false);
+        cpu.assert_irq(IrqSource::FrameCounter);
+        assert!(run_until(&mut cpu, 0x9000));
+        let pushed_pc = u16::from_le_bytes([cpu.bus.data[0x1FC], cpu.bus.data[0x1FD]]);
+        assert_eq!(pushed_pc, 0x8001);
+    }
+
+    fn pushed_pc(cpu: &Cpu<FlatBus>) -> u16 {
+        u16::from_le_bytes([cpu.bus.data[0x1FC], cpu.bus.data[0x1FD]])
+    }
+
+    fn run_instruction(cpu: &mut Cpu<FlatBus>) {
+        cpu.step_cycle();
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
     }
 
-    fn txa(&mut self, _operand: &Operand) -> Option<u8> {
-        self.a = self.x;
-        self.set_standard_flags(&self.a.clone());
-        None
+    #[test]
+    fn test_brk_pushes_b_and_skips_padding() {
+        let mut cpu = irq_program(&[0x00, 0xFF]);
+        cpu.set_i(false);
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(pushed_pc(&cpu), 0x8002);
+        assert_eq!(cpu.bus.data[0x1FB] & 0x30, 0x30);
+        assert!(cpu.get_i());
+        assert_eq!(cpu.cycles, 7);
+    }
+
+    #[test]
+    fn test_nmi_sequence() {
+        let mut cpu = irq_program(&[0xEA]);
+        cpu.set_i(false);
+        cpu.fire_nmi();
+        //The NOP in progress finishes first
+        assert!(run_until(&mut cpu, 0xA000));
+        assert_eq!(cpu.cycles, 2 + 7);
+        assert_eq!(pushed_pc(&cpu), 0x8001);
+        assert_eq!(cpu.bus.data[0x1FB] & 0x30, 0x20);
+        assert!(cpu.get_i());
+        //Taken once per edge
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0xA001);
+    }
+
+    #[test]
+    fn test_nmi_on_last_cycle_is_delayed() {
+        let mut cpu = irq_program(&[0xEA, 0xEA]);
+        cpu.step_cycle();
+        cpu.fire_nmi();
+        cpu.step_cycle();
+        assert!(run_until(&mut cpu, 0xA000));
+        assert_eq!(pushed_pc(&cpu), 0x8002);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut cpu = irq_program(&[0x00, 0xFF]);
+        for _ in 0..3 {
+            cpu.step_cycle();
+        }
+        cpu.fire_nmi();
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
+        //BRK's pushes with the nmi vector
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(pushed_pc(&cpu), 0x8002);
+        assert_eq!(cpu.bus.data[0x1FB] & 0x30, 0x30);
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0xA001);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_irq() {
+        let mut cpu = irq_program(&[0xEA]);
+        cpu.set_i(false);
+        cpu.assert_irq(IrqSource::External);
+        run_instruction(&mut cpu);
+        for _ in 0..2 {
+            cpu.step_cycle();
+        }
+        cpu.fire_nmi();
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(cpu.bus.data[0x1FB] & 0x30, 0x20);
+    }
+
+    #[test]
+    fn test_taken_branch_delays_irq() {
+        //BEQ to the next instruction, then NOP
+        let mut cpu = irq_program(&[0xF0, 0x00, 0xEA]);
+        cpu.set_i(false);
+        cpu.set_z(true);
+        cpu.step_cycle();
+        cpu.assert_irq(IrqSource::External);
+        assert!(run_until(&mut cpu, 0x9000));
+        assert_eq!(pushed_pc(&cpu), 0x8003);
+    }
+
+    #[test]
+    fn test_irq_shared_line() {
+        let mut cpu = irq_program(&[0xEA]);
+        cpu.assert_irq(IrqSource::Dmc);
+        cpu.assert_irq(IrqSource::Mapper);
+        cpu.release_irq(IrqSource::Dmc);
+        assert!(cpu.irq_asserted());
+        cpu.release_irq(IrqSource::Mapper);
+        assert!(!cpu.irq_asserted());
+    }
+
+    #[test]
+    fn test_jam_halts() {
+        let mut cpu = execute(&[0x02, 0xE8], |_| {});
+        assert!(cpu.jammed);
+        for _ in 0..10 {
+            cpu.step_cycle();
+        }
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.x, 0);
     }
 
-    fn txs(&mut self, _operand: &Operand) -> Option<u8> {
-        self.s = self.x;
-        None
+    #[test]
+    fn test_decimal_adc() {
+        let setup = |cpu: &mut Cpu<FlatBus>| {
+            cpu.a = 0x58;
+            cpu.set_c(true);
+            cpu.set_d(true);
+        };
+        let cpu = execute_on(Variant::Nmos6502, &[0x69, 0x46], setup);
+        assert_eq!(cpu.a, 0x05);
+        assert!(cpu.get_c());
+        //The 2A03 ignores D
+        let cpu = execute(&[0x69, 0x46], setup);
+        assert_eq!(cpu.a, 0x9F);
+        assert!(!cpu.get_c());
+    }
+
+    #[test]
+    fn test_decimal_sbc() {
+        let cpu = execute_on(Variant::Nmos6502, &[0xE9, 0x12], |cpu| {
+            cpu.a = 0x46;
+            cpu.set_c(true);
+            cpu.set_d(true);
+        });
+        assert_eq!(cpu.a, 0x34);
+        assert!(cpu.get_c());
+
+        let cpu = execute_on(Variant::Nmos6502, &[0xE9, 0x21], |cpu| {
+            cpu.a = 0x12;
+            cpu.set_c(true);
+            cpu.set_d(true);
+        });
+        assert_eq!(cpu.a, 0x91);
+        assert!(!cpu.get_c());
+    }
+
+    #[test]
+    fn test_decimal_arr() {
+        let setup = |cpu: &mut Cpu<FlatBus>| {
+            cpu.a = 0xFF;
+            cpu.set_c(false);
+            cpu.set_d(true);
+        };
+        //$7F rotated, then both nibbles fixed up. N is the old carry
+        let cpu = execute_on(Variant::Nmos6502, &[0x6B, 0xFF], setup);
+        assert_eq!(cpu.a, 0xD5);
+        assert!(cpu.get_c());
+        assert!(!cpu.get_n());
+        assert!(!cpu.get_z());
+        assert!(!cpu.get_v());
+        //The 2A03 ignores D
+        let cpu = execute(&[0x6B, 0xFF], setup);
+        assert_eq!(cpu.a, 0x7F);
+        assert!(cpu.get_c());
+
+        //Z from the rotated value, V from bit 6 changing
+        let cpu = execute_on(Variant::Nmos6502, &[0x6B, 0x41], |cpu| {
+            cpu.a = 0x41;
+            cpu.set_c(false);
+            cpu.set_d(true);
+        });
+        assert_eq!(cpu.a, 0x20);
+        assert!(!cpu.get_z());
+        assert!(cpu.get_v());
+        assert!(!cpu.get_c());
+    }
+
+    #[test]
+    fn test_decimal_flags_by_variant() {
+        let setup = |cpu: &mut Cpu<FlatBus>| {
+            cpu.a = 0x99;
+            cpu.set_d(true);
+        };
+        //NMOS takes Z from the binary sum, the 65C02 from the result and a cycle longer
+        let nmos = execute_on(Variant::Nmos6502, &[0x69, 0x01], setup);
+        assert_eq!(nmos.a, 0x00);
+        assert!(!nmos.get_z());
+        assert_eq!(nmos.cycles, 2);
+        let cmos = execute_on(Variant::Wdc65C02, &[0x69, 0x01], setup);
+        assert_eq!(cmos.a, 0x00);
+        assert!(cmos.get_z());
+        assert!(cmos.get_c());
+        assert_eq!(cmos.cycles, 3);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        let setup = |cpu: &mut Cpu<FlatBus>| {
+            cpu.bus.data[0x10FF] = 0x34;
+            cpu.bus.data[0x1000] = 0x12;
+            cpu.bus.data[0x1100] = 0x56;
+        };
+        let nmos = execute(&[0x6C, 0xFF, 0x10], setup);
+        assert_eq!(nmos.pc, 0x1234);
+        assert_eq!(nmos.cycles, 5);
+        let cmos = execute_on(Variant::Wdc65C02, &[0x6C, 0xFF, 0x10], setup);
+        assert_eq!(cmos.pc, 0x5634);
+        assert_eq!(cmos.cycles, 6);
+    }
+
+    #[test]
+    fn test_cmos_cycles_match_table() {
+        for (opcode, operation) in CMOS_OPCODES.iter().enumerate() {
+            if matches!(
+                operation.addressing_mode,
+                AddressingMode::Relative | AddressingMode::ZeroPageRelative
+            ) || matches!(operation.instruction, Instruction::STP | Instruction::WAI)
+            {
+                continue;
+            }
+            let cpu = execute_on(Variant::Wdc65C02, &[opcode as u8, 0x10, 0x02], |_| {});
+            assert_eq!(
+                cpu.cycles, operation.base_cycle_count as u64,
+                "opcode {:#04X}",
+                opcode
+            );
+            assert_eq!(
+                cpu.bus.log.len() as u64,
+                cpu.cycles,
+                "opcode {:#04X}",
+                opcode
+            );
+
+            let cpu = execute_on(Variant::Wdc65C02, &[opcode as u8, 0x10, 0x02], cross_page);
+            assert_eq!(
+                cpu.cycles,
+                operation.base_cycle_count as u64 + operation.page_cross_penalty as u64,
+                "opcode {:#04X} crossing a page",
+                opcode
+            );
+        }
     }
 
-    fn tya(&mut self, _operand: &Operand) -> Option<u8> {
-        self.a = self.y;
-        self.set_standard_flags(&self.a.clone());
-        None
+    #[test]
+    fn test_cmos_read_modify_write_double_read() {
+        use BusCycle::*;
+        let cpu = execute_on(Variant::Wdc65C02, &[0xE6, 0x10], |cpu| {
+            cpu.bus.data[0x10] = 0x41;
+        });
+        assert_eq!(
+            cpu.bus.log[2..],
+            [Read(0x10, 0x41), Read(0x10, 0x41), Write(0x10, 0x42)]
+        );
+    }
+
+    #[test]
+    fn test_cmos_bit_instructions() {
+        //SMB3, RMB0, TSB and TRB
+        let cpu = execute_on(Variant::Wdc65C02, &[0xB7, 0x10], |cpu| {
+            cpu.bus.data[0x10] = 0x01;
+        });
+        assert_eq!(cpu.bus.data[0x10], 0x09);
+        let cpu = execute_on(Variant::Wdc65C02, &[0x07, 0x10], |cpu| {
+            cpu.bus.data[0x10] = 0x03;
+        });
+        assert_eq!(cpu.bus.data[0x10], 0x02);
+        let cpu = execute_on(Variant::Wdc65C02, &[0x04, 0x10], |cpu| {
+            cpu.a = 0xF0;
+            cpu.bus.data[0x10] = 0x0F;
+        });
+        assert_eq!(cpu.bus.data[0x10], 0xFF);
+        assert!(cpu.get_z());
+        let cpu = execute_on(Variant::Wdc65C02, &[0x14, 0x10], |cpu| {
+            cpu.a = 0x0C;
+            cpu.bus.data[0x10] = 0x0F;
+        });
+        assert_eq!(cpu.bus.data[0x10], 0x03);
+        assert!(!cpu.get_z());
+    }
+
+    #[test]
+    fn test_cmos_bit_branch() {
+        //BBS2 $10 taken, BBR2 $10 not taken
+        let cpu = execute_on(Variant::Wdc65C02, &[0xAF, 0x10, 0x05], |cpu| {
+            cpu.bus.data[0x10] = 0x04;
+        });
+        assert_eq!(cpu.pc, 0x8008);
+        assert_eq!(cpu.cycles, 6);
+        let cpu = execute_on(Variant::Wdc65C02, &[0x2F, 0x10, 0x05], |cpu| {
+            cpu.bus.data[0x10] = 0x04;
+        });
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.cycles, 5);
+    }
+
+    #[test]
+    fn test_cmos_addressing() {
+        //LDA ($10)
+        let cpu = execute_on(Variant::Wdc65C02, &[0xB2, 0x10], |cpu| {
+            cpu.bus.data[0x10] = 0x34;
+            cpu.bus.data[0x11] = 0x12;
+            cpu.bus.data[0x1234] = 0x99;
+        });
+        assert_eq!(cpu.a, 0x99);
+        //JMP ($1000,X)
+        let cpu = execute_on(Variant::Wdc65C02, &[0x7C, 0x00, 0x10], |cpu| {
+            cpu.x = 0x02;
+            cpu.bus.data[0x1002] = 0x78;
+            cpu.bus.data[0x1003] = 0x56;
+        });
+        assert_eq!(cpu.pc, 0x5678);
+        //STZ $0200,X
+        let cpu = execute_on(Variant::Wdc65C02, &[0x9E, 0x00, 0x02], |cpu| {
+            cpu.x = 0x01;
+            cpu.bus.data[0x0201] = 0x55;
+        });
+        assert_eq!(cpu.bus.data[0x0201], 0x00);
+    }
+
+    #[test]
+    fn test_cmos_brk_clears_decimal() {
+        let mut cpu = irq_program(&[0x00]);
+        cpu.variant = Variant::Wdc65C02;
+        cpu.set_d(true);
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0x9000);
+        assert!(!cpu.get_d());
+    }
+
+    #[test]
+    fn test_wai_wakes_on_masked_irq() {
+        //WAI, INX
+        let mut cpu = irq_program(&[0xCB, 0xE8]);
+        cpu.variant = Variant::Wdc65C02;
+        run_instruction(&mut cpu);
+        for _ in 0..10 {
+            cpu.step_cycle();
+        }
+        assert_eq!(cpu.x, 0);
+        cpu.assert_irq(IrqSource::External);
+        run_instruction(&mut cpu);
+        //I is set, so execution carries on after WAI
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.pc, 0x8002);
+    }
+
+    //Cycles until the cpu gets to run an instruction again
+    fn stall(cpu: &mut Cpu<FlatBus>) -> u64 {
+        let start = cpu.cycles;
+        while !cpu.instruction_finished() {
+            cpu.step_cycle();
+        }
+        cpu.cycles - start
     }
-    fn set_standard_flags(&mut self, val: &u8) {
-        self.set_z(*val == 0);
-        self.set_n(val.get_bit(7));
+
+    //Runs STA $4014 with A = $02, and stops where the DMA takes over
+    fn oam_dma(start_cycle: u64) -> Cpu<FlatBus> {
+        let mut data = vec![0; 0x10000];
+        data[0x8000..0x8003].copy_from_slice(&[0x8D, 0x14, 0x40]);
+        for i in 0..0x100 {
+            data[0x0200 + i] = i as u8;
+        }
+        let mut cpu = Cpu::new(FlatBus::new(data));
+        cpu.set_pc(0x8000);
+        cpu.a = 0x02;
+        cpu.cycles = start_cycle;
+        for _ in 0..4 {
+            cpu.step_cycle();
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_oam_dma_stall() {
+        use BusCycle::*;
+        let mut cpu = oam_dma(1);
+        assert!(cpu.dma_active());
+        assert_eq!(stall(&mut cpu), 513);
+        assert_eq!(cpu.bus.log[4], Read(0x8003, 0x00));
+        assert_eq!(cpu.bus.log[5..7], [Read(0x0200, 0x00), Write(0x2004, 0x00)]);
+        assert_eq!(cpu.bus.log.last(), Some(&Write(0x2004, 0xFF)));
+        assert_eq!(cpu.bus.log.len(), 4 + 513);
+        assert_eq!(cpu.pc, 0x8003);
+
+        //Halting on a get cycle costs another to line back up
+        let mut cpu = oam_dma(0);
+        assert_eq!(stall(&mut cpu), 514);
+        assert_eq!(cpu.bus.log[4..6], [Read(0x8003, 0x00), Read(0x8003, 0x00)]);
+        assert_eq!(cpu.bus.log[6], Read(0x0200, 0x00));
+    }
+
+    #[test]
+    fn test_dmc_dma() {
+        for (start_cycle, expected) in [(0, 3), (1, 4)] {
+            let mut cpu = execute(&[0xEA], |cpu| cpu.bus.data[0xC000] = 0x5A);
+            cpu.cycles = start_cycle;
+            cpu.request_dmc_dma(0xC000);
+            assert!(!cpu.instruction_finished());
+            assert_eq!(stall(&mut cpu), expected);
+            assert_eq!(cpu.bus.sample, Some(0x5A));
+        }
     }
-}
 
-fn unpack_address(operand: &Operand) -> u16 {
-    match operand {
-        Operand::Address { location } => *location,
-        _ => panic!(),
+    #[test]
+    fn test_dmc_dma_during_oam_dma() {
+        let mut cpu = oam_dma(1);
+        cpu.bus.data[0xC000] = 0x5A;
+        for _ in 0..100 {
+            cpu.step_cycle();
+        }
+        cpu.request_dmc_dma(0xC000);
+        //The OAM DMA covers the DMC's halt and dummy cycles, it steals one to read and
+        //one to get OAM DMA back onto a get
+        assert_eq!(100 + stall(&mut cpu), 515);
+        assert_eq!(cpu.bus.sample, Some(0x5A));
+        assert!(cpu.bus.log.contains(&BusCycle::Read(0xC000, 0x5A)));
+        assert_eq!(cpu.bus.log.last(), Some(&BusCycle::Write(0x2004, 0xFF)));
     }
 }
@@ -0,0 +1,789 @@
+//Breakpoints, watchpoints and stepping. The emulator asks the debugger before every
+//instruction whether to stop, and hands it the bus accesses of every cycle while
+//read or write watchpoints are set
+
+use crate::call_stack::CallStack;
+use crate::cdl::CodeDataLog;
+use crate::cpu::Cpu;
+use crate::expression::{Context, Expression};
+use crate::history::{self, History};
+use crate::memory::{AddressSpace, Bus, BusAccess, ReadKind};
+use crate::profiler::Profiler;
+use crate::symbols::Symbols;
+use crate::trace::Tracer;
+
+pub use crate::call_stack::{CallFrame, FrameKind};
+pub use crate::history::{CrashReport, HistoryEntry};
+
+const BRK: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Execute,
+}
+
+//Stops before the instruction at pc, if the condition holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub pc: u16,
+    pub condition: Option<Expression>,
+}
+
+//Watches the cpu addresses start to end, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Watchpoint {
+    fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+//Why run_frame returned. Everything but FrameComplete leaves the cpu between two
+//instructions, with pc on the one that runs next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    FrameComplete,
+    Breakpoint(u16),
+    Watchpoint {
+        kind: WatchKind,
+        address: u16,
+        value: u8,
+    },
+    Opcode {
+        pc: u16,
+        opcode: u8,
+    },
+    Step(u16),
+    Condition(u16),
+    Jammed(u16), //The cpu hit a JAM (or STP) and won't run again until a reset
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepMode {
+    Into,
+    Over,
+    Out,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    mode: StepMode,
+    from: Option<usize>, //Call depth of the instruction being stepped, once it has been reached
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    opcodes: Vec<u8>,
+    conditions: Vec<Expression>, //Checked before every instruction
+    step: Option<Step>,
+    call_stack: CallStack,
+    history: History,
+    tracer: Option<Tracer>,
+    code_data_log: Option<CodeDataLog>,
+    profiler: Option<Profiler>,
+    symbols: Symbols,
+    crash_reporting: bool,
+    jam_reported: bool,
+    resume_cycle: Option<u64>, //Cycle of the last stop on the instruction at pc, so resuming doesn't stop again
+    hit: Option<StopReason>,   //Watchpoint hit, reported once the instruction finishes
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        if !self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.pc == pc)
+        {
+            self.breakpoints.push(Breakpoint {
+                pc,
+                condition: None,
+            });
+        }
+    }
+
+    //Replaces any other breakpoint at pc
+    pub fn add_breakpoint_if(&mut self, pc: u16, condition: Expression) {
+        self.remove_breakpoint(pc);
+        self.breakpoints.push(Breakpoint {
+            pc,
+            condition: Some(condition),
+        });
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.retain(|breakpoint| breakpoint.pc != pc);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    //Stops before any instruction where the condition holds
+    pub fn add_condition(&mut self, condition: Expression) {
+        if !self.conditions.contains(&condition) {
+            self.conditions.push(condition);
+        }
+    }
+
+    pub fn remove_condition(&mut self, source: &str) {
+        self.conditions
+            .retain(|condition| condition.source() != source);
+    }
+
+    pub fn conditions(&self) -> &[Expression] {
+        &self.conditions
+    }
+
+    pub fn add_watchpoint(&mut self, kind: WatchKind, start: u16, end: u16) {
+        let watchpoint = Watchpoint { kind, start, end };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, kind: WatchKind, start: u16, end: u16) {
+        let watchpoint = Watchpoint { kind, start, end };
+        self.watchpoints.retain(|&other| other != watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn break_on_opcode(&mut self, opcode: u8) {
+        if !self.opcodes.contains(&opcode) {
+            self.opcodes.push(opcode);
+        }
+    }
+
+    pub fn remove_opcode_break(&mut self, opcode: u8) {
+        self.opcodes.retain(|&other| other != opcode);
+    }
+
+    pub fn set_break_on_brk(&mut self, enabled: bool) {
+        if enabled {
+            self.break_on_opcode(BRK);
+        } else {
+            self.remove_opcode_break(BRK);
+        }
+    }
+
+    //Removes every breakpoint and watchpoint and cancels stepping
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.opcodes.clear();
+        self.conditions.clear();
+        self.step = None;
+        self.hit = None;
+    }
+
+    //The step functions take effect on the next run_frame
+    pub fn step_into(&mut self) {
+        self.start_step(StepMode::Into);
+    }
+
+    //Runs a whole subroutine when the next instruction is a JSR
+    pub fn step_over(&mut self) {
+        self.start_step(StepMode::Over);
+    }
+
+    //Runs until the current subroutine or interrupt handler returns
+    pub fn step_out(&mut self) {
+        self.start_step(StepMode::Out);
+    }
+
+    fn start_step(&mut self, mode: StepMode) {
+        self.step = Some(Step { mode, from: None });
+    }
+
+    //Forgets the state of the running program, breakpoints stay
+    pub(crate) fn reset(&mut self) {
+        self.step = None;
+        self.call_stack.clear();
+        self.history.clear();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.reset();
+        }
+        self.jam_reported = false;
+        self.resume_cycle = None;
+        self.hit = None;
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.depth()
+    }
+
+    //Outermost first
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.call_stack.frames()
+    }
+
+    //Keeps the call stack and history for crash reports even when nothing else is
+    //set. Off by default, it costs a few bus reads per instruction
+    pub fn set_crash_reporting(&mut self, enabled: bool) {
+        self.crash_reporting = enabled;
+    }
+
+    //How many instructions are kept for crash reports
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history.set_length(length);
+    }
+
+    //Oldest first
+    pub fn history(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.history.entries()
+    }
+
+    //Replaces any trace already running
+    pub fn start_trace(&mut self, tracer: Tracer) -> Option<Tracer> {
+        self.tracer.replace(tracer)
+    }
+
+    pub fn stop_trace(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    //Replaces any log already running
+    pub fn start_code_data_log(&mut self, log: CodeDataLog) -> Option<CodeDataLog> {
+        self.code_data_log.replace(log)
+    }
+
+    pub fn stop_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.code_data_log.take()
+    }
+
+    //CHR flags are kept by the cartridge, see Emulator::code_data_log
+    pub fn code_data_log(&mut self) -> Option<&mut CodeDataLog> {
+        self.code_data_log.as_mut()
+    }
+
+    //Labels and source lines shown in traces and profiles
+    pub fn symbols(&mut self) -> &mut Symbols {
+        &mut self.symbols
+    }
+
+    pub(crate) fn symbols_ref(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    //Replaces any profiler already running
+    pub fn start_profiler(&mut self, profiler: Profiler) -> Option<Profiler> {
+        self.profiler.replace(profiler)
+    }
+
+    pub fn stop_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub(crate) fn crash_report(&self, cpu: &Cpu<Bus>) -> CrashReport {
+        CrashReport {
+            jammed: cpu.jammed(),
+            registers: cpu.registers(),
+            cycle: cpu.cycles(),
+            frame: cpu.bus.ppu.frame(),
+            scanline: cpu.bus.ppu.scanline(),
+            dot: cpu.bus.ppu.dot(),
+            call_stack: self.call_stack.frames().to_vec(),
+            history: self
+                .history
+                .entries()
+                .map(|entry| history::history_line(entry, cpu.variant()))
+                .collect(),
+        }
+    }
+
+    //Bus accesses only have to be recorded while something is watching them
+    pub(crate) fn watches_bus(&self) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.kind != WatchKind::Execute)
+    }
+
+    //Whether anything needs to look at instructions as they run
+    fn active(&self) -> bool {
+        self.crash_reporting
+            || !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || !self.opcodes.is_empty()
+            || !self.conditions.is_empty()
+            || self.step.is_some()
+            || self.hit.is_some()
+            || self.tracer.is_some()
+            || self.code_data_log.is_some()
+            || self.profiler.is_some()
+    }
+
+    //Called between instructions, before the cpu starts the next one
+    pub(crate) fn before_instruction(&mut self, cpu: &Cpu<Bus>) -> Option<StopReason> {
+        let registers = cpu.registers();
+        let pc = registers.pc;
+
+        if cpu.jammed() {
+            if self.jam_reported {
+                return None;
+            }
+            self.jam_reported = true;
+            return Some(StopReason::Jammed(pc));
+        }
+
+        if cpu.halted() || !self.active() {
+            return None;
+        }
+
+        //A watchpoint hit stops before any interrupt the instruction let in
+        if let Some(hit) = self.hit.take() {
+            self.step = None;
+            self.resume_cycle = None;
+            return Some(hit);
+        }
+
+        let nmi_vector =
+            u16::from_le_bytes([cpu.bus.debug_peek(0xFFFA), cpu.bus.debug_peek(0xFFFB)]);
+        if cpu.interrupt_pending() {
+            self.call_stack.interrupt(pc, registers.s, nmi_vector);
+            if let Some(log) = &mut self.code_data_log {
+                log.interrupt();
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.interrupt(cpu.cycles(), cpu.bus.ppu.frame());
+            }
+            return None;
+        }
+
+        let opcode = cpu.bus.debug_peek(pc);
+        let resumed = self.resume_cycle.take() == Some(cpu.cycles());
+
+        let checked = if resumed {
+            None
+        } else {
+            self.check_instruction(cpu, pc, opcode)
+        };
+        if checked.is_some() {
+            self.resume_cycle = Some(cpu.cycles());
+        }
+        let reason = checked.or_else(|| self.check_step(pc));
+
+        match reason {
+            Some(_) => self.step = None,
+            None => {
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.instruction(cpu, &self.symbols);
+                }
+                if let Some(log) = &mut self.code_data_log {
+                    log.instruction(cpu);
+                }
+                if let Some(profiler) = &mut self.profiler {
+                    self.call_stack.enter(pc, nmi_vector);
+                    let symbols = &self.symbols;
+                    profiler.instruction(
+                        pc,
+                        cpu.cycles(),
+                        cpu.bus.ppu.frame(),
+                        self.call_stack.frames(),
+                        &|address| symbols.label_at(&cpu.bus.cartridge, address),
+                    );
+                }
+                self.history.record(HistoryEntry {
+                    registers,
+                    bytes: [
+                        opcode,
+                        cpu.bus.debug_peek(pc.wrapping_add(1)),
+                        cpu.bus.debug_peek(pc.wrapping_add(2)),
+                    ],
+                    cycle: cpu.cycles(),
+                    scanline: cpu.bus.ppu.scanline(),
+                    dot: cpu.bus.ppu.dot(),
+                });
+                self.call_stack
+                    .instruction(pc, opcode, registers.s, nmi_vector);
+            }
+        }
+
+        reason
+    }
+
+    //Called after every cpu cycle with the accesses it made
+    pub(crate) fn after_cycle(&mut self, accesses: &[BusAccess]) {
+        if self.hit.is_some() {
+            return;
+        }
+
+        for access in accesses {
+            let (kind, address, value) = match *access {
+                BusAccess::Read(address, value, ReadKind::Data) => {
+                    (WatchKind::Read, address, value)
+                }
+                //Fetches, dummy reads and DMA aren't the program reading memory
+                BusAccess::Read(..) => continue,
+                BusAccess::Write(address, value) => (WatchKind::Write, address, value),
+            };
+
+            let watched = self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.kind == kind && watchpoint.contains(address));
+            if watched {
+                self.hit = Some(StopReason::Watchpoint {
+                    kind,
+                    address,
+                    value,
+                });
+                return;
+            }
+        }
+    }
+
+    fn check_instruction(&self, cpu: &Cpu<Bus>, pc: u16, opcode: u8) -> Option<StopReason> {
+        let context = || Context {
+            registers: cpu.registers(),
+            cycle: cpu.cycles(),
+            scanline: cpu.bus.ppu.scanline(),
+            dot: cpu.bus.ppu.dot(),
+            frame: cpu.bus.ppu.frame(),
+            memory: &cpu.bus,
+        };
+
+        let stopped = self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| match &breakpoint.condition {
+                _ if breakpoint.pc != pc => false,
+                Some(condition) => condition.holds(&context()),
+                None => true,
+            });
+        if stopped {
+            return Some(StopReason::Breakpoint(pc));
+        }
+
+        let executed = self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.kind == WatchKind::Execute && watchpoint.contains(pc));
+        if executed {
+            return Some(StopReason::Watchpoint {
+                kind: WatchKind::Execute,
+                address: pc,
+                value: opcode,
+            });
+        }
+
+        if self.opcodes.contains(&opcode) {
+            return Some(StopReason::Opcode { pc, opcode });
+        }
+
+        if !self.conditions.is_empty() {
+            let context = context();
+            if self
+                .conditions
+                .iter()
+                .any(|condition| condition.holds(&context))
+            {
+                return Some(StopReason::Condition(pc));
+            }
+        }
+
+        None
+    }
+
+    fn check_step(&mut self, pc: u16) -> Option<StopReason> {
+        let step = self.step?;
+        let from = match step.from {
+            Some(from) => from,
+            None => {
+                //This is the instruction being stepped, it gets to run
+                self.step = Some(Step {
+                    from: Some(self.call_stack.depth()),
+                    ..step
+                });
+                return None;
+            }
+        };
+
+        let done = match step.mode {
+            StepMode::Into => true,
+            StepMode::Over => self.call_stack.depth() <= from,
+            StepMode::Out => self.call_stack.depth() < from,
+        };
+        if done {
+            Some(StopReason::Step(pc))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+
+    //C000 LDX #$00
+    //C002 JSR $C010
+    //C005 STA $0300
+    //C008 JMP $C002
+    //C010 LDA $0200
+    //C013 INX
+    //C014 RTS
+    fn emulator() -> Emulator {
+        let mut program = vec![0xEA; 0x15];
+        program[..0x0B].copy_from_slice(&[
+            0xA2, 0x00, 0x20, 0x10, 0xC0, 0x8D, 0x00, 0x03, 0x4C, 0x02, 0xC0,
+        ]);
+        program[0x10..].copy_from_slice(&[0xAD, 0x00, 0x02, 0xE8, 0x60]);
+        Emulator::new(test_rom(&program))
+    }
+
+    //Runs until the instruction at pc is next
+    fn run_to(emu: &mut Emulator, pc: u16) {
+        emu.debugger().add_breakpoint(pc);
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(pc));
+        emu.debugger().remove_breakpoint(pc);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_and_resumes() {
+        let mut emu = emulator();
+        emu.debugger().add_breakpoint(0xC013);
+
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(0xC013));
+        assert_eq!(emu.cpu.registers().pc, 0xC013);
+        assert_eq!(emu.cpu.registers().x, 0);
+
+        //Resuming runs the INX instead of stopping on it again
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(0xC013));
+        assert_eq!(emu.cpu.registers().x, 1);
+    }
+
+    #[test]
+    fn test_breakpoint_after_watchpoint_stop() {
+        let mut emu = emulator();
+        emu.debugger()
+            .add_watchpoint(WatchKind::Read, 0x0200, 0x0200);
+        emu.debugger().add_breakpoint(0xC013);
+
+        assert!(matches!(emu.run_frame(), StopReason::Watchpoint { .. }));
+        assert_eq!(emu.cpu.registers().pc, 0xC013);
+        //The watchpoint stopped on this pc, the breakpoint still gets its turn
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(0xC013));
+        assert_eq!(emu.cpu.registers().x, 0);
+    }
+
+    #[test]
+    fn test_watchpoint_before_interrupt() {
+        let mut emu = emulator();
+        run_to(&mut emu, 0xC010);
+        emu.debugger()
+            .add_watchpoint(WatchKind::Read, 0x0200, 0x0200);
+        emu.cpu.fire_nmi();
+
+        assert!(matches!(emu.run_frame(), StopReason::Watchpoint { .. }));
+        assert_eq!(emu.cpu.registers().pc, 0xC013);
+        assert!(emu.cpu.interrupt_pending());
+    }
+
+    #[test]
+    fn test_run_frame_without_breakpoints() {
+        let mut emu = emulator();
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut emu = emulator();
+        emu.debugger()
+            .add_watchpoint(WatchKind::Write, 0x0300, 0x0300);
+        emu.debugger()
+            .add_watchpoint(WatchKind::Read, 0x0200, 0x02FF);
+
+        //Stops once the accessing instruction has finished
+        assert_eq!(
+            emu.run_frame(),
+            StopReason::Watchpoint {
+                kind: WatchKind::Read,
+                address: 0x0200,
+                value: 0
+            }
+        );
+        assert_eq!(emu.cpu.registers().pc, 0xC013);
+
+        assert_eq!(
+            emu.run_frame(),
+            StopReason::Watchpoint {
+                kind: WatchKind::Write,
+                address: 0x0300,
+                value: 0
+            }
+        );
+        assert_eq!(emu.cpu.registers().pc, 0xC008);
+
+        emu.debugger().clear();
+        emu.debugger()
+            .add_watchpoint(WatchKind::Execute, 0xC010, 0xC014);
+        assert_eq!(
+            emu.run_frame(),
+            StopReason::Watchpoint {
+                kind: WatchKind::Execute,
+                address: 0xC010,
+                value: 0xAD
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_watchpoints_ignore_fetches() {
+        let mut emu = emulator();
+        emu.debugger()
+            .add_watchpoint(WatchKind::Read, 0xC000, 0xFFFF);
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_opcode_breaks() {
+        let mut emu = emulator();
+        emu.debugger().break_on_opcode(0xE8);
+        assert_eq!(
+            emu.run_frame(),
+            StopReason::Opcode {
+                pc: 0xC013,
+                opcode: 0xE8
+            }
+        );
+
+        emu.debugger().remove_opcode_break(0xE8);
+        emu.debugger().set_break_on_brk(true);
+        assert!(emu.debugger().opcodes.contains(&0x00));
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut emu = emulator();
+        emu.debugger()
+            .add_breakpoint_if(0xC013, "X == 3".parse().unwrap());
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(0xC013));
+        assert_eq!(emu.cpu.registers().x, 3);
+    }
+
+    #[test]
+    fn test_condition_anywhere() {
+        let mut emu = emulator();
+        emu.debugger()
+            .add_condition("x == 2 && pc == $C005".parse().unwrap());
+        assert_eq!(emu.run_frame(), StopReason::Condition(0xC005));
+        assert_eq!(emu.cpu.registers().x, 2);
+
+        emu.debugger().remove_condition("x == 2 && pc == $C005");
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut emu = emulator();
+        run_to(&mut emu, 0xC013);
+        let frames = emu.debugger().call_stack().to_vec();
+        assert_eq!(
+            frames,
+            vec![CallFrame {
+                kind: FrameKind::Subroutine,
+                caller: 0xC002,
+                target: 0xC010,
+                stack_pointer: 0xFD
+            }]
+        );
+
+        run_to(&mut emu, 0xC005);
+        assert!(emu.debugger().call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_jam_report() {
+        //JSR to a JAM
+        let mut program = vec![0x20, 0x10, 0xC0];
+        program.resize(0x10, 0xEA);
+        program.extend(&[0xA9, 0x55, 0x02]);
+        let mut emu = Emulator::new(test_rom(&program));
+        emu.debugger().set_crash_reporting(true);
+
+        assert_eq!(emu.run_frame(), StopReason::Jammed(0xC012));
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+
+        let report = emu.crash_report();
+        assert!(report.jammed);
+        assert_eq!(report.registers.a, 0x55);
+        assert_eq!(report.call_stack.len(), 1);
+        assert_eq!(report.history.len(), 3);
+        assert!(report.history[2].starts_with("C012  02        JAM"));
+
+        let text = report.to_string();
+        assert!(text.starts_with("CPU jammed at $C012\n"));
+        assert!(text.contains("  JSR $C010 from $C000 (SP:FD)\n"));
+        assert!(text.contains("  C010  A9 55     LDA #$55"));
+
+        emu.reset();
+        assert!(emu.debugger().call_stack().is_empty());
+        assert_eq!(emu.debugger().history().count(), 0);
+    }
+
+    #[test]
+    fn test_no_bookkeeping_when_idle() {
+        let mut emu = emulator();
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+        assert_eq!(emu.debugger().history().count(), 0);
+
+        emu.debugger().set_crash_reporting(true);
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+        assert_eq!(emu.debugger().history().count(), 64);
+    }
+
+    #[test]
+    fn test_step_into_and_out() {
+        let mut emu = emulator();
+        run_to(&mut emu, 0xC002);
+
+        assert_eq!(emu.step_into(), StopReason::Step(0xC010));
+        assert_eq!(emu.debugger().call_depth(), 1);
+        assert_eq!(emu.step_into(), StopReason::Step(0xC013));
+        assert_eq!(emu.step_out(), StopReason::Step(0xC005));
+        assert_eq!(emu.debugger().call_depth(), 0);
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut emu = emulator();
+        run_to(&mut emu, 0xC002);
+
+        assert_eq!(emu.step_over(), StopReason::Step(0xC005));
+        assert_eq!(emu.cpu.registers().x, 1);
+        assert_eq!(emu.step_over(), StopReason::Step(0xC008));
+    }
+
+    #[test]
+    fn test_breakpoint_interrupts_step() {
+        let mut emu = emulator();
+        run_to(&mut emu, 0xC002);
+
+        emu.debugger().add_breakpoint(0xC013);
+        assert_eq!(emu.step_over(), StopReason::Breakpoint(0xC013));
+        emu.debugger().remove_breakpoint(0xC013);
+        assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+    }
+}
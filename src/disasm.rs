@@ -0,0 +1,274 @@
+//Instruction decoding for debuggers and trace logs. Memory is only read through
+//debug_peek, so disassembling over registers like $2002 doesn't disturb them
+
+use crate::cpu::Variant;
+use crate::instruction::{Instruction, Operation, CMOS_OPCODES, OPCODES};
+use crate::memory::AddressSpace;
+
+pub use crate::instruction::AddressingMode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub opcode: u8,
+    pub mnemonic: String,
+    pub addressing_mode: AddressingMode,
+    pub operands: Vec<u8>,
+    pub length: u16,
+    pub text: String,          //Mnemonic and operand, e.g. "LDA ($10),Y"
+    pub label: Option<String>, //Symbol at address, filled in by Emulator::disassemble
+}
+
+impl Disassembly {
+    //The text after the mnemonic, empty for implied instructions
+    pub fn operand(&self) -> &str {
+        self.text[self.mnemonic.len()..].trim_start()
+    }
+
+    //The address the operand names, a branch's destination for branches
+    pub fn target(&self) -> Option<u16> {
+        let operands = &self.operands;
+        match self.addressing_mode {
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY
+            | AddressingMode::ZeroPageIndirect => Some(operands[0] as u16),
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect
+            | AddressingMode::AbsoluteIndexedIndirect => {
+                Some(u16::from_le_bytes([operands[0], operands[1]]))
+            }
+            AddressingMode::Relative => Some(branch_target(self.address, 2, operands[0])),
+            AddressingMode::ZeroPageRelative => Some(branch_target(self.address, 3, operands[1])),
+            _ => None,
+        }
+    }
+
+    //Writes name in place of the target address in text
+    pub fn name_target(&mut self, name: &str) {
+        self.text = self.with_target_name(&self.text, name);
+    }
+
+    //Replaces the target address as it appears in text, which starts with the operand
+    pub(crate) fn with_target_name(&self, text: &str, name: &str) -> String {
+        let written = match (self.addressing_mode, self.target()) {
+            (AddressingMode::ZeroPageRelative, Some(target))
+            | (AddressingMode::Relative, Some(target)) => format!("${:04X}", target),
+            (_, Some(target)) if self.operands.len() == 1 => format!("${:02X}", target),
+            (_, Some(target)) => format!("${:04X}", target),
+            (_, None) => return text.to_string(),
+        };
+        text.replacen(&written, name, 1)
+    }
+}
+
+//Decodes the instruction at address
+pub fn decode<T: AddressSpace>(bus: &T, address: u16, variant: Variant) -> Disassembly {
+    let opcode = bus.debug_peek(address);
+    let operation = lookup(opcode, variant);
+    let length = operation_length(operation.addressing_mode);
+    let operands: Vec<u8> = (1..length)
+        .map(|i| bus.debug_peek(address.wrapping_add(i)))
+        .collect();
+
+    let mnemonic = match operation.instruction {
+        //The bit number is part of the mnemonic
+        Instruction::RMB | Instruction::SMB | Instruction::BBR | Instruction::BBS => {
+            format!("{:?}{}", operation.instruction, (opcode >> 4) & 0x07)
+        }
+        instruction => format!("{:?}", instruction),
+    };
+
+    let operand = operand_text(operation.addressing_mode, address, &operands);
+    let text = format!("{} {}", mnemonic, operand).trim_end().to_string();
+
+    Disassembly {
+        address,
+        opcode,
+        mnemonic,
+        addressing_mode: operation.addressing_mode,
+        operands,
+        length,
+        text,
+        label: None,
+    }
+}
+
+//Decodes instructions one after another from start, up to and including the one at end
+pub fn disassemble<T: AddressSpace>(
+    bus: &T,
+    start: u16,
+    end: u16,
+    variant: Variant,
+) -> Vec<Disassembly> {
+    let mut instructions = Vec::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let instruction = decode(bus, address as u16, variant);
+        address += instruction.length as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+pub(crate) fn lookup(opcode: u8, variant: Variant) -> &'static Operation {
+    let table = match variant {
+        Variant::Wdc65C02 => &CMOS_OPCODES,
+        _ => &OPCODES,
+    };
+    &table[opcode as usize]
+}
+
+pub(crate) fn operation_length(mode: AddressingMode) -> u16 {
+    match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => 1,
+        AddressingMode::Absolute
+        | AddressingMode::AbsoluteX
+        | AddressingMode::AbsoluteY
+        | AddressingMode::Indirect
+        | AddressingMode::AbsoluteIndexedIndirect
+        | AddressingMode::ZeroPageRelative => 3,
+        _ => 2,
+    }
+}
+
+fn branch_target(address: u16, length: u16, offset: u8) -> u16 {
+    address
+        .wrapping_add(length)
+        .wrapping_add(offset as i8 as u16)
+}
+
+fn operand_text(mode: AddressingMode, address: u16, operands: &[u8]) -> String {
+    let word = || u16::from_le_bytes([operands[0], operands[1]]);
+
+    match mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => String::from("A"),
+        AddressingMode::Immediate => format!("#${:02X}", operands[0]),
+        AddressingMode::ZeroPage => format!("${:02X}", operands[0]),
+        AddressingMode::ZeroPageX => format!("${:02X},X", operands[0]),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", operands[0]),
+        AddressingMode::Absolute => format!("${:04X}", word()),
+        AddressingMode::AbsoluteX => format!("${:04X},X", word()),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", word()),
+        AddressingMode::Indirect => format!("(${:04X})", word()),
+        AddressingMode::IndirectX => format!("(${:02X},X)", operands[0]),
+        AddressingMode::IndirectY => format!("(${:02X}),Y", operands[0]),
+        AddressingMode::Relative => format!("${:04X}", branch_target(address, 2, operands[0])),
+        AddressingMode::ZeroPageIndirect => format!("(${:02X})", operands[0]),
+        AddressingMode::AbsoluteIndexedIndirect => format!("(${:04X},X)", word()),
+        AddressingMode::ZeroPageRelative => format!(
+            "${:02X},${:04X}",
+            operands[0],
+            branch_target(address, 3, operands[1])
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::FlatRam;
+
+    //Fails the test if anything reads with side effects
+    struct DebugOnly(FlatRam);
+
+    impl AddressSpace for DebugOnly {
+        fn peek(&mut self, ptr: u16) -> u8 {
+            panic!("peek({:#06X}) while disassembling", ptr)
+        }
+
+        fn poke(&mut self, ptr: u16, _byte: u8) {
+            panic!("poke({:#06X}) while disassembling", ptr)
+        }
+
+        fn debug_peek(&self, ptr: u16) -> u8 {
+            self.0.debug_peek(ptr)
+        }
+    }
+
+    fn memory(address: u16, program: &[u8]) -> DebugOnly {
+        let mut ram = FlatRam::new();
+        ram.load(address, program);
+        DebugOnly(ram)
+    }
+
+    #[test]
+    fn test_decode() {
+        let bus = memory(0xC000, &[0xB1, 0x10]);
+        let decoded = decode(&bus, 0xC000, Variant::Ricoh2A03);
+        assert_eq!(decoded.mnemonic, "LDA");
+        assert_eq!(decoded.addressing_mode, AddressingMode::IndirectY);
+        assert_eq!(decoded.operands, vec![0x10]);
+        assert_eq!(decoded.length, 2);
+        assert_eq!(decoded.text, "LDA ($10),Y");
+        assert_eq!(decoded.operand(), "($10),Y");
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        //SEI, LDA $2002, BPL back to the LDA, JMP ($FFFC)
+        let bus = memory(
+            0x8000,
+            &[0x78, 0xAD, 0x02, 0x20, 0x10, 0xFB, 0x6C, 0xFC, 0xFF],
+        );
+        let text: Vec<String> = disassemble(&bus, 0x8000, 0x8006, Variant::Ricoh2A03)
+            .into_iter()
+            .map(|d| format!("{:04X} {}", d.address, d.text))
+            .collect();
+        assert_eq!(
+            text,
+            vec![
+                "8000 SEI",
+                "8001 LDA $2002",
+                "8004 BPL $8001",
+                "8006 JMP ($FFFC)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_name_target() {
+        //BNE back to itself, LDA ($10),Y, BBR0 $10 forward
+        let bus = memory(0xC000, &[0xD0, 0xFE, 0xB1, 0x10, 0x0F, 0x10, 0x01]);
+        let mut branch = decode(&bus, 0xC000, Variant::Ricoh2A03);
+        assert_eq!(branch.target(), Some(0xC000));
+        branch.name_target("loop");
+        assert_eq!(branch.text, "BNE loop");
+
+        let mut indirect = decode(&bus, 0xC002, Variant::Ricoh2A03);
+        indirect.name_target("pointer");
+        assert_eq!(indirect.text, "LDA (pointer),Y");
+
+        let mut bbr = decode(&bus, 0xC004, Variant::Wdc65C02);
+        assert_eq!(bbr.target(), Some(0xC008));
+        bbr.name_target("skip");
+        assert_eq!(bbr.text, "BBR0 $10,skip");
+
+        let immediate = decode(&memory(0, &[0xA9, 0x10]), 0, Variant::Ricoh2A03);
+        assert_eq!(immediate.target(), None);
+    }
+
+    #[test]
+    fn test_disassemble_stops_at_top_of_memory() {
+        let bus = memory(0xFFFE, &[0xEA, 0xEA]);
+        assert_eq!(
+            disassemble(&bus, 0xFFFE, 0xFFFF, Variant::Ricoh2A03).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_decode_65c02() {
+        let bus = memory(0x0200, &[0xBF, 0x12, 0x03, 0xB2, 0x40]);
+        let decoded = disassemble(&bus, 0x0200, 0x0203, Variant::Wdc65C02);
+        assert_eq!(decoded[0].text, "BBS3 $12,$0206");
+        assert_eq!(decoded[1].text, "LDA ($40)");
+        //The same bytes are unofficial opcodes on the 2A03
+        assert_eq!(decode(&bus, 0x0200, Variant::Ricoh2A03).text, "LAX $0312,Y");
+    }
+}
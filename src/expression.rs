@@ -0,0 +1,588 @@
+//Conditions for the debugger, e.g. "A == $10 && [$0300] > 5 && scanline < 20".
+//
+//Values are registers (a x y s/sp p pc), flags (n v d i z c), ppu state (scanline dot
+//frame), the cpu cycle count (cycle), numbers ($10, 0x10, %1010, 16), [address] for
+//a byte of memory and {address} for a little endian word. Operators and precedence
+//are C's, comparisons give 1 or 0 and names are case insensitive.
+//An expression is parsed once into a tree, which is walked every time it's checked.
+
+use crate::cpu::Registers;
+use crate::memory::AddressSpace;
+use std::fmt;
+use std::str::FromStr;
+
+//Everything an expression can look at
+pub(crate) struct Context<'a, T: AddressSpace> {
+    pub registers: Registers,
+    pub cycle: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    pub memory: &'a T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize, //Counted from 1, in characters
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: source.chars().count() + 1,
+        };
+        let root = parser.expression(0)?;
+        match parser.peek() {
+            None => Ok(Expression {
+                source: source.to_string(),
+                root,
+            }),
+            Some(token) => Err(ParseError {
+                column: token.column,
+                message: format!("unexpected {}", token.kind),
+            }),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub(crate) fn evaluate<T: AddressSpace>(&self, context: &Context<T>) -> i64 {
+        self.root.evaluate(context)
+    }
+
+    //True when the expression isn't zero
+    pub(crate) fn holds<T: AddressSpace>(&self, context: &Context<T>) -> bool {
+        self.evaluate(context) != 0
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Expression::parse(source)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+    Flag(u8), //Bit of p
+    Cycle,
+    Scanline,
+    Dot,
+    Frame,
+}
+
+impl Value {
+    fn named(name: &str) -> Option<Value> {
+        let value = match name.to_ascii_lowercase().as_str() {
+            "a" => Value::A,
+            "x" => Value::X,
+            "y" => Value::Y,
+            "s" | "sp" => Value::S,
+            "p" => Value::P,
+            "pc" => Value::Pc,
+            "n" => Value::Flag(7),
+            "v" => Value::Flag(6),
+            "d" => Value::Flag(3),
+            "i" => Value::Flag(2),
+            "z" => Value::Flag(1),
+            "c" => Value::Flag(0),
+            "cycle" => Value::Cycle,
+            "scanline" => Value::Scanline,
+            "dot" => Value::Dot,
+            "frame" => Value::Frame,
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unary {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Binary {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl Binary {
+    //Operator for a symbol and how tightly it binds, higher first
+    fn from_symbol(symbol: &str) -> Option<(Binary, u8)> {
+        let operator = match symbol {
+            "||" => (Binary::Or, 1),
+            "&&" => (Binary::And, 2),
+            "|" => (Binary::BitOr, 3),
+            "^" => (Binary::BitXor, 4),
+            "&" => (Binary::BitAnd, 5),
+            "==" => (Binary::Equal, 6),
+            "!=" => (Binary::NotEqual, 6),
+            "<" => (Binary::Less, 7),
+            "<=" => (Binary::LessEqual, 7),
+            ">" => (Binary::Greater, 7),
+            ">=" => (Binary::GreaterEqual, 7),
+            "<<" => (Binary::ShiftLeft, 8),
+            ">>" => (Binary::ShiftRight, 8),
+            "+" => (Binary::Add, 9),
+            "-" => (Binary::Subtract, 9),
+            "*" => (Binary::Multiply, 10),
+            "/" => (Binary::Divide, 10),
+            "%" => (Binary::Remainder, 10),
+            _ => return None,
+        };
+        Some(operator)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    Value(Value),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate<T: AddressSpace>(&self, context: &Context<T>) -> i64 {
+        let registers = &context.registers;
+        match self {
+            Node::Number(number) => *number,
+            Node::Value(value) => match value {
+                Value::A => registers.a as i64,
+                Value::X => registers.x as i64,
+                Value::Y => registers.y as i64,
+                Value::S => registers.s as i64,
+                Value::P => registers.p as i64,
+                Value::Pc => registers.pc as i64,
+                Value::Flag(bit) => ((registers.p >> bit) & 1) as i64,
+                Value::Cycle => context.cycle as i64,
+                Value::Scanline => context.scanline as i64,
+                Value::Dot => context.dot as i64,
+                Value::Frame => context.frame as i64,
+            },
+            Node::Byte(address) => {
+                let address = address.evaluate(context) as u16;
+                context.memory.debug_peek(address) as i64
+            }
+            Node::Word(address) => {
+                let address = address.evaluate(context) as u16;
+                u16::from_le_bytes([
+                    context.memory.debug_peek(address),
+                    context.memory.debug_peek(address.wrapping_add(1)),
+                ]) as i64
+            }
+            Node::Unary(operator, operand) => {
+                let operand = operand.evaluate(context);
+                match operator {
+                    Unary::Not => (operand == 0) as i64,
+                    Unary::Negate => operand.wrapping_neg(),
+                    Unary::Complement => !operand,
+                }
+            }
+            Node::Binary(Binary::Or, left, right) => {
+                (left.evaluate(context) != 0 || right.evaluate(context) != 0) as i64
+            }
+            Node::Binary(Binary::And, left, right) => {
+                (left.evaluate(context) != 0 && right.evaluate(context) != 0) as i64
+            }
+            Node::Binary(operator, left, right) => {
+                let left = left.evaluate(context);
+                let right = right.evaluate(context);
+                match operator {
+                    Binary::BitOr => left | right,
+                    Binary::BitXor => left ^ right,
+                    Binary::BitAnd => left & right,
+                    Binary::Equal => (left == right) as i64,
+                    Binary::NotEqual => (left != right) as i64,
+                    Binary::Less => (left < right) as i64,
+                    Binary::LessEqual => (left <= right) as i64,
+                    Binary::Greater => (left > right) as i64,
+                    Binary::GreaterEqual => (left >= right) as i64,
+                    Binary::ShiftLeft => left.wrapping_shl(right as u32),
+                    Binary::ShiftRight => left.wrapping_shr(right as u32),
+                    Binary::Add => left.wrapping_add(right),
+                    Binary::Subtract => left.wrapping_sub(right),
+                    Binary::Multiply => left.wrapping_mul(right),
+                    //Dividing by zero gives zero rather than stopping the emulator
+                    Binary::Divide => left.checked_div(right).unwrap_or(0),
+                    Binary::Remainder => left.checked_rem(right).unwrap_or(0),
+                    Binary::Or | Binary::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Number(number) => write!(f, "number {}", number),
+            TokenKind::Name(name) => write!(f, "'{}'", name),
+            TokenKind::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+const SYMBOLS: [&str; 27] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]", "{", "}", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let column = index + 1;
+
+        if c.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        //% is a binary number where a value is expected, otherwise the remainder operator
+        let operand_expected = match tokens.last().map(|token| &token.kind) {
+            None => true,
+            Some(TokenKind::Symbol(symbol)) => !matches!(*symbol, ")" | "]" | "}"),
+            Some(_) => false,
+        };
+
+        let (radix, skip) = match c {
+            '$' => (16, 1),
+            '0' if matches!(chars.get(index + 1), Some('x') | Some('X')) => (16, 2),
+            '%' if operand_expected => (2, 1),
+            _ if c.is_ascii_digit() => (10, 0),
+            _ => (0, 0),
+        };
+
+        if radix != 0 {
+            let start = index + skip;
+            let mut end = start;
+            while end < chars.len() && chars[end].is_alphanumeric() {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            if digits.is_empty() {
+                return Err(ParseError {
+                    column,
+                    message: String::from("expected digits"),
+                });
+            }
+            let number = i64::from_str_radix(&digits, radix).map_err(|_| {
+                let bad = digits
+                    .chars()
+                    .position(|digit| !digit.is_digit(radix))
+                    .map_or(column, |position| start + position + 1);
+                ParseError {
+                    column: bad,
+                    message: format!("bad number '{}'", digits),
+                }
+            })?;
+            tokens.push(Token {
+                kind: TokenKind::Number(number),
+                column,
+            });
+            index = end;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = index;
+            while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            tokens.push(Token {
+                kind: TokenKind::Name(chars[index..end].iter().collect()),
+                column,
+            });
+            index = end;
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| {
+                symbol
+                    .chars()
+                    .enumerate()
+                    .all(|(offset, s)| chars.get(index + offset) == Some(&s))
+            });
+            match symbol {
+                //A lone = is almost always a mistyped ==
+                Some(&"=") => {
+                    return Err(ParseError {
+                        column,
+                        message: String::from("'=' should be '=='"),
+                    })
+                }
+                Some(symbol) => {
+                    tokens.push(Token {
+                        kind: TokenKind::Symbol(symbol),
+                        column,
+                    });
+                    index += symbol.len();
+                }
+                None => {
+                    return Err(ParseError {
+                        column,
+                        message: format!("unexpected character '{}'", c),
+                    })
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    end: usize, //Column just past the last character
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    //Column of the next token, or of the end of the input
+    fn column(&self) -> usize {
+        self.peek().map_or(self.end, |token| token.column)
+    }
+
+    //Binary operators binding at least as tightly as precedence
+    fn expression(&mut self, precedence: u8) -> Result<Node, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token {
+                    kind: TokenKind::Symbol(symbol),
+                    ..
+                }) => Binary::from_symbol(symbol),
+                _ => None,
+            };
+            match operator {
+                Some((operator, binds)) if binds >= precedence => {
+                    self.next();
+                    let right = self.expression(binds + 1)?;
+                    left = Node::Binary(operator, Box::new(left), Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        let operator = match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Symbol("!")) => Unary::Not,
+            Some(TokenKind::Symbol("-")) => Unary::Negate,
+            Some(TokenKind::Symbol("~")) => Unary::Complement,
+            _ => return self.primary(),
+        };
+        self.next();
+        Ok(Node::Unary(operator, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        let column = self.column();
+        let token = match self.next() {
+            Some(token) => token,
+            None => {
+                return Err(ParseError {
+                    column,
+                    message: String::from("expected a value"),
+                })
+            }
+        };
+
+        match token.kind {
+            TokenKind::Number(number) => Ok(Node::Number(number)),
+            TokenKind::Name(name) => match Value::named(&name) {
+                Some(value) => Ok(Node::Value(value)),
+                None => Err(ParseError {
+                    column,
+                    message: format!("unknown name '{}'", name),
+                }),
+            },
+            TokenKind::Symbol("(") => {
+                let inner = self.expression(0)?;
+                self.close(")")?;
+                Ok(inner)
+            }
+            TokenKind::Symbol("[") => {
+                let inner = self.expression(0)?;
+                self.close("]")?;
+                Ok(Node::Byte(Box::new(inner)))
+            }
+            TokenKind::Symbol("{") => {
+                let inner = self.expression(0)?;
+                self.close("}")?;
+                Ok(Node::Word(Box::new(inner)))
+            }
+            kind => Err(ParseError {
+                column,
+                message: format!("expected a value, found {}", kind),
+            }),
+        }
+    }
+
+    fn close(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        let column = self.column();
+        match self.next() {
+            Some(Token {
+                kind: TokenKind::Symbol(found),
+                ..
+            }) if found == symbol => Ok(()),
+            _ => Err(ParseError {
+                column,
+                message: format!("expected '{}'", symbol),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::FlatRam;
+
+    fn evaluate(source: &str) -> i64 {
+        let mut memory = FlatRam::new();
+        memory.load(0x0300, &[7, 0x12]);
+        let context = Context {
+            registers: Registers {
+                pc: 0xC000,
+                s: 0xFD,
+                p: 0x25, //I and C set
+                a: 0x10,
+                x: 3,
+                y: 3,
+            },
+            cycle: 1000,
+            scanline: 12,
+            dot: 100,
+            frame: 2,
+            memory: &memory,
+        };
+        Expression::parse(source)
+            .unwrap_or_else(|e| panic!("{}: {}", source, e))
+            .evaluate(&context)
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        let error = Expression::parse(source).unwrap_err();
+        (error.column, error.message)
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        assert_eq!(evaluate("A == $10 && [$0300] > 5 && scanline < 20"), 1);
+        assert_eq!(evaluate("X != Y"), 0);
+        assert_eq!(evaluate("{$0300}"), 0x1207);
+        assert_eq!(evaluate("[$0300 + x - 2]"), 0x12);
+        assert_eq!(evaluate("pc == 0xC000 && SP == 253 && p == %00100101"), 1);
+        assert_eq!(evaluate("c && i && !z && !n"), 1);
+        assert_eq!(evaluate("frame * 1000 + dot + cycle"), 3100);
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), 7);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate("1 | 2 == 2"), 1);
+        assert_eq!(evaluate("0 || 1 && 0"), 0);
+        assert_eq!(evaluate("1 << 2 + 1"), 8);
+        assert_eq!(evaluate("-a + ~0"), -0x11);
+        assert_eq!(evaluate("7 % 4 + %11"), 6);
+        assert_eq!(evaluate("5 / 0"), 0);
+    }
+
+    #[test]
+    fn test_error_columns() {
+        assert_eq!(error("A == $10 &&"), (12, String::from("expected a value")));
+        assert_eq!(error("A == foo"), (6, String::from("unknown name 'foo'")));
+        assert_eq!(error("[$0300 > 5"), (11, String::from("expected ']'")));
+        assert_eq!(error("A = 5"), (3, String::from("'=' should be '=='")));
+        assert_eq!(
+            error("X @ Y"),
+            (3, String::from("unexpected character '@'"))
+        );
+        assert_eq!(error("$12G4 == 1"), (4, String::from("bad number '12G4'")));
+        assert_eq!(error("A == $"), (6, String::from("expected digits")));
+        assert_eq!(error("(A) X"), (5, String::from("unexpected 'X'")));
+        assert_eq!(
+            Expression::parse("A ==").unwrap_err().to_string(),
+            "expected a value at column 5"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//The last instructions the cpu ran, and the post-mortem report built from them
+
+use crate::call_stack::CallFrame;
+use crate::cpu::{Registers, Variant};
+use crate::disasm::{self, Disassembly};
+use crate::memory::AddressSpace;
+use std::collections::VecDeque;
+use std::fmt;
+
+const DEFAULT_LENGTH: usize = 64;
+
+//One instruction, with the state before it ran
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub registers: Registers,
+    pub bytes: [u8; 3], //Opcode and the two bytes after it, as they were when it ran
+    pub cycle: u64,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+impl HistoryEntry {
+    pub fn disassemble(&self, variant: Variant) -> Disassembly {
+        disasm::decode(self, self.registers.pc, variant)
+    }
+}
+
+//Lets the disassembler decode the saved bytes instead of today's memory
+impl AddressSpace for HistoryEntry {
+    fn peek(&mut self, ptr: u16) -> u8 {
+        self.debug_peek(ptr)
+    }
+
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        let offset = ptr.wrapping_sub(self.registers.pc) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+
+    fn poke(&mut self, _ptr: u16, _byte: u8) {}
+}
+
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    length: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(DEFAULT_LENGTH),
+            length: DEFAULT_LENGTH,
+        }
+    }
+}
+
+impl History {
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+        while self.entries.len() > length {
+            self.entries.pop_front();
+        }
+    }
+
+    //Oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn record(&mut self, entry: HistoryEntry) {
+        if self.length == 0 {
+            return;
+        }
+        if self.entries.len() == self.length {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+//Everything needed to make sense of a crash after the fact, Display gives the text
+//for a bug report
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    pub jammed: bool,
+    pub registers: Registers,
+    pub cycle: u64,
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub call_stack: Vec<CallFrame>, //Outermost first
+    pub history: Vec<String>,       //Disassembled, oldest first
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs = &self.registers;
+        if self.jammed {
+            writeln!(f, "CPU jammed at ${:04X}", regs.pc)?;
+        } else {
+            writeln!(f, "CPU stopped at ${:04X}", regs.pc)?;
+        }
+        writeln!(
+            f,
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            regs.a, regs.x, regs.y, regs.p, regs.s, self.cycle
+        )?;
+        writeln!(
+            f,
+            "PPU frame {} scanline {} dot {}",
+            self.frame, self.scanline, self.dot
+        )?;
+
+        writeln!(f, "Call stack, innermost first:")?;
+        if self.call_stack.is_empty() {
+            writeln!(f, "  (empty)")?;
+        }
+        for frame in self.call_stack.iter().rev() {
+            writeln!(f, "  {}", frame)?;
+        }
+
+        writeln!(f, "Last {} instructions:", self.history.len())?;
+        for line in &self.history {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+//C013  E8        INX                 A:00 X:01 Y:00 P:24 SP:FB PPU: 30, 21 CYC:1234
+pub(crate) fn history_line(entry: &HistoryEntry, variant: Variant) -> String {
+    let decoded = entry.disassemble(variant);
+    let bytes: Vec<String> = entry.bytes[..decoded.length as usize]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let regs = &entry.registers;
+    format!(
+        "{:04X}  {:<8}  {:<20}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        regs.pc,
+        bytes.join(" "),
+        decoded.text,
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.p,
+        regs.s,
+        entry.scanline,
+        entry.dot,
+        entry.cycle
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: u16, bytes: [u8; 3]) -> HistoryEntry {
+        HistoryEntry {
+            registers: Registers {
+                pc,
+                s: 0xFD,
+                p: 0x24,
+                a: 0,
+                x: 0,
+                y: 0,
+            },
+            bytes,
+            cycle: 7,
+            scanline: 0,
+            dot: 21,
+        }
+    }
+
+    #[test]
+    fn test_ring_keeps_the_newest() {
+        let mut history = History::default();
+        history.set_length(2);
+        for pc in 0..5 {
+            history.record(entry(pc, [0xEA, 0, 0]));
+        }
+        let pcs: Vec<u16> = history.entries().map(|entry| entry.registers.pc).collect();
+        assert_eq!(pcs, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_history_line_uses_saved_bytes() {
+        let line = history_line(&entry(0xFFFE, [0xAD, 0x02, 0x20]), Variant::Ricoh2A03);
+        assert_eq!(
+            line,
+            "FFFE  AD 02 20  LDA $2002           A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+}
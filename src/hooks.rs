@@ -0,0 +1,184 @@
+//Callbacks on cpu bus accesses, for cheats, RAM watches, scripting and anything else
+//that wants to see or change what the cpu reads without patching the bus itself.
+//With nothing registered the bus pays for one branch per access
+
+use std::ops::RangeInclusive;
+
+//Which accesses a hook wants, combine with |
+pub const READ: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+pub const EXECUTE: u8 = 0x04; //Opcode fetches. Operand fetches are reads
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessKind {
+    fn flag(self) -> u8 {
+        match self {
+            AccessKind::Read => READ,
+            AccessKind::Write => WRITE,
+            AccessKind::Execute => EXECUTE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusEvent {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub value: u8, //What was read, after any earlier hook changed it, or what was written
+    pub cycle: u64, //Cpu cycles run before the access
+}
+
+pub trait BusObserver {
+    //Returning Some on a read or execute hands the cpu that value instead. Ignored on writes
+    fn access(&mut self, event: &BusEvent) -> Option<u8>;
+}
+
+impl<F: FnMut(&BusEvent) -> Option<u8>> BusObserver for F {
+    fn access(&mut self, event: &BusEvent) -> Option<u8> {
+        self(event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+struct Hook {
+    id: HookId,
+    kinds: u8,
+    range: RangeInclusive<u16>,
+    observer: Box<dyn BusObserver>,
+}
+
+#[derive(Default)]
+pub struct BusHooks {
+    hooks: Vec<Hook>,
+    next_id: u64,
+    pub(crate) cycle: u64, //Set by the emulator before each cycle while hooks are registered
+}
+
+impl BusHooks {
+    pub(crate) fn add(
+        &mut self,
+        kinds: u8,
+        range: RangeInclusive<u16>,
+        observer: Box<dyn BusObserver>,
+    ) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id,
+            kinds,
+            range,
+            observer,
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: HookId) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != before
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    //Runs the hooks in the order they were added, each one sees the value the last one left
+    pub(crate) fn access(&mut self, kind: AccessKind, address: u16, value: u8) -> u8 {
+        let mut value = value;
+        for hook in &mut self.hooks {
+            if hook.kinds & kind.flag() == 0 || !hook.range.contains(&address) {
+                continue;
+            }
+            let event = BusEvent {
+                kind,
+                address,
+                value,
+                cycle: self.cycle,
+            };
+            if let Some(replacement) = hook.observer.access(&event) {
+                if kind != AccessKind::Write {
+                    value = replacement;
+                }
+            }
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    //LDA $10, STA $0300, JMP $C005
+    const PROGRAM: [u8; 8] = [0xA5, 0x10, 0x8D, 0x00, 0x03, 0x4C, 0x05, 0xC0];
+
+    #[test]
+    fn test_hooks_see_accesses_in_range() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        emu.add_bus_hook(READ | WRITE, 0x0300..=0x03FF, move |event: &BusEvent| {
+            log.borrow_mut().push(*event);
+            None
+        });
+        let executed = Rc::new(RefCell::new(Vec::new()));
+        let log = executed.clone();
+        emu.add_bus_hook(EXECUTE, 0xC000..=0xFFFF, move |event: &BusEvent| {
+            log.borrow_mut().push((event.address, event.cycle));
+            None
+        });
+
+        emu.step_instruction();
+        emu.step_instruction();
+        assert_eq!(
+            *events.borrow(),
+            [BusEvent {
+                kind: AccessKind::Write,
+                address: 0x0300,
+                value: 0x00,
+                cycle: 7 + 3 + 3,
+            }]
+        );
+        //Opcodes only, not operands
+        assert_eq!(*executed.borrow(), [(0xC000, 7), (0xC002, 10)]);
+    }
+
+    #[test]
+    fn test_hooks_change_reads() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        emu.add_bus_hook(READ, 0x0010..=0x0010, |_: &BusEvent| Some(0x40));
+        //Sees the first hook's value
+        let id = emu.add_bus_hook(READ, 0x0000..=0x07FF, |event: &BusEvent| {
+            Some(event.value + 1)
+        });
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x41);
+
+        assert!(emu.remove_bus_hook(id));
+        assert!(!emu.remove_bus_hook(id));
+        emu.cpu.set_pc(0xC000);
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x40);
+    }
+
+    #[test]
+    fn test_execute_hooks_change_opcodes() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        //LDA $10 becomes LDA #$10
+        emu.add_bus_hook(EXECUTE, 0xC000..=0xC000, |_: &BusEvent| Some(0xA9));
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x10);
+    }
+}
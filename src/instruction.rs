@@ -16,6 +16,10 @@ pub enum AddressingMode {
     ZeroPage,
     ZeroPageX,
     ZeroPageY,
+    //65C02 only
+    ZeroPageIndirect,
+    AbsoluteIndexedIndirect, //JMP (abs,X)
+    ZeroPageRelative,        //BBR and BBS, a zero page address followed by a branch offset
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -76,547 +80,1109 @@ pub enum Instruction {
     TXA,
     TXS,
     TYA,
+
+    //Unofficial
+    ALR,
+    ANC,
+    ARR,
+    AXS,
+    DCP,
+    ISB,
+    JAM,
+    LAS,
+    LAX,
+    RLA,
+    RRA,
+    SAX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+    XAA,
+
+    //65C02. RMB, SMB, BBR and BBS take their bit number from the opcode
+    BBR,
+    BBS,
+    BRA,
+    PHX,
+    PHY,
+    PLX,
+    PLY,
+    RMB,
+    SMB,
+    STP,
+    STZ,
+    TRB,
+    TSB,
+    WAI,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Operation {
     pub instruction: Instruction,
     pub addressing_mode: AddressingMode,
+    //Reference timing, the cpu counts its cycles as it runs
     pub base_cycle_count: u8,
-    pub data: Vec<u8>,
+    //Only the cycle tests read this, the cpu works it out from the kind of access
+    #[cfg(test)]
+    pub page_cross_penalty: bool,
 }
 
+//oc!(instruction, addressing mode, cycles). PageCross marks reads that take one more
+//cycle when indexing crosses a page. Taken branches add their own cycles when executed
+//https://www.nesdev.org/obelisk-6502-guide/reference.html
 macro_rules! oc {
-    ($inst:expr, $address:expr) => {
-        Some(Operation {
+    ($inst:expr, $address:expr, $cycles:expr) => {
+        Operation {
             instruction: $inst,
             addressing_mode: $address,
-            base_cycle_count: match $address {
-                ZeroPageX => 4,
-                ZeroPageY => 4,
-                AbsoluteX => 4,
-                AbsoluteY => 4,
-                IndirectX => 6,
-                IndirectY => 5,
-                _ => 2,
-            },
-            data: Vec::<u8>::new(),
-        })
+            base_cycle_count: $cycles,
+            #[cfg(test)]
+            page_cross_penalty: false,
+        }
+    };
+    ($inst:expr, $address:expr, $cycles:expr, PageCross) => {
+        Operation {
+            instruction: $inst,
+            addressing_mode: $address,
+            base_cycle_count: $cycles,
+            #[cfg(test)]
+            page_cross_penalty: true,
+        }
     };
 }
 
-#[allow(dead_code)]
-pub static OPCODES: [Option<Operation>; 256] = [
+pub static OPCODES: [Operation; 256] = [
+    //0x00
+    oc!(BRK, Implied, 7),
+    //0x01
+    oc!(ORA, IndirectX, 6),
+    //0x02
+    oc!(JAM, Implied, 2),
+    //0x03
+    oc!(SLO, IndirectX, 8),
+    //0x04
+    oc!(NOP, ZeroPage, 3), //Illegal
+    //0x05
+    oc!(ORA, ZeroPage, 3),
+    //0x06
+    oc!(ASL, ZeroPage, 5),
+    //0x07
+    oc!(SLO, ZeroPage, 5),
+    //0x08
+    oc!(PHP, Implied, 3),
+    //0x09
+    oc!(ORA, Immediate, 2),
+    //0x0a
+    oc!(ASL, Accumulator, 2),
+    //0x0b
+    oc!(ANC, Immediate, 2),
+    //0x0c
+    oc!(NOP, Absolute, 4),
+    //0x0d
+    oc!(ORA, Absolute, 4),
+    //0x0e
+    oc!(ASL, Absolute, 6),
+    //0x0f
+    oc!(SLO, Absolute, 6),
+    //0x10
+    oc!(BPL, Relative, 2),
+    //0x11
+    oc!(ORA, IndirectY, 5, PageCross),
+    //0x12
+    oc!(JAM, Implied, 2),
+    //0x13
+    oc!(SLO, IndirectY, 8),
+    //0x14
+    oc!(NOP, ZeroPageX, 4),
+    //0x15
+    oc!(ORA, ZeroPageX, 4),
+    //0x16
+    oc!(ASL, ZeroPageX, 6),
+    //0x17
+    oc!(SLO, ZeroPageX, 6),
+    //0x18
+    oc!(CLC, Implied, 2),
+    //0x19
+    oc!(ORA, AbsoluteY, 4, PageCross),
+    //0x1a
+    oc!(NOP, Implied, 2),
+    //0x1b
+    oc!(SLO, AbsoluteY, 7),
+    //0x1c
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0x1d
+    oc!(ORA, AbsoluteX, 4, PageCross),
+    //0x1e
+    oc!(ASL, AbsoluteX, 7),
+    //0x1f
+    oc!(SLO, AbsoluteX, 7),
+    //0x20
+    oc!(JSR, Absolute, 6),
+    //0x21
+    oc!(AND, IndirectX, 6),
+    //0x22
+    oc!(JAM, Implied, 2),
+    //0x23
+    oc!(RLA, IndirectX, 8),
+    //0x24
+    oc!(BIT, ZeroPage, 3),
+    //0x25
+    oc!(AND, ZeroPage, 3),
+    //0x26
+    oc!(ROL, ZeroPage, 5),
+    //0x27
+    oc!(RLA, ZeroPage, 5),
+    //0x28
+    oc!(PLP, Implied, 4),
+    //0x29
+    oc!(AND, Immediate, 2),
+    //0x2a
+    oc!(ROL, Accumulator, 2),
+    //0x2b
+    oc!(ANC, Immediate, 2),
+    //0x2c
+    oc!(BIT, Absolute, 4),
+    //0x2d
+    oc!(AND, Absolute, 4),
+    //0x2e
+    oc!(ROL, Absolute, 6),
+    //0x2f
+    oc!(RLA, Absolute, 6),
+    //0x30
+    oc!(BMI, Relative, 2),
+    //0x31
+    oc!(AND, IndirectY, 5, PageCross),
+    //0x32
+    oc!(JAM, Implied, 2),
+    //0x33
+    oc!(RLA, IndirectY, 8),
+    //0x34
+    oc!(NOP, ZeroPageX, 4),
+    //0x35
+    oc!(AND, ZeroPageX, 4),
+    //0x36
+    oc!(ROL, ZeroPageX, 6),
+    //0x37
+    oc!(RLA, ZeroPageX, 6),
+    //0x38
+    oc!(SEC, Implied, 2),
+    //0x39
+    oc!(AND, AbsoluteY, 4, PageCross),
+    //0x3a
+    oc!(NOP, Implied, 2),
+    //0x3b
+    oc!(RLA, AbsoluteY, 7),
+    //0x3c
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0x3d
+    oc!(AND, AbsoluteX, 4, PageCross),
+    //0x3e
+    oc!(ROL, AbsoluteX, 7),
+    //0x3f
+    oc!(RLA, AbsoluteX, 7),
+    //0x40
+    oc!(RTI, Implied, 6),
+    //0x41
+    oc!(EOR, IndirectX, 6),
+    //0x42
+    oc!(JAM, Implied, 2),
+    //0x43
+    oc!(SRE, IndirectX, 8),
+    //0x44
+    oc!(NOP, ZeroPage, 3),
+    //0x45
+    oc!(EOR, ZeroPage, 3),
+    //0x46
+    oc!(LSR, ZeroPage, 5),
+    //0x47
+    oc!(SRE, ZeroPage, 5),
+    //0x48
+    oc!(PHA, Implied, 3),
+    //0x49
+    oc!(EOR, Immediate, 2),
+    //0x4a
+    oc!(LSR, Accumulator, 2),
+    //0x4b
+    oc!(ALR, Immediate, 2),
+    //0x4c
+    oc!(JMP, Absolute, 3),
+    //0x4d
+    oc!(EOR, Absolute, 4),
+    //0x4e
+    oc!(LSR, Absolute, 6),
+    //0x4f
+    oc!(SRE, Absolute, 6),
+    //0x50
+    oc!(BVC, Relative, 2),
+    //0x51
+    oc!(EOR, IndirectY, 5, PageCross),
+    //0x52
+    oc!(JAM, Implied, 2),
+    //0x53
+    oc!(SRE, IndirectY, 8),
+    //0x54
+    oc!(NOP, ZeroPageX, 4),
+    //0x55
+    oc!(EOR, ZeroPageX, 4),
+    //0x56
+    oc!(LSR, ZeroPageX, 6),
+    //0x57
+    oc!(SRE, ZeroPageX, 6),
+    //0x58
+    oc!(CLI, Implied, 2),
+    //0x59
+    oc!(EOR, AbsoluteY, 4, PageCross),
+    //0x5a
+    oc!(NOP, Implied, 2),
+    //0x5b
+    oc!(SRE, AbsoluteY, 7),
+    //0x5c
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0x5d
+    oc!(EOR, AbsoluteX, 4, PageCross),
+    //0x5e
+    oc!(LSR, AbsoluteX, 7),
+    //0x5f
+    oc!(SRE, AbsoluteX, 7),
+    //0x60
+    oc!(RTS, Implied, 6),
+    //0x61
+    oc!(ADC, IndirectX, 6),
+    //0x62
+    oc!(JAM, Implied, 2),
+    //0x63
+    oc!(RRA, IndirectX, 8),
+    //0x64
+    oc!(NOP, ZeroPage, 3),
+    //0x65
+    oc!(ADC, ZeroPage, 3),
+    //0x66
+    oc!(ROR, ZeroPage, 5),
+    //0x67
+    oc!(RRA, ZeroPage, 5),
+    //0x68
+    oc!(PLA, Implied, 4),
+    //0x69
+    oc!(ADC, Immediate, 2),
+    //0x6a
+    oc!(ROR, Accumulator, 2),
+    //0x6b
+    oc!(ARR, Immediate, 2),
+    //0x6c
+    oc!(JMP, Indirect, 5),
+    //0x6d
+    oc!(ADC, Absolute, 4),
+    //0x6e
+    oc!(ROR, Absolute, 6),
+    //0x6f
+    oc!(RRA, Absolute, 6),
+    //0x70
+    oc!(BVS, Relative, 2),
+    //0x71
+    oc!(ADC, IndirectY, 5, PageCross),
+    //0x72
+    oc!(JAM, Implied, 2),
+    //0x73
+    oc!(RRA, IndirectY, 8),
+    //0x74
+    oc!(NOP, ZeroPageX, 4),
+    //0x75
+    oc!(ADC, ZeroPageX, 4),
+    //0x76
+    oc!(ROR, ZeroPageX, 6),
+    //0x77
+    oc!(RRA, ZeroPageX, 6),
+    //0x78
+    oc!(SEI, Implied, 2),
+    //0x79
+    oc!(ADC, AbsoluteY, 4, PageCross),
+    //0x7a
+    oc!(NOP, Implied, 2),
+    //0x7b
+    oc!(RRA, AbsoluteY, 7),
+    //0x7c
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0x7d
+    oc!(ADC, AbsoluteX, 4, PageCross),
+    //0x7e
+    oc!(ROR, AbsoluteX, 7),
+    //0x7f
+    oc!(RRA, AbsoluteX, 7),
+    //0x80
+    oc!(NOP, Immediate, 2),
+    //0x81
+    oc!(STA, IndirectX, 6),
+    //0x82
+    oc!(NOP, Immediate, 2),
+    //0x83
+    oc!(SAX, IndirectX, 6),
+    //0x84
+    oc!(STY, ZeroPage, 3),
+    //0x85
+    oc!(STA, ZeroPage, 3),
+    //0x86
+    oc!(STX, ZeroPage, 3),
+    //0x87
+    oc!(SAX, ZeroPage, 3),
+    //0x88
+    oc!(DEY, Implied, 2),
+    //0x89
+    oc!(NOP, Immediate, 2),
+    //0x8a
+    oc!(TXA, Implied, 2),
+    //0x8b
+    oc!(XAA, Immediate, 2),
+    //0x8c
+    oc!(STY, Absolute, 4),
+    //0x8d
+    oc!(STA, Absolute, 4),
+    //0x8e
+    oc!(STX, Absolute, 4),
+    //0x8f
+    oc!(SAX, Absolute, 4),
+    //0x90
+    oc!(BCC, Relative, 2),
+    //0x91
+    oc!(STA, IndirectY, 6),
+    //0x92
+    oc!(JAM, Implied, 2),
+    //0x93
+    oc!(SHA, IndirectY, 6),
+    //0x94
+    oc!(STY, ZeroPageX, 4),
+    //0x95
+    oc!(STA, ZeroPageX, 4),
+    //0x96
+    oc!(STX, ZeroPageY, 4),
+    //0x97
+    oc!(SAX, ZeroPageY, 4),
+    //0x98
+    oc!(TYA, Implied, 2),
+    //0x99
+    oc!(STA, AbsoluteY, 5),
+    //0x9a
+    oc!(TXS, Implied, 2),
+    //0x9b
+    oc!(TAS, AbsoluteY, 5),
+    //0x9c
+    oc!(SHY, AbsoluteX, 5),
+    //0x9d
+    oc!(STA, AbsoluteX, 5),
+    //0x9e
+    oc!(SHX, AbsoluteY, 5),
+    //0x9f
+    oc!(SHA, AbsoluteY, 5),
+    //0xa0
+    oc!(LDY, Immediate, 2),
+    //0xa1
+    oc!(LDA, IndirectX, 6),
+    //0xa2
+    oc!(LDX, Immediate, 2),
+    //0xa3
+    oc!(LAX, IndirectX, 6),
+    //0xa4
+    oc!(LDY, ZeroPage, 3),
+    //0xa5
+    oc!(LDA, ZeroPage, 3),
+    //0xa6
+    oc!(LDX, ZeroPage, 3),
+    //0xa7
+    oc!(LAX, ZeroPage, 3),
+    //0xa8
+    oc!(TAY, Implied, 2),
+    //0xa9
+    oc!(LDA, Immediate, 2),
+    //0xaa
+    oc!(TAX, Implied, 2),
+    //0xab
+    oc!(LAX, Immediate, 2),
+    //0xac
+    oc!(LDY, Absolute, 4),
+    //0xad
+    oc!(LDA, Absolute, 4),
+    //0xae
+    oc!(LDX, Absolute, 4),
+    //0xaf
+    oc!(LAX, Absolute, 4),
+    //0xb0
+    oc!(BCS, Relative, 2),
+    //0xb1
+    oc!(LDA, IndirectY, 5, PageCross),
+    //0xb2
+    oc!(JAM, Implied, 2),
+    //0xb3
+    oc!(LAX, IndirectY, 5, PageCross),
+    //0xb4
+    oc!(LDY, ZeroPageX, 4),
+    //0xb5
+    oc!(LDA, ZeroPageX, 4),
+    //0xb6
+    oc!(LDX, ZeroPageY, 4),
+    //0xb7
+    oc!(LAX, ZeroPageY, 4),
+    //0xb8
+    oc!(CLV, Implied, 2),
+    //0xb9
+    oc!(LDA, AbsoluteY, 4, PageCross),
+    //0xba
+    oc!(TSX, Implied, 2),
+    //0xbb
+    oc!(LAS, AbsoluteY, 4, PageCross),
+    //0xbc
+    oc!(LDY, AbsoluteX, 4, PageCross),
+    //0xbd
+    oc!(LDA, AbsoluteX, 4, PageCross),
+    //0xbe
+    oc!(LDX, AbsoluteY, 4, PageCross),
+    //0xbf
+    oc!(LAX, AbsoluteY, 4, PageCross),
+    //0xc0
+    oc!(CPY, Immediate, 2),
+    //0xc1
+    oc!(CMP, IndirectX, 6),
+    //0xc2
+    oc!(NOP, Immediate, 2),
+    //0xc3
+    oc!(DCP, IndirectX, 8),
+    //0xc4
+    oc!(CPY, ZeroPage, 3),
+    //0xc5
+    oc!(CMP, ZeroPage, 3),
+    //0xc6
+    oc!(DEC, ZeroPage, 5),
+    //0xc7
+    oc!(DCP, ZeroPage, 5),
+    //0xc8
+    oc!(INY, Implied, 2),
+    //0xc9
+    oc!(CMP, Immediate, 2),
+    //0xca
+    oc!(DEX, Implied, 2),
+    //0xcb
+    oc!(AXS, Immediate, 2),
+    //0xcc
+    oc!(CPY, Absolute, 4),
+    //0xcd
+    oc!(CMP, Absolute, 4),
+    //0xce
+    oc!(DEC, Absolute, 6),
+    //0xcf
+    oc!(DCP, Absolute, 6),
+    //0xd0
+    oc!(BNE, Relative, 2),
+    //0xd1
+    oc!(CMP, IndirectY, 5, PageCross),
+    //0xd2
+    oc!(JAM, Implied, 2),
+    //0xd3
+    oc!(DCP, IndirectY, 8),
+    //0xd4
+    oc!(NOP, ZeroPageX, 4),
+    //0xd5
+    oc!(CMP, ZeroPageX, 4),
+    //0xd6
+    oc!(DEC, ZeroPageX, 6),
+    //0xd7
+    oc!(DCP, ZeroPageX, 6),
+    //0xd8
+    oc!(CLD, Implied, 2),
+    //0xd9
+    oc!(CMP, AbsoluteY, 4, PageCross),
+    //0xda
+    oc!(NOP, Implied, 2),
+    //0xdb
+    oc!(DCP, AbsoluteY, 7),
+    //0xdc
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0xdd
+    oc!(CMP, AbsoluteX, 4, PageCross),
+    //0xde
+    oc!(DEC, AbsoluteX, 7),
+    //0xdf
+    oc!(DCP, AbsoluteX, 7),
+    //0xe0
+    oc!(CPX, Immediate, 2),
+    //0xe1
+    oc!(SBC, IndirectX, 6),
+    //0xe2
+    oc!(NOP, Immediate, 2),
+    //0xe3
+    oc!(ISB, IndirectX, 8),
+    //0xe4
+    oc!(CPX, ZeroPage, 3),
+    //0xe5
+    oc!(SBC, ZeroPage, 3),
+    //0xe6
+    oc!(INC, ZeroPage, 5),
+    //0xe7
+    oc!(ISB, ZeroPage, 5),
+    //0xe8
+    oc!(INX, Implied, 2),
+    //0xe9
+    oc!(SBC, Immediate, 2),
+    //0xea
+    oc!(NOP, Implied, 2),
+    //0xeb
+    oc!(SBC, Immediate, 2),
+    //0xec
+    oc!(CPX, Absolute, 4),
+    //0xed
+    oc!(SBC, Absolute, 4),
+    //0xee
+    oc!(INC, Absolute, 6),
+    //0xef
+    oc!(ISB, Absolute, 6),
+    //0xf0
+    oc!(BEQ, Relative, 2),
+    //0xf1
+    oc!(SBC, IndirectY, 5, PageCross),
+    //0xf2
+    oc!(JAM, Implied, 2),
+    //0xf3
+    oc!(ISB, IndirectY, 8),
+    //0xf4
+    oc!(NOP, ZeroPageX, 4),
+    //0xf5
+    oc!(SBC, ZeroPageX, 4),
+    //0xf6
+    oc!(INC, ZeroPageX, 6),
+    //0xf7
+    oc!(ISB, ZeroPageX, 6),
+    //0xf8
+    oc!(SED, Implied, 2),
+    //0xf9
+    oc!(SBC, AbsoluteY, 4, PageCross),
+    //0xfa
+    oc!(NOP, Implied, 2),
+    //0xfb
+    oc!(ISB, AbsoluteY, 7),
+    //0xfc
+    oc!(NOP, AbsoluteX, 4, PageCross),
+    //0xfd
+    oc!(SBC, AbsoluteX, 4, PageCross),
+    //0xfe
+    oc!(INC, AbsoluteX, 7),
+    //0xff
+    oc!(ISB, AbsoluteX, 7),
+];
+
+//WDC 65C02. The NMOS unofficial opcodes are replaced by new instructions and NOPs,
+//some of which only take a single cycle
+//http://www.6502.org/tutorials/65c02opcodes.html
+pub static CMOS_OPCODES: [Operation; 256] = [
     //0x00
-    oc!(BRK, Implied),
+    oc!(BRK, Implied, 7),
     //0x01
-    oc!(ORA, IndirectX),
+    oc!(ORA, IndirectX, 6),
     //0x02
-    None,
+    oc!(NOP, Immediate, 2),
     //0x03
-    None,
+    oc!(NOP, Implied, 1),
     //0x04
-    oc!(NOP, ZeroPage), //Illegal
+    oc!(TSB, ZeroPage, 5),
     //0x05
-    oc!(ORA, ZeroPage),
+    oc!(ORA, ZeroPage, 3),
     //0x06
-    oc!(ASL, ZeroPage),
+    oc!(ASL, ZeroPage, 5),
     //0x07
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x08
-    oc!(PHP, Implied),
+    oc!(PHP, Implied, 3),
     //0x09
-    oc!(ORA, Immediate),
+    oc!(ORA, Immediate, 2),
     //0x0a
-    oc!(ASL, Accumulator),
+    oc!(ASL, Accumulator, 2),
     //0x0b
-    None,
+    oc!(NOP, Implied, 1),
     //0x0c
-    oc!(NOP, Absolute),
+    oc!(TSB, Absolute, 6),
     //0x0d
-    oc!(ORA, Absolute),
+    oc!(ORA, Absolute, 4),
     //0x0e
-    oc!(ASL, Absolute),
+    oc!(ASL, Absolute, 6),
     //0x0f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x10
-    oc!(BPL, Relative),
+    oc!(BPL, Relative, 2),
     //0x11
-    oc!(ORA, IndirectY),
+    oc!(ORA, IndirectY, 5, PageCross),
     //0x12
-    None,
+    oc!(ORA, ZeroPageIndirect, 5),
     //0x13
-    None,
+    oc!(NOP, Implied, 1),
     //0x14
-    oc!(NOP, ZeroPageX),
+    oc!(TRB, ZeroPage, 5),
     //0x15
-    oc!(ORA, ZeroPageX),
+    oc!(ORA, ZeroPageX, 4),
     //0x16
-    oc!(ASL, ZeroPageX),
+    oc!(ASL, ZeroPageX, 6),
     //0x17
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x18
-    oc!(CLC, Implied),
+    oc!(CLC, Implied, 2),
     //0x19
-    oc!(ORA, AbsoluteY),
+    oc!(ORA, AbsoluteY, 4, PageCross),
     //0x1a
-    oc!(NOP, Absolute),
+    oc!(INC, Accumulator, 2),
     //0x1b
-    None,
+    oc!(NOP, Implied, 1),
     //0x1c
-    oc!(NOP, AbsoluteX),
+    oc!(TRB, Absolute, 6),
     //0x1d
-    oc!(ORA, AbsoluteX),
+    oc!(ORA, AbsoluteX, 4, PageCross),
     //0x1e
-    oc!(ASL, AbsoluteX),
+    oc!(ASL, AbsoluteX, 6, PageCross),
     //0x1f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x20
-    oc!(JSR, Absolute),
+    oc!(JSR, Absolute, 6),
     //0x21
-    oc!(AND, IndirectX),
+    oc!(AND, IndirectX, 6),
     //0x22
-    None,
+    oc!(NOP, Immediate, 2),
     //0x23
-    None,
+    oc!(NOP, Implied, 1),
     //0x24
-    oc!(BIT, ZeroPage),
+    oc!(BIT, ZeroPage, 3),
     //0x25
-    oc!(AND, ZeroPage),
+    oc!(AND, ZeroPage, 3),
     //0x26
-    oc!(ROL, ZeroPage),
+    oc!(ROL, ZeroPage, 5),
     //0x27
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x28
-    oc!(PLP, Implied),
+    oc!(PLP, Implied, 4),
     //0x29
-    oc!(AND, Immediate),
+    oc!(AND, Immediate, 2),
     //0x2a
-    oc!(ROL, Accumulator),
+    oc!(ROL, Accumulator, 2),
     //0x2b
-    None,
+    oc!(NOP, Implied, 1),
     //0x2c
-    oc!(BIT, Absolute),
+    oc!(BIT, Absolute, 4),
     //0x2d
-    oc!(AND, Absolute),
+    oc!(AND, Absolute, 4),
     //0x2e
-    oc!(ROL, Absolute),
+    oc!(ROL, Absolute, 6),
     //0x2f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x30
-    oc!(BMI, Relative),
+    oc!(BMI, Relative, 2),
     //0x31
-    oc!(AND, IndirectY),
+    oc!(AND, IndirectY, 5, PageCross),
     //0x32
-    None,
+    oc!(AND, ZeroPageIndirect, 5),
     //0x33
-    None,
+    oc!(NOP, Implied, 1),
     //0x34
-    oc!(NOP, ZeroPageX),
+    oc!(BIT, ZeroPageX, 4),
     //0x35
-    oc!(AND, ZeroPageX),
+    oc!(AND, ZeroPageX, 4),
     //0x36
-    oc!(ROL, ZeroPageX),
+    oc!(ROL, ZeroPageX, 6),
     //0x37
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x38
-    oc!(SEC, Implied),
+    oc!(SEC, Implied, 2),
     //0x39
-    oc!(AND, AbsoluteY),
+    oc!(AND, AbsoluteY, 4, PageCross),
     //0x3a
-    None,
+    oc!(DEC, Accumulator, 2),
     //0x3b
-    None,
+    oc!(NOP, Implied, 1),
     //0x3c
-    oc!(NOP, AbsoluteX),
+    oc!(BIT, AbsoluteX, 4, PageCross),
     //0x3d
-    oc!(AND, AbsoluteX),
+    oc!(AND, AbsoluteX, 4, PageCross),
     //0x3e
-    oc!(ROL, AbsoluteX),
+    oc!(ROL, AbsoluteX, 6, PageCross),
     //0x3f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x40
-    oc!(RTI, Implied),
+    oc!(RTI, Implied, 6),
     //0x41
-    oc!(EOR, IndirectX),
+    oc!(EOR, IndirectX, 6),
     //0x42
-    None,
+    oc!(NOP, Immediate, 2),
     //0x43
-    None,
+    oc!(NOP, Implied, 1),
     //0x44
-    oc!(NOP, ZeroPage),
+    oc!(NOP, ZeroPage, 3),
     //0x45
-    oc!(EOR, ZeroPage),
+    oc!(EOR, ZeroPage, 3),
     //0x46
-    oc!(LSR, ZeroPage),
+    oc!(LSR, ZeroPage, 5),
     //0x47
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x48
-    oc!(PHA, Implied),
+    oc!(PHA, Implied, 3),
     //0x49
-    oc!(EOR, Immediate),
+    oc!(EOR, Immediate, 2),
     //0x4a
-    oc!(LSR, Accumulator),
+    oc!(LSR, Accumulator, 2),
     //0x4b
-    None,
+    oc!(NOP, Implied, 1),
     //0x4c
-    oc!(JMP, Absolute),
+    oc!(JMP, Absolute, 3),
     //0x4d
-    oc!(EOR, Absolute),
+    oc!(EOR, Absolute, 4),
     //0x4e
-    oc!(LSR, Absolute),
+    oc!(LSR, Absolute, 6),
     //0x4f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x50
-    oc!(BVC, Relative),
+    oc!(BVC, Relative, 2),
     //0x51
-    oc!(EOR, IndirectY),
+    oc!(EOR, IndirectY, 5, PageCross),
     //0x52
-    None,
+    oc!(EOR, ZeroPageIndirect, 5),
     //0x53
-    None,
+    oc!(NOP, Implied, 1),
     //0x54
-    oc!(NOP, ZeroPageX),
+    oc!(NOP, ZeroPageX, 4),
     //0x55
-    oc!(EOR, ZeroPageX),
+    oc!(EOR, ZeroPageX, 4),
     //0x56
-    oc!(LSR, ZeroPageX),
+    oc!(LSR, ZeroPageX, 6),
     //0x57
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x58
-    oc!(CLI, Implied),
+    oc!(CLI, Implied, 2),
     //0x59
-    oc!(EOR, AbsoluteY),
+    oc!(EOR, AbsoluteY, 4, PageCross),
     //0x5a
-    None,
+    oc!(PHY, Implied, 3),
     //0x5b
-    None,
+    oc!(NOP, Implied, 1),
     //0x5c
-    oc!(NOP, AbsoluteX),
+    oc!(NOP, Absolute, 8),
     //0x5d
-    oc!(EOR, AbsoluteX),
+    oc!(EOR, AbsoluteX, 4, PageCross),
     //0x5e
-    oc!(LSR, AbsoluteX),
+    oc!(LSR, AbsoluteX, 6, PageCross),
     //0x5f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x60
-    oc!(RTS, Implied),
+    oc!(RTS, Implied, 6),
     //0x61
-    oc!(ADC, IndirectX),
+    oc!(ADC, IndirectX, 6),
     //0x62
-    None,
+    oc!(NOP, Immediate, 2),
     //0x63
-    None,
+    oc!(NOP, Implied, 1),
     //0x64
-    oc!(NOP, ZeroPage),
+    oc!(STZ, ZeroPage, 3),
     //0x65
-    oc!(ADC, ZeroPage),
+    oc!(ADC, ZeroPage, 3),
     //0x66
-    oc!(ROR, ZeroPage),
+    oc!(ROR, ZeroPage, 5),
     //0x67
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x68
-    oc!(PLA, Implied),
+    oc!(PLA, Implied, 4),
     //0x69
-    oc!(ADC, Immediate),
+    oc!(ADC, Immediate, 2),
     //0x6a
-    oc!(ROR, Accumulator),
+    oc!(ROR, Accumulator, 2),
     //0x6b
-    None,
+    oc!(NOP, Implied, 1),
     //0x6c
-    oc!(JMP, Indirect),
+    oc!(JMP, Indirect, 6),
     //0x6d
-    oc!(ADC, Absolute),
+    oc!(ADC, Absolute, 4),
     //0x6e
-    oc!(ROR, Absolute),
+    oc!(ROR, Absolute, 6),
     //0x6f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x70
-    oc!(BVS, Relative),
+    oc!(BVS, Relative, 2),
     //0x71
-    oc!(ADC, IndirectY),
+    oc!(ADC, IndirectY, 5, PageCross),
     //0x72
-    None,
+    oc!(ADC, ZeroPageIndirect, 5),
     //0x73
-    None,
+    oc!(NOP, Implied, 1),
     //0x74
-    oc!(NOP, ZeroPageX),
+    oc!(STZ, ZeroPageX, 4),
     //0x75
-    oc!(ADC, ZeroPageX),
+    oc!(ADC, ZeroPageX, 4),
     //0x76
-    oc!(ROR, ZeroPageX),
+    oc!(ROR, ZeroPageX, 6),
     //0x77
-    None,
+    oc!(RMB, ZeroPage, 5),
     //0x78
-    oc!(SEI, Implied),
+    oc!(SEI, Implied, 2),
     //0x79
-    oc!(ADC, AbsoluteY),
+    oc!(ADC, AbsoluteY, 4, PageCross),
     //0x7a
-    oc!(NOP, Absolute),
+    oc!(PLY, Implied, 4),
     //0x7b
-    None,
+    oc!(NOP, Implied, 1),
     //0x7c
-    oc!(NOP, AbsoluteX),
+    oc!(JMP, AbsoluteIndexedIndirect, 6),
     //0x7d
-    oc!(ADC, AbsoluteX),
+    oc!(ADC, AbsoluteX, 4, PageCross),
     //0x7e
-    oc!(ROR, AbsoluteX),
+    oc!(ROR, AbsoluteX, 6, PageCross),
     //0x7f
-    None,
+    oc!(BBR, ZeroPageRelative, 5),
     //0x80
-    oc!(NOP, Immediate),
+    oc!(BRA, Relative, 2),
     //0x81
-    oc!(STA, IndirectX),
+    oc!(STA, IndirectX, 6),
     //0x82
-    None,
+    oc!(NOP, Immediate, 2),
     //0x83
-    None,
+    oc!(NOP, Implied, 1),
     //0x84
-    oc!(STY, ZeroPage),
+    oc!(STY, ZeroPage, 3),
     //0x85
-    oc!(STA, ZeroPage),
+    oc!(STA, ZeroPage, 3),
     //0x86
-    oc!(STX, ZeroPage),
+    oc!(STX, ZeroPage, 3),
     //0x87
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0x88
-    oc!(DEY, Implied),
+    oc!(DEY, Implied, 2),
     //0x89
-    None,
+    oc!(BIT, Immediate, 2),
     //0x8a
-    oc!(TXA, Implied),
+    oc!(TXA, Implied, 2),
     //0x8b
-    None,
+    oc!(NOP, Implied, 1),
     //0x8c
-    oc!(STY, Absolute),
+    oc!(STY, Absolute, 4),
     //0x8d
-    oc!(STA, Absolute),
+    oc!(STA, Absolute, 4),
     //0x8e
-    oc!(STX, Absolute),
+    oc!(STX, Absolute, 4),
     //0x8f
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0x90
-    oc!(BCC, Relative),
+    oc!(BCC, Relative, 2),
     //0x91
-    oc!(STA, IndirectY),
+    oc!(STA, IndirectY, 6),
     //0x92
-    None,
+    oc!(STA, ZeroPageIndirect, 5),
     //0x93
-    None,
+    oc!(NOP, Implied, 1),
     //0x94
-    oc!(STY, ZeroPageX),
+    oc!(STY, ZeroPageX, 4),
     //0x95
-    oc!(STA, ZeroPageX),
+    oc!(STA, ZeroPageX, 4),
     //0x96
-    oc!(STX, ZeroPageY),
+    oc!(STX, ZeroPageY, 4),
     //0x97
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0x98
-    oc!(TYA, Implied),
+    oc!(TYA, Implied, 2),
     //0x99
-    oc!(STA, AbsoluteY),
+    oc!(STA, AbsoluteY, 5),
     //0x9a
-    oc!(TXS, Implied),
+    oc!(TXS, Implied, 2),
     //0x9b
-    None,
+    oc!(NOP, Implied, 1),
     //0x9c
-    None,
+    oc!(STZ, Absolute, 4),
     //0x9d
-    oc!(STA, AbsoluteX),
+    oc!(STA, AbsoluteX, 5),
     //0x9e
-    None,
+    oc!(STZ, AbsoluteX, 5),
     //0x9f
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xa0
-    oc!(LDY, Immediate),
+    oc!(LDY, Immediate, 2),
     //0xa1
-    oc!(LDA, IndirectX),
+    oc!(LDA, IndirectX, 6),
     //0xa2
-    oc!(LDX, Immediate),
+    oc!(LDX, Immediate, 2),
     //0xa3
-    None,
+    oc!(NOP, Implied, 1),
     //0xa4
-    oc!(LDY, ZeroPage),
+    oc!(LDY, ZeroPage, 3),
     //0xa5
-    oc!(LDA, ZeroPage),
+    oc!(LDA, ZeroPage, 3),
     //0xa6
-    oc!(LDX, ZeroPage),
+    oc!(LDX, ZeroPage, 3),
     //0xa7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xa8
-    oc!(TAY, Implied),
+    oc!(TAY, Implied, 2),
     //0xa9
-    oc!(LDA, Immediate),
+    oc!(LDA, Immediate, 2),
     //0xaa
-    oc!(TAX, Implied),
+    oc!(TAX, Implied, 2),
     //0xab
-    None,
+    oc!(NOP, Implied, 1),
     //0xac
-    oc!(LDY, Absolute),
+    oc!(LDY, Absolute, 4),
     //0xad
-    oc!(LDA, Absolute),
+    oc!(LDA, Absolute, 4),
     //0xae
-    oc!(LDX, Absolute),
+    oc!(LDX, Absolute, 4),
     //0xaf
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xb0
-    oc!(BCS, Relative),
+    oc!(BCS, Relative, 2),
     //0xb1
-    oc!(LDA, IndirectY),
+    oc!(LDA, IndirectY, 5, PageCross),
     //0xb2
-    None,
+    oc!(LDA, ZeroPageIndirect, 5),
     //0xb3
-    None,
+    oc!(NOP, Implied, 1),
     //0xb4
-    oc!(LDY, ZeroPageX),
+    oc!(LDY, ZeroPageX, 4),
     //0xb5
-    oc!(LDA, ZeroPageX),
+    oc!(LDA, ZeroPageX, 4),
     //0xb6
-    oc!(LDX, ZeroPageY),
+    oc!(LDX, ZeroPageY, 4),
     //0xb7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xb8
-    oc!(CLV, Implied),
+    oc!(CLV, Implied, 2),
     //0xb9
-    oc!(LDA, AbsoluteY),
+    oc!(LDA, AbsoluteY, 4, PageCross),
     //0xba
-    oc!(TSX, Implied),
+    oc!(TSX, Implied, 2),
     //0xbb
-    None,
+    oc!(NOP, Implied, 1),
     //0xbc
-    oc!(LDY, AbsoluteX),
+    oc!(LDY, AbsoluteX, 4, PageCross),
     //0xbd
-    oc!(LDA, AbsoluteX),
+    oc!(LDA, AbsoluteX, 4, PageCross),
     //0xbe
-    oc!(LDX, AbsoluteY),
+    oc!(LDX, AbsoluteY, 4, PageCross),
     //0xbf
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xc0
-    oc!(CPY, Immediate),
+    oc!(CPY, Immediate, 2),
     //0xc1
-    oc!(CMP, IndirectX),
+    oc!(CMP, IndirectX, 6),
     //0xc2
-    None,
+    oc!(NOP, Immediate, 2),
     //0xc3
-    None,
+    oc!(NOP, Implied, 1),
     //0xc4
-    oc!(CPY, ZeroPage),
+    oc!(CPY, ZeroPage, 3),
     //0xc5
-    oc!(CMP, ZeroPage),
+    oc!(CMP, ZeroPage, 3),
     //0xc6
-    oc!(DEC, ZeroPage),
+    oc!(DEC, ZeroPage, 5),
     //0xc7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xc8
-    oc!(INY, Implied),
+    oc!(INY, Implied, 2),
     //0xc9
-    oc!(CMP, Immediate),
+    oc!(CMP, Immediate, 2),
     //0xca
-    oc!(DEX, Implied),
+    oc!(DEX, Implied, 2),
     //0xcb
-    None,
+    oc!(WAI, Implied, 3),
     //0xcc
-    oc!(CPY, Absolute),
+    oc!(CPY, Absolute, 4),
     //0xcd
-    oc!(CMP, Absolute),
+    oc!(CMP, Absolute, 4),
     //0xce
-    oc!(DEC, Absolute),
+    oc!(DEC, Absolute, 6),
     //0xcf
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xd0
-    oc!(BNE, Relative),
+    oc!(BNE, Relative, 2),
     //0xd1
-    oc!(CMP, IndirectY),
+    oc!(CMP, IndirectY, 5, PageCross),
     //0xd2
-    None,
+    oc!(CMP, ZeroPageIndirect, 5),
     //0xd3
-    None,
+    oc!(NOP, Implied, 1),
     //0xd4
-    oc!(NOP, ZeroPageX),
+    oc!(NOP, ZeroPageX, 4),
     //0xd5
-    oc!(CMP, ZeroPageX),
+    oc!(CMP, ZeroPageX, 4),
     //0xd6
-    oc!(DEC, ZeroPageX),
+    oc!(DEC, ZeroPageX, 6),
     //0xd7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xd8
-    oc!(CLD, Implied),
+    oc!(CLD, Implied, 2),
     //0xd9
-    oc!(CMP, AbsoluteY),
+    oc!(CMP, AbsoluteY, 4, PageCross),
     //0xda
-    None,
+    oc!(PHX, Implied, 3),
     //0xdb
-    None,
+    oc!(STP, Implied, 3),
     //0xdc
-    oc!(NOP, AbsoluteX),
+    oc!(NOP, Absolute, 4),
     //0xdd
-    oc!(CMP, AbsoluteX),
+    oc!(CMP, AbsoluteX, 4, PageCross),
     //0xde
-    oc!(DEC, AbsoluteX),
+    oc!(DEC, AbsoluteX, 7),
     //0xdf
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xe0
-    oc!(CPX, Immediate),
+    oc!(CPX, Immediate, 2),
     //0xe1
-    oc!(SBC, IndirectX),
+    oc!(SBC, IndirectX, 6),
     //0xe2
-    None,
+    oc!(NOP, Immediate, 2),
     //0xe3
-    None,
+    oc!(NOP, Implied, 1),
     //0xe4
-    oc!(CPX, ZeroPage),
+    oc!(CPX, ZeroPage, 3),
     //0xe5
-    oc!(SBC, ZeroPage),
+    oc!(SBC, ZeroPage, 3),
     //0xe6
-    oc!(INC, ZeroPage),
+    oc!(INC, ZeroPage, 5),
     //0xe7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xe8
-    oc!(INX, Implied),
+    oc!(INX, Implied, 2),
     //0xe9
-    oc!(SBC, Immediate),
+    oc!(SBC, Immediate, 2),
     //0xea
-    oc!(NOP, Implied),
+    oc!(NOP, Implied, 2),
     //0xeb
-    None,
+    oc!(NOP, Implied, 1),
     //0xec
-    oc!(CPX, Absolute),
+    oc!(CPX, Absolute, 4),
     //0xed
-    oc!(SBC, Absolute),
+    oc!(SBC, Absolute, 4),
     //0xee
-    oc!(INC, Absolute),
+    oc!(INC, Absolute, 6),
     //0xef
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
     //0xf0
-    oc!(BEQ, Relative),
+    oc!(BEQ, Relative, 2),
     //0xf1
-    oc!(SBC, IndirectY),
+    oc!(SBC, IndirectY, 5, PageCross),
     //0xf2
-    None,
+    oc!(SBC, ZeroPageIndirect, 5),
     //0xf3
-    None,
+    oc!(NOP, Implied, 1),
     //0xf4
-    oc!(NOP, ZeroPageX),
+    oc!(NOP, ZeroPageX, 4),
     //0xf5
-    oc!(SBC, ZeroPageX),
+    oc!(SBC, ZeroPageX, 4),
     //0xf6
-    oc!(INC, ZeroPageX),
+    oc!(INC, ZeroPageX, 6),
     //0xf7
-    None,
+    oc!(SMB, ZeroPage, 5),
     //0xf8
-    oc!(SED, Implied),
+    oc!(SED, Implied, 2),
     //0xf9
-    oc!(SBC, AbsoluteY),
+    oc!(SBC, AbsoluteY, 4, PageCross),
     //0xfa
-    None,
+    oc!(PLX, Implied, 4),
     //0xfb
-    None,
+    oc!(NOP, Implied, 1),
     //0xfc
-    oc!(NOP, AbsoluteX),
+    oc!(NOP, Absolute, 4),
     //0xfd
-    oc!(SBC, AbsoluteX),
+    oc!(SBC, AbsoluteX, 4, PageCross),
     //0xfe
-    oc!(INC, AbsoluteX),
+    oc!(INC, AbsoluteX, 7),
     //0xff
-    None,
+    oc!(BBS, ZeroPageRelative, 5),
 ];
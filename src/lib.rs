@@ -1,25 +1,72 @@
+#![allow(clippy::upper_case_acronyms)] //6502 mnemonics and chip names read better in caps
+
+mod call_stack;
 mod cartridge;
+pub mod cdl;
+pub mod cheats;
 mod controller;
 mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod expression;
+mod history;
+pub mod hooks;
 mod instruction;
 mod memory;
+pub mod nestest;
+pub mod power_on;
 mod ppu;
+pub mod profiler;
+pub mod ram_search;
+#[cfg(test)]
+mod single_step;
+pub mod symbols;
+pub mod trace;
 
+use cdl::{CodeDataLog, SizeMismatch};
+use cheats::{Cheat, CheatEngine};
 use controller::ControllerState;
-use cpu::Cpu;
+use cpu::{Cpu, IrqSource};
+use debugger::{Debugger, StopReason};
+use hooks::{BusObserver, HookId};
+use memory::Domain;
+use power_on::PowerOnState;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use symbols::{Location, SymbolError, Symbols};
+use trace::{TraceFilter, Tracer};
 
 
 pub mod prelude {
     pub use super::controller::ControllerState;
+    pub use super::cpu::IrqSource;
+    pub use super::debugger::{StopReason, WatchKind};
+    pub use super::memory::Domain;
+    pub use super::power_on::PowerOnState;
+    pub use super::trace::TraceFilter;
     pub use super::Emulator;
 }
+
+//The 6502 core on its own, for running other 6502 machines
+pub mod cpu6502 {
+    pub use super::cpu::{Cpu, IrqSource, Registers, Variant};
+    pub use super::memory::{AddressSpace, FlatRam};
+}
 pub struct Emulator {
     cpu: Cpu<memory::Bus>,
     framebuffer: Vec<u32>,
+    debugger: Debugger,
+    cheats: CheatEngine,
+    power_on_state: PowerOnState,
 }
 
 impl Emulator {
     pub fn new(rom_data: Vec<u8>) -> Self {
+        Self::with_power_on_state(rom_data, PowerOnState::default())
+    }
+
+    //Starts with RAM filled as the state says, and fills it the same way on every power cycle
+    pub fn with_power_on_state(rom_data: Vec<u8>, power_on_state: PowerOnState) -> Self {
         let mut rom = cartridge::Cartridge::load(rom_data);
         println!("Mirror mode: {:?}", rom.mirror_mode);
 
@@ -32,24 +79,66 @@ impl Emulator {
             cartridge: rom.take_program_data(),
             ppu,
             controller,
+            record_accesses: false,
+            accesses: Vec::new(),
+            data_bus: 0,
+            oam_dma: None,
+            hooks: hooks::BusHooks::default(),
         };
 
 
         let mut cpu = Cpu::new(bus);
 
-        cpu.reset();
+        power_on_state.apply(&mut cpu.bus);
+        cpu.power_on();
 
         Self {
             cpu,
             framebuffer: vec![0; 256 * 240],
+            debugger: Debugger::new(),
+            cheats: CheatEngine::default(),
+            power_on_state,
         }
     }
 
+    //The state memory comes up in. Random states include the seed
+    pub fn power_on_state(&self) -> PowerOnState {
+        self.power_on_state
+    }
+
+    //Used from the next power_cycle on
+    pub fn set_power_on_state(&mut self, power_on_state: PowerOnState) {
+        self.power_on_state = power_on_state;
+    }
+
+    //Same as pressing the reset button on the console. RAM and most of the PPU keep their contents
+    pub fn reset(&mut self) {
+        self.cpu.bus.ppu.reset();
+        self.cpu.reset();
+        self.debugger.reset();
+    }
+
+    //Cold boot, as if the console had been switched off and on again
+    pub fn power_cycle(&mut self) {
+        self.cpu.bus.ram = memory::Ram::new();
+        self.cpu.bus.data_bus = 0;
+        self.cpu.bus.oam_dma = None;
+        self.cpu.bus.ppu.power_on();
+        self.power_on_state.apply(&mut self.cpu.bus);
+        self.cpu.bus.controller = controller::Controller::new();
+        self.cpu.power_on();
+        self.debugger.reset();
+        self.framebuffer = vec![0; 256 * 240];
+    }
+
     fn step_cycle(&mut self) {
         if self.cpu.bus.ppu.check_nmi() {
             self.cpu.fire_nmi();
         }
 
+        if !self.cpu.bus.hooks.is_empty() {
+            self.cpu.bus.hooks.cycle = self.cpu.cycles();
+        }
         self.cpu.step_cycle();
 
         self.cpu.bus.ppu.step_cycle();
@@ -57,18 +146,130 @@ impl Emulator {
         self.cpu.bus.ppu.step_cycle();
     }
 
-    pub fn run_frame(&mut self) {
+    fn step_instruction(&mut self) {
+        loop {
+            self.step_cycle();
+            if self.cpu.instruction_finished() {
+                return;
+            }
+        }
+    }
+
+    //Runs to the end of the frame, or until the debugger stops it. A stopped frame
+    //carries on from where it left off on the next call
+    pub fn run_frame(&mut self) -> StopReason {
+        self.cpu.bus.record_accesses = self.debugger.watches_bus();
         loop {
+            if self.cpu.instruction_finished() {
+                if let Some(reason) = self.debugger.before_instruction(&self.cpu) {
+                    return reason;
+                }
+            }
+
             self.step_cycle();
+
+            if self.cpu.bus.record_accesses {
+                self.debugger.after_cycle(&self.cpu.bus.accesses);
+                self.cpu.bus.accesses.clear();
+            }
+
             if self.cpu.bus.ppu.show_frame() {
+                self.cheats.freeze(&mut self.cpu.bus);
+
                 //Render frame
-                self.framebuffer = self.cpu.bus.ppu.buffer.clone();
+                self.framebuffer.copy_from_slice(&self.cpu.bus.ppu.buffer);
 
-                return;
+                return StopReason::FrameComplete;
             }
         }
     }
 
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    //Registers, call stack and recent instructions, for when the cpu jams or a game
+    //goes off the rails
+    pub fn crash_report(&self) -> debugger::CrashReport {
+        self.debugger.crash_report(&self.cpu)
+    }
+
+    //Logs every instruction that passes the filter to output, until stop_trace
+    pub fn start_trace(&mut self, output: Box<dyn Write>, filter: TraceFilter) {
+        if let Some(old) = self.debugger.start_trace(Tracer::new(output, filter)) {
+            let _ = old.finish();
+        }
+    }
+
+    //Flushes the trace, and returns the first error writing it if there was one
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.debugger.stop_trace() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    //Flags PRG and CHR bytes by how they're used, until stop_code_data_log. Carries on
+    //with the flags so far if a log is already running
+    pub fn start_code_data_log(&mut self) {
+        if self.debugger.code_data_log().is_none() {
+            let log = CodeDataLog::new(
+                self.cpu.bus.cartridge.size(),
+                self.cpu.bus.ppu.character_data().size(),
+            );
+            self.debugger.start_code_data_log(log);
+        }
+        self.cpu.bus.ppu.character_data().start_log();
+    }
+
+    pub fn stop_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.sync_chr_log();
+        self.cpu.bus.ppu.character_data().stop_log();
+        self.debugger.stop_code_data_log()
+    }
+
+    pub fn code_data_log(&mut self) -> Option<&CodeDataLog> {
+        self.sync_chr_log();
+        self.debugger.code_data_log().map(|log| &*log)
+    }
+
+    //Merges a .cdl file into the log, starting one if needed
+    pub fn load_code_data_log(&mut self, data: &[u8]) -> Result<(), SizeMismatch> {
+        self.start_code_data_log();
+        self.debugger.code_data_log().unwrap().merge_fceux(data)
+    }
+
+    fn sync_chr_log(&mut self) {
+        let chr = self.cpu.bus.ppu.character_data();
+        if let (Some(log), Some(flags)) = (self.debugger.code_data_log(), chr.log()) {
+            log.merge_chr(flags);
+        }
+    }
+
+    pub fn step_into(&mut self) -> StopReason {
+        self.debugger.step_into();
+        self.run_frame()
+    }
+
+    pub fn step_over(&mut self) -> StopReason {
+        self.debugger.step_over();
+        self.run_frame()
+    }
+
+    pub fn step_out(&mut self) -> StopReason {
+        self.debugger.step_out();
+        self.run_frame()
+    }
+
+    //Bus devices hold the shared IRQ line low until they are acknowledged
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.cpu.assert_irq(source);
+    }
+
+    pub fn release_irq(&mut self, source: IrqSource) {
+        self.cpu.release_irq(source);
+    }
+
     pub fn update_controller_state(&mut self, state: ControllerState) {
         self.cpu.bus.controller.update_controller(state);
     }
@@ -77,7 +278,224 @@ impl Emulator {
         &self.framebuffer
     }
 
+    //Disassembles from start up to end without side effects on the bus. Labels from
+    //the debugger's symbols replace the addresses they name
+    pub fn disassemble(&self, start: u16, end: u16) -> Vec<disasm::Disassembly> {
+        let mut instructions = disasm::disassemble(&self.cpu.bus, start, end, self.cpu.variant());
+        for instruction in &mut instructions {
+            instruction.label = self.label(instruction.address).map(String::from);
+            if let Some(name) = instruction.target().and_then(|target| self.label(target)) {
+                instruction.name_target(name);
+            }
+        }
+        instructions
+    }
+
+    //The label of a cpu address, in whichever bank is mapped there now
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.debugger
+            .symbols_ref()
+            .label_at(&self.cpu.bus.cartridge, address)
+    }
+
+    //Source line of the code at a cpu address
+    pub fn source_line(&self, address: u16) -> Option<&symbols::SourceLine> {
+        let location = Symbols::location(&self.cpu.bus.cartridge, address);
+        self.debugger.symbols_ref().source_line(location)
+    }
+
+    //Breaks on a label or file:line, wherever it's mapped. Returns the addresses
+    pub fn add_breakpoint_at(&mut self, name: &str) -> Result<Vec<u16>, SymbolError> {
+        let mut addresses = Vec::new();
+        for location in self.debugger.symbols().resolve(name)? {
+            match location {
+                Location::Cpu(address) => addresses.push(address),
+                Location::Prg(offset) => {
+                    addresses.extend(self.cpu.bus.cartridge.cpu_addresses(offset))
+                }
+            }
+        }
+        for &address in &addresses {
+            self.debugger.add_breakpoint(address);
+        }
+        Ok(addresses)
+    }
+
+    //Calls observer on every access of the kinds given (hooks::READ, WRITE and EXECUTE)
+    //to an address in range. Reads and executes can have their value changed
+    pub fn add_bus_hook(
+        &mut self,
+        kinds: u8,
+        range: RangeInclusive<u16>,
+        observer: impl BusObserver + 'static,
+    ) -> HookId {
+        self.cpu.bus.hooks.add(kinds, range, Box::new(observer))
+    }
+
+    //False if the hook was already gone
+    pub fn remove_bus_hook(&mut self, id: HookId) -> bool {
+        self.cpu.bus.hooks.remove(id)
+    }
+
+    //Applies a cheat until it's disabled or removed. Returns its index in cheats()
+    pub fn add_cheat(&mut self, cheat: Cheat) -> usize {
+        self.cheats.cheats_mut().push(cheat);
+        self.cheats.install(&mut self.cpu.bus.hooks);
+        self.cheats.cheats().len() - 1
+    }
+
+    pub fn remove_cheat(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.cheats.cheats().len() {
+            return None;
+        }
+        let cheat = self.cheats.cheats_mut().remove(index);
+        self.cheats.install(&mut self.cpu.bus.hooks);
+        Some(cheat)
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.cheats_mut().get_mut(index) {
+            cheat.enabled = enabled;
+            self.cheats.install(&mut self.cpu.bus.hooks);
+        }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cheats.cheats()
+    }
+
+    //Memory domains, for looking at memory without the side effects of reading it
+    //through the bus
+    pub fn domain_size(&self, domain: Domain) -> usize {
+        self.cpu.bus.domain_size(domain)
+    }
+
+    pub fn read_domain(&self, domain: Domain, address: usize) -> u8 {
+        self.cpu.bus.read_domain(domain, address)
+    }
+
+    pub fn write_domain(&mut self, domain: Domain, address: usize, byte: u8) {
+        self.cpu.bus.write_domain(domain, address, byte)
+    }
+
+    pub fn dump_domain(&self, domain: Domain) -> Vec<u8> {
+        self.cpu.bus.dump_domain(domain)
+    }
+
+    pub fn hex_dump(&self, domain: Domain, output: &mut dyn Write) -> io::Result<()> {
+        self.cpu.bus.hex_dump(domain, output)
+    }
+
     pub fn nametable_buffer(&mut self) -> Vec<u32> {
         self.cpu.bus.ppu.render_nametable().clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::AddressSpace;
+
+    //NROM-128 image with the program at 0xC000, which is also the reset vector
+    pub(crate) fn test_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0xC0;
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        rom
+    }
+
+    //LDA #$42, STA $10, LDX #$80, TXS, CLI, then JMP to itself
+    const PROGRAM: [u8; 11] = [
+        0xA9, 0x42, 0x85, 0x10, 0xA2, 0x80, 0x9A, 0x58, 0x4C, 0x08, 0xC0,
+    ];
+
+    #[test]
+    fn test_open_bus_reads_address_high_byte() {
+        //LDA $4018 reads back the last byte fetched, the $40 of the operand
+        let mut emu = Emulator::new(test_rom(&[0xAD, 0x18, 0x40, 0xAD, 0x00, 0x60]));
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x40);
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x60);
+    }
+
+    #[test]
+    fn test_oam_dma_reads_through_the_bus() {
+        //LDA #$C0, STA $4014 copies the start of PRG-ROM
+        let program: Vec<u8> = [0xA9, 0xC0, 0x8D, 0x14, 0x40]
+            .iter()
+            .copied()
+            .chain(0..=250)
+            .collect();
+        let mut emu = Emulator::new(test_rom(&program));
+        emu.step_instruction();
+        let cycles = emu.cpu.cycles();
+        emu.step_instruction();
+
+        //4 for the STA, then the DMA
+        let taken = emu.cpu.cycles() - cycles;
+        assert!(taken == 4 + 513 || taken == 4 + 514);
+        let ppu = &mut emu.cpu.bus.ppu;
+        for (i, &byte) in program[..256].iter().enumerate() {
+            ppu.poke(0x2003, i as u8);
+            assert_eq!(ppu.debug_peek(0x2004), byte);
+        }
+        assert_eq!(emu.cpu.registers().pc, 0xC005);
+    }
+
+    #[test]
+    fn test_reset_keeps_ram() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        for _ in 0..5 {
+            emu.step_instruction();
+        }
+        let before = emu.cpu.registers();
+        let cycles = emu.cpu.cycles();
+
+        emu.reset();
+        let after = emu.cpu.registers();
+        assert_eq!(after.pc, 0xC000);
+        assert_eq!(after.s, 0x7D);
+        assert_eq!(after.p, before.p | 0x04);
+        assert_eq!((after.a, after.x, after.y), (before.a, before.x, before.y));
+        assert_eq!(emu.cpu.cycles(), cycles + 7);
+        assert_eq!(emu.cpu.bus.ram.debug_peek(0x10), 0x42);
+    }
+
+    #[test]
+    fn test_power_cycle_matches_cold_boot() {
+        let rom = test_rom(&PROGRAM);
+        let mut emu = Emulator::new(rom.clone());
+        emu.run_frame();
+        emu.reset();
+        emu.power_cycle();
+
+        let mut fresh = Emulator::new(rom);
+        assert_eq!(emu.cpu.registers(), fresh.cpu.registers());
+        assert_eq!(emu.cpu.cycles(), 7);
+        assert_eq!(emu.cpu.bus.ram.debug_peek(0x10), 0);
+
+        emu.run_frame();
+        fresh.run_frame();
+        assert_eq!(emu.cpu.registers(), fresh.cpu.registers());
+        assert_eq!(emu.cpu.cycles(), fresh.cpu.cycles());
+        assert_eq!(emu.buffer(), fresh.buffer());
+    }
+
+    #[test]
+    fn test_power_cycle_drops_pending_oam_dma() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        //Written but not picked up by the cpu yet
+        emu.cpu.bus.poke(0x4014, 0xC0);
+        emu.power_cycle();
+        assert_eq!(emu.cpu.bus.oam_dma, None);
+
+        //No DMA stall before the first instruction
+        emu.step_instruction();
+        assert_eq!(emu.cpu.cycles(), 7 + 2);
+    }
+}
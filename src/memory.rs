@@ -1,16 +1,46 @@
-use std::io::Write;
+use std::fmt;
+use std::io::{self, Write};
 
+use crate::hooks::{AccessKind, BusHooks};
 use crate::ppu;
 use crate::{cartridge, controller};
 
 pub trait AddressSpace {
     fn peek(&mut self, ptr: u16) -> u8;
     fn poke(&mut self, ptr: u16, byte: u8);
+    //Read without any side effects, for tracing and debugging
+    fn debug_peek(&self, ptr: u16) -> u8;
     fn peek_16(&mut self, ptr: u16) -> u16 {
         let byte1 = self.peek(ptr);
         let byte2 = self.peek(ptr + 1);
         u16::from_le_bytes([byte1, byte2])
     }
+    //Page written to $4014, taken by the cpu at the end of the cycle to start OAM DMA
+    fn oam_dma_request(&mut self) -> Option<u8> {
+        None
+    }
+    //Address the APU's sample channel wants the next byte from
+    fn dmc_dma_request(&mut self) -> Option<u16> {
+        None
+    }
+    //Hands the byte fetched for dmc_dma_request back to the APU
+    fn dmc_dma_complete(&mut self, _byte: u8) {}
+    //A read the cpu is about to run as an instruction
+    fn peek_opcode(&mut self, ptr: u16) -> u8 {
+        self.peek(ptr)
+    }
+    //The bytes of an instruction after its opcode
+    fn peek_operand(&mut self, ptr: u16) -> u8 {
+        self.peek(ptr)
+    }
+    //A read the cpu makes on its way to the real one and throws away
+    fn peek_dummy(&mut self, ptr: u16) -> u8 {
+        self.peek(ptr)
+    }
+    //A read by OAM or DMC DMA while the cpu is halted
+    fn peek_dma(&mut self, ptr: u16) -> u8 {
+        self.peek(ptr)
+    }
 }
 
 pub struct Ram {
@@ -23,82 +53,320 @@ impl Ram {
             data: vec![0; 0x0800],
         }
     }
+}
+
+impl AddressSpace for Ram {
+    fn peek(&mut self, ptr: u16) -> u8 {
+        self.data[ptr as usize]
+    }
+
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        self.data[ptr as usize]
+    }
+
+    fn poke(&mut self, ptr: u16, byte: u8) {
+        self.data[ptr as usize] = byte;
+    }
+}
+
+//Plain 64k of RAM with nothing mapped, for running the cpu on its own
+pub struct FlatRam {
+    data: Vec<u8>,
+}
+
+impl FlatRam {
+    pub fn new() -> FlatRam {
+        FlatRam {
+            data: vec![0; 0x10000],
+        }
+    }
 
-    pub fn get_dma_data(&self, start: u8) -> &[u8] {
-        &self.data[(((start as u16) << 8) as usize)..((((start as u16) << 8) + 256) as usize)]
+    //Copies data in starting at address, wrapping around at the top of memory
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.data[address.wrapping_add(offset as u16) as usize] = *byte;
+        }
     }
 }
 
-impl AddressSpace for Ram {
+impl Default for FlatRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddressSpace for FlatRam {
     fn peek(&mut self, ptr: u16) -> u8 {
         self.data[ptr as usize]
     }
 
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        self.data[ptr as usize]
+    }
+
     fn poke(&mut self, ptr: u16, byte: u8) {
         self.data[ptr as usize] = byte;
     }
 }
 
+//What a read was for. Only data reads are the program looking at memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadKind {
+    Data,
+    Opcode,
+    Operand,
+    Dummy,
+    Dma,
+}
+
+//A cpu access, as seen by the debugger's watchpoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusAccess {
+    Read(u16, u8, ReadKind),
+    Write(u16, u8),
+}
+
 pub struct Bus {
     pub ram: Ram,
     pub cartridge: cartridge::ProgramData,
     pub ppu: ppu::PPU,
     pub controller: controller::Controller,
+    pub record_accesses: bool, //Fill accesses, only while something is watching
+    pub accesses: Vec<BusAccess>,
+    pub data_bus: u8,        //Last value read or written, what open bus reads return
+    pub oam_dma: Option<u8>, //Page written to $4014 until the cpu starts the DMA
+    pub hooks: BusHooks,
+}
+
+//Named regions tools can read and write without going through the bus, so nothing
+//the game can see changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Domain {
+    CpuBus, //What the cpu sees, through Bus::debug_peek
+    SystemRam,
+    PrgRom,
+    PrgRam, //Empty unless the iNES header asks for PRG-RAM
+    Chr,
+    Nametables,
+    Oam,
+    Palette,
+}
+
+impl Domain {
+    pub const ALL: [Domain; 8] = [
+        Domain::CpuBus,
+        Domain::SystemRam,
+        Domain::PrgRom,
+        Domain::PrgRam,
+        Domain::Chr,
+        Domain::Nametables,
+        Domain::Oam,
+        Domain::Palette,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Domain::CpuBus => "CPU Bus",
+            Domain::SystemRam => "System RAM",
+            Domain::PrgRom => "PRG-ROM",
+            Domain::PrgRam => "PRG-RAM",
+            Domain::Chr => "CHR",
+            Domain::Nametables => "Nametables",
+            Domain::Oam => "OAM",
+            Domain::Palette => "Palette RAM",
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 impl Bus {
-    #[allow(dead_code)]
-    pub fn debug_print_memory(&mut self) {
-        for address in 0..0xFFFF {
-            let val = self.peek(address as u16);
-            print!("{:#X} : {}, ", address, val);
-            if address % 5 == 0 {
-                print!("\n");
-            }
+    pub fn domain_size(&self, domain: Domain) -> usize {
+        match domain {
+            Domain::CpuBus => 0x10000,
+            _ => self.domain(domain).len(),
+        }
+    }
+
+    //Reads past the end of the domain give 0
+    pub fn read_domain(&self, domain: Domain, address: usize) -> u8 {
+        match domain {
+            Domain::CpuBus if address < 0x10000 => self.debug_peek(address as u16),
+            _ => self.domain(domain).get(address).copied().unwrap_or(0),
         }
     }
-    #[allow(dead_code)]
-    pub fn write_mem(&mut self) {
-        let mut file = std::fs::File::create("mem.txt").unwrap();
-        for address in 0..0x10000 {
-            let val = self.peek(address as u16);
-            file.write_all(format!("{:#X} : {:#X}, ", address, val).as_bytes())
-                .unwrap();
-            if address % 5 == 0 {
-                file.write_all("\n".as_bytes()).unwrap();
+
+    //On the cpu bus only RAM and ROM can be written, registers are left alone
+    pub fn write_domain(&mut self, domain: Domain, address: usize, byte: u8) {
+        let target = match domain {
+            Domain::CpuBus => match address {
+                0x0000..=0x1FFF => self.ram.data.get_mut(address & 0x07FF),
+                0x6000..=0x7FFF => match self.cartridge.ram_offset(address as u16) {
+                    Some(offset) => self.cartridge.ram_mut().get_mut(offset),
+                    None => None,
+                },
+                0x8000..=0xFFFF => match self.cartridge.rom_offset(address as u16) {
+                    Some(offset) => self.cartridge.bytes_mut().get_mut(offset),
+                    None => None,
+                },
+                _ => None,
+            },
+            _ => self.domain_mut(domain).get_mut(address),
+        };
+        if let Some(target) = target {
+            *target = byte;
+        }
+    }
+
+    pub fn dump_domain(&self, domain: Domain) -> Vec<u8> {
+        (0..self.domain_size(domain))
+            .map(|address| self.read_domain(domain, address))
+            .collect()
+    }
+
+    //16 bytes a line, each starting with its address
+    pub fn hex_dump(&self, domain: Domain, output: &mut dyn Write) -> io::Result<()> {
+        for (line, bytes) in self.dump_domain(domain).chunks(16).enumerate() {
+            write!(output, "{:04X}:", line * 16)?;
+            for byte in bytes {
+                write!(output, " {:02X}", byte)?;
             }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+
+    fn domain(&self, domain: Domain) -> &[u8] {
+        match domain {
+            Domain::CpuBus => &[],
+            Domain::SystemRam => &self.ram.data,
+            Domain::PrgRom => self.cartridge.bytes(),
+            Domain::PrgRam => self.cartridge.ram(),
+            Domain::Chr => self.ppu.chr(),
+            Domain::Nametables => self.ppu.nametable_ram(),
+            Domain::Oam => self.ppu.oam(),
+            Domain::Palette => self.ppu.palette_ram(),
+        }
+    }
+
+    fn domain_mut(&mut self, domain: Domain) -> &mut [u8] {
+        match domain {
+            Domain::CpuBus => &mut [],
+            Domain::SystemRam => &mut self.ram.data,
+            Domain::PrgRom => self.cartridge.bytes_mut(),
+            Domain::PrgRam => self.cartridge.ram_mut(),
+            Domain::Chr => self.ppu.character_data().bytes_mut(),
+            Domain::Nametables => self.ppu.nametable_ram_mut(),
+            Domain::Oam => self.ppu.oam_mut(),
+            Domain::Palette => self.ppu.palette_ram_mut(),
         }
     }
 }
 
+//2A03 memory map
+//$0000-$1FFF 2k of RAM, mirrored every $0800
+//$2000-$3FFF PPU registers, mirrored every 8 bytes
+//$4000-$4017 APU and I/O. Only $4015, $4016 and $4017 can be read
+//$4018-$401F APU test registers, disabled on retail consoles
+//$4020-$FFFF Cartridge. PRG-RAM at $6000-$7FFF when it has some, PRG-ROM from $8000
+//Nothing drives the data bus on a read of a write-only or unmapped address, so the
+//cpu sees whatever was last on it (open bus), usually the high byte of the address
+impl Bus {
+    fn read(&mut self, ptr: u16, kind: ReadKind) -> u8 {
+        let mut value = match ptr {
+            0x0000..=0x1FFF => self.ram.peek(ptr & 0x07FF),
+            0x2000..=0x3FFF => self.ppu.peek(0x2000 | (ptr & 0x0007)),
+            //Bit 5 of the APU status isn't driven. There's no APU yet to drive the rest
+            0x4015 => self.data_bus & 0x20,
+            //The controller ports drive the low bits only
+            0x4016 => (self.data_bus & 0xE0) | (self.controller.peek(ptr) & 0x1F),
+            0x4017 => self.data_bus & 0xE0, //Nothing plugged into port 2
+            0x4020..=0xFFFF if self.cartridge.drives(ptr) => self.cartridge.peek(ptr),
+            _ => self.data_bus,
+        };
+        if !self.hooks.is_empty() {
+            let hook_kind = match kind {
+                ReadKind::Opcode => AccessKind::Execute,
+                _ => AccessKind::Read,
+            };
+            value = self.hooks.access(hook_kind, ptr, value);
+        }
+        //$4015 is read inside the 2A03, the external bus keeps its old value
+        if ptr != 0x4015 {
+            self.data_bus = value;
+        }
+        if self.record_accesses {
+            self.accesses.push(BusAccess::Read(ptr, value, kind));
+        }
+        value
+    }
+}
+
 impl AddressSpace for Bus {
     fn peek(&mut self, ptr: u16) -> u8 {
+        self.read(ptr, ReadKind::Data)
+    }
+
+    fn peek_opcode(&mut self, ptr: u16) -> u8 {
+        self.read(ptr, ReadKind::Opcode)
+    }
+
+    fn peek_operand(&mut self, ptr: u16) -> u8 {
+        self.read(ptr, ReadKind::Operand)
+    }
+
+    fn peek_dummy(&mut self, ptr: u16) -> u8 {
+        self.read(ptr, ReadKind::Dummy)
+    }
+
+    fn peek_dma(&mut self, ptr: u16) -> u8 {
+        self.read(ptr, ReadKind::Dma)
+    }
+
+    fn debug_peek(&self, ptr: u16) -> u8 {
         match ptr {
-            0x4016 => self.controller.peek(ptr), //Controller port 1
-            0x4017 => 0,                         //Empty controller 2 hack
-            0x0000..=0x07FF => self.ram.peek(ptr),
-            0x2000..=0x2007 => self.ppu.peek(ptr),
-            0x4020..=0xFFFF => self.cartridge.peek(ptr),
-            _ => 0,
+            0x0000..=0x1FFF => self.ram.debug_peek(ptr & 0x07FF),
+            0x2000..=0x3FFF => self.ppu.debug_peek(0x2000 | (ptr & 0x0007)),
+            0x4015 => self.data_bus & 0x20,
+            0x4016 => (self.data_bus & 0xE0) | (self.controller.debug_peek(ptr) & 0x1F),
+            0x4017 => self.data_bus & 0xE0,
+            0x4020..=0xFFFF if self.cartridge.drives(ptr) => self.cartridge.debug_peek(ptr),
+            _ => self.data_bus,
         }
     }
 
     fn poke(&mut self, ptr: u16, byte: u8) {
+        self.data_bus = byte;
+        if !self.hooks.is_empty() {
+            self.hooks.access(AccessKind::Write, ptr, byte);
+        }
+        if self.record_accesses {
+            self.accesses.push(BusAccess::Write(ptr, byte));
+        }
         match ptr {
-            0x4014 => {
-                //OAM DMA
-                self.ppu.write_dma(self.ram.get_dma_data(byte));
-            }
+            0x0000..=0x1FFF => self.ram.poke(ptr & 0x07FF, byte),
+            0x2000..=0x3FFF => self.ppu.poke(0x2000 | (ptr & 0x0007), byte),
+            //OAM DMA. The cpu does the copy, a byte every other cycle
+            0x4014 => self.oam_dma = Some(byte),
             0x4016 => self.controller.poke(ptr, byte),
-            0x0000..=0x07FF => self.ram.poke(ptr, byte),
-            0x2000..=0x2007 => self.ppu.poke(ptr, byte),
             0x4020..=0xFFFF => self.cartridge.poke(ptr, byte),
+            //The APU registers and the frame counter at $4017 go nowhere until there's
+            //an APU, the test registers are disabled
             _ => (),
         }
     }
+
+    fn oam_dma_request(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
 }
 
 //Returns the value given as address % 255
+#[allow(dead_code)]
 pub struct TestBus;
 
 impl AddressSpace for TestBus {
@@ -106,6 +374,10 @@ impl AddressSpace for TestBus {
         (ptr % 255) as u8
     }
 
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        (ptr % 255) as u8
+    }
+
     fn poke(&mut self, _ptr: u16, _byte: u8) {}
 }
 
@@ -127,7 +399,7 @@ mod tests {
     #[test]
     fn test_testbus() {
         let mut bus = TestBus;
-        assert_eq!(bus.peek(256), 1 as u8);
+        assert_eq!(bus.peek(256), 1_u8);
     }
 
     #[test]
@@ -136,6 +408,151 @@ mod tests {
         assert_eq!(bus.peek_16(0x20), 0x2120);
     }
 
+    #[test]
+    fn test_flat_ram_load_wraps() {
+        let mut ram = FlatRam::new();
+        ram.load(0xFFFF, &[0x12, 0x34]);
+        assert_eq!(ram.peek(0xFFFF), 0x12);
+        assert_eq!(ram.peek(0x0000), 0x34);
+    }
+
+    fn bus() -> Bus {
+        bus_from(crate::tests::test_rom(&[]))
+    }
+
+    fn bus_from(rom: Vec<u8>) -> Bus {
+        let mut rom = cartridge::Cartridge::load(rom);
+        Bus {
+            ram: Ram::new(),
+            cartridge: rom.take_program_data(),
+            ppu: ppu::PPU::new(rom.take_character_data()),
+            controller: controller::Controller::new(),
+            record_accesses: false,
+            accesses: Vec::new(),
+            data_bus: 0,
+            oam_dma: None,
+            hooks: BusHooks::default(),
+        }
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let mut bus = bus();
+        bus.poke(0x0801, 0x42);
+        assert_eq!(bus.peek(0x0001), 0x42);
+        assert_eq!(bus.peek(0x1801), 0x42);
+
+        //$3FF8 is $2000, PPUCTRL
+        bus.poke(0x3FF8, 0x80);
+        assert_eq!(bus.debug_peek(0x2000), 0x80);
+        assert_eq!(bus.debug_peek(0x2008), 0x80);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = bus();
+        bus.poke(0x0010, 0xA5);
+        for address in [0x4000, 0x4014, 0x4018, 0x401F, 0x5000, 0x7FFF] {
+            assert_eq!(bus.peek(address), 0xA5, "${:04X}", address);
+        }
+        //Only the undriven bits
+        assert_eq!(bus.peek(0x4017), 0xA0);
+        assert_eq!(bus.peek(0x4015), 0x20);
+        assert_eq!(bus.debug_peek(0x4018), 0xA0);
+
+        //Writes to the APU and test registers land nowhere
+        bus.poke(0x4018, 0x12);
+        assert_eq!(bus.peek(0x4018), 0x12);
+    }
+
+    #[test]
+    fn test_apu_status_read_leaves_open_bus() {
+        //LDA $4015, the last thing on the bus is the $40 operand
+        let mut emu = crate::Emulator::new(crate::tests::test_rom(&[0xAD, 0x15, 0x40]));
+        emu.step_instruction();
+        assert_eq!(emu.cpu.registers().a, 0x00);
+        assert_eq!(emu.cpu.bus.peek(0x4018), 0x40);
+    }
+
+    #[test]
+    fn test_domains_have_no_side_effects() {
+        let mut bus = bus();
+        bus.poke(0x2006, 0x21);
+        bus.poke(0x2006, 0x00);
+        bus.poke(0x2007, 0x55);
+        bus.poke(0x2006, 0x21);
+        bus.poke(0x2006, 0x00);
+
+        for domain in Domain::ALL {
+            bus.dump_domain(domain);
+        }
+        //The PPUDATA address didn't move
+        assert_eq!(bus.peek(0x2007), 0x55);
+        assert_eq!(bus.read_domain(Domain::Nametables, 0x100), 0x55);
+    }
+
+    #[test]
+    fn test_domain_sizes_and_writes() {
+        let mut bus = bus();
+        let sizes: Vec<usize> = Domain::ALL
+            .iter()
+            .map(|&domain| bus.domain_size(domain))
+            .collect();
+        assert_eq!(
+            sizes,
+            [0x10000, 0x800, 0x4000, 0, 0x2000, 0x800, 0x100, 0x20]
+        );
+
+        bus.write_domain(Domain::CpuBus, 0x1801, 0x42);
+        assert_eq!(bus.read_domain(Domain::SystemRam, 0x0001), 0x42);
+        //The 16k of PRG-ROM shows up twice
+        bus.write_domain(Domain::CpuBus, 0xC010, 0x99);
+        assert_eq!(bus.read_domain(Domain::PrgRom, 0x0010), 0x99);
+        assert_eq!(bus.read_domain(Domain::CpuBus, 0x8010), 0x99);
+        //Registers aren't memory
+        bus.write_domain(Domain::CpuBus, 0x2000, 0x80);
+        assert_eq!(bus.read_domain(Domain::CpuBus, 0x2000), 0x00);
+
+        bus.write_domain(Domain::Oam, 0xFF, 0x12);
+        bus.write_domain(Domain::Oam, 0x100, 0x34);
+        assert_eq!(bus.read_domain(Domain::Oam, 0xFF), 0x12);
+        assert_eq!(bus.read_domain(Domain::Oam, 0x100), 0x00);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut rom = crate::tests::test_rom(&[]);
+        rom[6] |= 0b00000010; //Battery
+        let mut bus = bus_from(rom);
+        assert_eq!(bus.domain_size(Domain::PrgRam), 0x2000);
+
+        bus.poke(0x6000, 0x12);
+        bus.poke(0x7FFF, 0x34);
+        assert_eq!(bus.peek(0x6000), 0x12);
+        assert_eq!(bus.peek(0x7FFF), 0x34);
+        assert_eq!(bus.read_domain(Domain::PrgRam, 0x1FFF), 0x34);
+        bus.write_domain(Domain::CpuBus, 0x6001, 0x56);
+        assert_eq!(bus.read_domain(Domain::PrgRam, 0x0001), 0x56);
+
+        //A size in byte 8 asks for it too
+        let mut rom = crate::tests::test_rom(&[]);
+        rom[8] = 1;
+        assert_eq!(bus_from(rom).domain_size(Domain::PrgRam), 0x2000);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let mut bus = bus();
+        bus.write_domain(Domain::Palette, 0x11, 0x0F);
+        let mut output = Vec::new();
+        bus.hex_dump(Domain::Palette, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0000: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+             0010: 00 0F 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n"
+        );
+    }
+
     #[test]
     fn test_relative_address() {
         assert_eq!(relative_address(10, 1000), 1010);
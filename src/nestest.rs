@@ -0,0 +1,176 @@
+//Conformance run against kevtris' nestest rom
+//https://www.qmtpro.com/~nes/misc/nestest.txt
+//
+//The rom is started at 0xC000 ("automation mode"), which runs every test without a ppu
+//and leaves the results in 0x02 and 0x03. A trace line is produced before each
+//instruction and compared to the matching line of nestest.log.
+//
+//The nestest.log shipped here only has the pc, disassembly and registers, so only the
+//pc and registers are checked against it. Logs with PPU: and CYC: columns get those
+//compared too.
+
+use crate::symbols::Symbols;
+use crate::trace;
+use crate::Emulator;
+use std::collections::VecDeque;
+use std::fmt;
+
+//Number of matching lines kept to give some context on a mismatch
+const HISTORY_LENGTH: usize = 5;
+
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+    pub history: Vec<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nestest mismatch on line {}", self.line)?;
+        for line in &self.history {
+            writeln!(f, "          {}", line)?;
+        }
+        writeln!(f, "expected: {}", self.expected)?;
+        write!(f, "actual:   {}", self.actual)
+    }
+}
+
+impl fmt::Debug for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+//Runs the rom in automation mode, checking one instruction per line of the log.
+//Returns the number of lines that matched, or the first line that didn't
+pub fn run(rom_data: Vec<u8>, log: &[&str]) -> Result<usize, Mismatch> {
+    let mut emu = Emulator::new(rom_data);
+    emu.cpu.set_pc(0xC000);
+
+    let mut history = VecDeque::with_capacity(HISTORY_LENGTH);
+
+    for (index, expected) in log.iter().enumerate() {
+        let actual = trace_line(&emu);
+        if !lines_match(expected, &actual) {
+            return Err(Mismatch {
+                line: index + 1,
+                expected: expected.to_string(),
+                actual,
+                history: history.into_iter().collect(),
+            });
+        }
+
+        if history.len() == HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(actual);
+
+        emu.step_instruction();
+    }
+
+    Ok(log.len())
+}
+
+//Formats the instruction about to be executed the same way nestest.log does
+pub fn trace_line(emu: &Emulator) -> String {
+    trace::trace_line(&emu.cpu, &Symbols::new())
+}
+
+//Compares the register columns, and the ppu and cycle columns when the log has them.
+//The disassembly is only there for reading, logs in the wild don't agree on its format
+fn lines_match(expected: &str, actual: &str) -> bool {
+    let expected_fields = TraceFields::parse(expected);
+    let actual_fields = TraceFields::parse(actual);
+
+    match (expected_fields, actual_fields) {
+        (Some(expected), Some(actual)) => {
+            expected.pc == actual.pc
+                && expected.registers == actual.registers
+                && (expected.ppu.is_none() || expected.ppu == actual.ppu)
+                && (expected.cycles.is_none() || expected.cycles == actual.cycles)
+        }
+        _ => false,
+    }
+}
+
+struct TraceFields<'a> {
+    pc: &'a str,
+    registers: &'a str,
+    ppu: Option<&'a str>,
+    cycles: Option<&'a str>,
+}
+
+impl<'a> TraceFields<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let pc = line.get(..4)?;
+        let registers_start = line.find("A:")?;
+        let rest = &line[registers_start..];
+
+        let ppu_start = rest.find("PPU:");
+        let cycles_start = rest.find("CYC:");
+        let registers_end = ppu_start.or(cycles_start).unwrap_or(rest.len());
+
+        Some(Self {
+            pc,
+            registers: rest[..registers_end].trim(),
+            ppu: ppu_start.map(|start| rest[start + 4..cycles_start.unwrap_or(rest.len())].trim()),
+            cycles: cycles_start.map(|start| rest[start + 4..].trim()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = include_str!("../nestest.log");
+
+    //nestest.nes isn't distributed with the repo. Put it next to nestest.log or point
+    //NESTEST_ROM at it and run cargo test -- --ignored
+    fn load_rom() -> Vec<u8> {
+        let path = std::env::var("NESTEST_ROM")
+            .unwrap_or_else(|_| concat!(env!("CARGO_MANIFEST_DIR"), "/nestest.nes").to_string());
+        std::fs::read(&path).unwrap_or_else(|e| panic!("Can't read {}: {}", path, e))
+    }
+
+    #[test]
+    #[ignore = "needs nestest.nes, see load_rom"]
+    fn test_nestest_log() {
+        let rom = load_rom();
+
+        let log: Vec<&str> = LOG.lines().collect();
+        if let Err(mismatch) = run(rom, &log) {
+            panic!("{}", mismatch);
+        }
+    }
+
+    #[test]
+    fn test_lines_match_ignores_disassembly() {
+        let expected = "CFDB LDA $200 = 5A                   A:5D X:00 Y:69 P:27 SP:FB";
+        let actual = "CFDB  A1 80     LDA ($80,X) @ 80 = 0200 = 5A    A:5D X:00 Y:69 P:27 SP:FB PPU:  0,  0 CYC:7";
+        assert!(lines_match(expected, actual));
+    }
+
+    #[test]
+    fn test_lines_match_registers() {
+        let expected = "C72E SEC                             A:00 X:00 Y:00 P:26 SP:FB";
+        let actual = "C72E  38        SEC                             A:00 X:00 Y:00 P:27 SP:FB PPU:  0,  0 CYC:7";
+        assert!(!lines_match(expected, actual));
+    }
+
+    #[test]
+    fn test_lines_match_cycles() {
+        let expected = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let actual = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:8";
+        assert!(!lines_match(expected, actual));
+    }
+
+    #[test]
+    fn test_lines_match_ppu() {
+        let expected = "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0,300 CYC:100";
+        assert!(lines_match(expected, expected));
+        let actual = "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  1, 44 CYC:100";
+        assert!(!lines_match(expected, actual));
+    }
+}
@@ -0,0 +1,168 @@
+//What CPU RAM, nametable RAM, OAM and palette RAM hold at power on. Real consoles come up
+//with semi-random contents, so games that read memory before writing it can behave
+//differently from one boot to the next. A random state keeps its seed, so a run can
+//always be repeated exactly
+
+use crate::memory::{Bus, Domain};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Filled in this order, so a seed always gives the same contents
+const DOMAINS: [Domain; 4] = [
+    Domain::SystemRam,
+    Domain::Nametables,
+    Domain::Oam,
+    Domain::Palette,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerOnState {
+    #[default]
+    Zeros,
+    Ones,        //Every byte $FF
+    Pattern,     //4 bytes of $00 then 4 of $FF, what FCEUX does
+    Random(u64), //Seed
+}
+
+impl PowerOnState {
+    //Random contents with a seed from the clock
+    pub fn random() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        PowerOnState::Random(nanos)
+    }
+
+    pub(crate) fn apply(self, bus: &mut Bus) {
+        let mut rng = SplitMix64(match self {
+            PowerOnState::Random(seed) => seed,
+            _ => 0,
+        });
+        for &domain in DOMAINS.iter() {
+            for address in 0..bus.domain_size(domain) {
+                let byte = match self {
+                    PowerOnState::Zeros => 0x00,
+                    PowerOnState::Ones => 0xFF,
+                    PowerOnState::Pattern if address & 4 == 0 => 0x00,
+                    PowerOnState::Pattern => 0xFF,
+                    PowerOnState::Random(_) => rng.next() as u8,
+                };
+                //Palette entries are 6 bits
+                let byte = match domain {
+                    Domain::Palette => byte & 0x3F,
+                    _ => byte,
+                };
+                bus.write_domain(domain, address, byte);
+            }
+        }
+    }
+}
+
+//The same text FromStr takes, so a random run can be repeated from its log
+impl fmt::Display for PowerOnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerOnState::Zeros => write!(f, "zeros"),
+            PowerOnState::Ones => write!(f, "ff"),
+            PowerOnState::Pattern => write!(f, "pattern"),
+            PowerOnState::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+//zeros, ff, pattern, random for a new seed or random:SEED
+impl FromStr for PowerOnState {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "zeros" => Ok(PowerOnState::Zeros),
+            "ff" => Ok(PowerOnState::Ones),
+            "pattern" => Ok(PowerOnState::Pattern),
+            "random" => Ok(PowerOnState::random()),
+            _ => text
+                .strip_prefix("random:")
+                .and_then(|seed| seed.parse().ok())
+                .map(PowerOnState::Random)
+                .ok_or_else(|| format!("Unknown power on state {}", text)),
+        }
+    }
+}
+
+//http://xoshiro.di.unimi.it/splitmix64.c
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+
+    fn memory(emu: &Emulator) -> Vec<Vec<u8>> {
+        DOMAINS
+            .iter()
+            .map(|&domain| emu.dump_domain(domain))
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_states() {
+        let emu = Emulator::new(test_rom(&[]));
+        assert!(memory(&emu).iter().flatten().all(|&byte| byte == 0));
+
+        let emu = Emulator::with_power_on_state(test_rom(&[]), PowerOnState::Ones);
+        assert_eq!(emu.read_domain(Domain::SystemRam, 0x07FF), 0xFF);
+        assert_eq!(emu.read_domain(Domain::Oam, 0x00), 0xFF);
+        assert_eq!(emu.read_domain(Domain::Palette, 0x1F), 0x3F);
+
+        let emu = Emulator::with_power_on_state(test_rom(&[]), PowerOnState::Pattern);
+        assert_eq!(
+            emu.dump_domain(Domain::SystemRam)[..12],
+            [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_random_state_repeats() {
+        let state = PowerOnState::Random(1234);
+        let mut emu = Emulator::with_power_on_state(test_rom(&[]), state);
+        let first = memory(&emu);
+        assert!(first[0].iter().any(|&byte| byte != first[0][0]));
+        assert!(first[3].iter().all(|&byte| byte <= 0x3F));
+
+        emu.write_domain(Domain::SystemRam, 0x10, !first[0][0x10]);
+        emu.power_cycle();
+        assert_eq!(memory(&emu), first);
+        assert_eq!(emu.power_on_state(), state);
+
+        emu.set_power_on_state(PowerOnState::Random(1235));
+        emu.power_cycle();
+        assert_ne!(memory(&emu), first);
+    }
+
+    #[test]
+    fn test_parse() {
+        for state in [
+            PowerOnState::Zeros,
+            PowerOnState::Ones,
+            PowerOnState::Pattern,
+            PowerOnState::Random(42),
+        ] {
+            assert_eq!(state.to_string().parse(), Ok(state));
+        }
+        assert!(matches!("random".parse(), Ok(PowerOnState::Random(_))));
+        assert!("random:x".parse::<PowerOnState>().is_err());
+    }
+}
@@ -39,6 +39,8 @@ pub struct PPU {
     scroll_y: u16,
 
     tmp_nametable: u8,
+
+    frame: u64, //Frames shown since power on
 }
 
 impl PPU {
@@ -65,9 +67,40 @@ impl PPU {
             scroll_x: 0,
             scroll_y: 0,
             tmp_nametable: 0,
+            frame: 0,
         }
     }
 
+    //Reset button. The registers written at $2000, $2001 and $2005 are cleared along with
+    //the write latch, memory and the rest of the state survive
+    pub fn reset(&mut self) {
+        self.ppuctrl = 0;
+        self.ppumask = 0;
+        self.ppuscroll = 0;
+        self.addr_latch = false;
+        self.scroll_latch = false;
+        self.scroll_x = 0;
+        self.scroll_y = 0;
+        self.tmp_nametable = 0;
+        self.nmi_fired = false;
+        self.x = 0;
+        self.y = 0;
+    }
+
+    //Everything apart from the cartridge's character data goes back to the power on state
+    pub fn power_on(&mut self) {
+        self.reset();
+        self.ppustatus = 0;
+        self.frame = 0;
+        self.oamaddr = 0;
+        self.ppuaddr = 0;
+        self.ppuaddr_address = 0;
+        self.vram.iter_mut().for_each(|byte| *byte = 0);
+        self.oam_mem.iter_mut().for_each(|byte| *byte = 0);
+        self.palette_ram.iter_mut().for_each(|byte| *byte = 0);
+        self.buffer.iter_mut().for_each(|pixel| *pixel = 0);
+    }
+
     
 
     pub fn step_cycle(&mut self) {
@@ -96,10 +129,10 @@ impl PPU {
 
             let offset = calculate_nametable_offset(self.x, self.y, self.scroll_x, self.scroll_y, base_offset);
 
-            let col = (((self.x + self.scroll_x) % 256) / 8) as u16;
-            let row = (((self.y + self.scroll_y) % 240) / 8) as u16;
+            let col = ((self.x + self.scroll_x) % 256) / 8;
+            let row = ((self.y + self.scroll_y) % 240) / 8;
         
-            let addr = ((row * 32) + col) as u16 + offset;
+            let addr = ((row * 32) + col) + offset;
 
             let tile_val = self.peek_vram(addr);
             let tcol = tile_val & 0xF;
@@ -161,18 +194,64 @@ impl PPU {
                 self.peek_vram(0x3F00) //Universal background
             } else {
                 match location {
-                    PaletteRam::Background0 => self.peek_vram((0x3F00 + val) as u16),
-                    PaletteRam::Background1 => self.peek_vram((0x3F04 + val) as u16),
-                    PaletteRam::Background2 => self.peek_vram((0x3F08 + val) as u16),
-                    PaletteRam::Background3 => self.peek_vram((0x3F0C + val) as u16),
-                    PaletteRam::Sprite0 => self.peek_vram((0x3F10 + val) as u16),
-                    PaletteRam::Sprite1 => self.peek_vram((0x3F14 + val) as u16),
-                    PaletteRam::Sprite2 => self.peek_vram((0x3F18 + val) as u16),
-                    PaletteRam::Sprite3 => self.peek_vram((0x3F1C + val) as u16),
+                    PaletteRam::Background0 => self.peek_vram(0x3F00 + val),
+                    PaletteRam::Background1 => self.peek_vram(0x3F04 + val),
+                    PaletteRam::Background2 => self.peek_vram(0x3F08 + val),
+                    PaletteRam::Background3 => self.peek_vram(0x3F0C + val),
+                    PaletteRam::Sprite0 => self.peek_vram(0x3F10 + val),
+                    PaletteRam::Sprite1 => self.peek_vram(0x3F14 + val),
+                    PaletteRam::Sprite2 => self.peek_vram(0x3F18 + val),
+                    PaletteRam::Sprite3 => self.peek_vram(0x3F1C + val),
                 }
             }
         };
-        PALETTE[color_code as usize]
+        PALETTE[(color_code & 0x3F) as usize]
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.y
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.x
+    }
+
+    pub fn character_data(&mut self) -> &mut CharacterData {
+        &mut self.character_data
+    }
+
+    //The cartridge's pattern tables, without flagging anything in the code/data log
+    pub fn chr(&self) -> &[u8] {
+        self.character_data.bytes()
+    }
+
+    //The 2k of nametable RAM inside the console, before mirroring
+    pub fn nametable_ram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn nametable_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam_mem
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.oam_mem
+    }
+
+    pub fn palette_ram(&self) -> &[u8] {
+        &self.palette_ram
+    }
+
+    pub fn palette_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.palette_ram
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
     }
 
     pub fn check_nmi(&mut self) -> bool {
@@ -225,6 +304,7 @@ impl PPU {
                 );
             }
 
+            self.frame += 1;
             true
         } else {
             false
@@ -271,6 +351,9 @@ impl PPU {
     }
 
     fn peek_vram(&self, ptr: u16) -> u8 {
+        if ptr < 0x2000 {
+            return self.character_data.debug_peek(ptr);
+        }
         match self.character_data.mirror {
             MirrorMode::Vertical => match ptr {
                 0x2000..=0x23FF => self.vram[(ptr - 0x2000) as usize],
@@ -336,6 +419,7 @@ impl PPU {
         self.buffer[index] = color;
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_tile(
         &mut self,
         half: TableHalf,
@@ -372,7 +456,7 @@ impl PPU {
 
     fn check_sprite_hit(&mut self) -> bool {
         let moam = self.oam_mem.clone();
-        let sprite = moam.chunks(4).nth(0).unwrap().clone();
+        let sprite = moam.chunks(4).nth(0).unwrap();
         let tcol = sprite[1] & 0xF;
         let trow = sprite[1] >> 4;
         let half = match self.ppuctrl.get_bit(3) {
@@ -380,8 +464,8 @@ impl PPU {
             false => TableHalf::Left,
         };
 
-        let mx = self.x.clone() as u8;
-        let my = self.y.clone() as u8;
+        let mx = self.x as u8;
+        let my = self.y as u8;
 
         let opaque = self.get_background_pixel_value(
             half,
@@ -423,11 +507,6 @@ impl PPU {
         lower_bit | (upper_bit << 1)
     }
 
-    pub fn write_dma(&mut self, data: &[u8]) {
-        //self.oam_mem.clear();
-        self.oam_mem.copy_from_slice(data);
-    }
-
     pub fn render_nametable(&mut self) -> Vec<u32> {
         let mut buffer = vec![0; 512 * 480];
 
@@ -438,7 +517,10 @@ impl PPU {
 
         //let nametable = self.ppuctrl & 0x3;
 
-        for index in 0..buffer.len() {
+        //The viewer isn't the game drawing, keep it out of the code/data log
+        let log = self.character_data.stop_log();
+
+        for pixel in buffer.iter_mut() {
             let mx = (self.x + self.scroll_x.saturating_sub(256)) % 512;
             let my = (self.y + self.scroll_y.saturating_sub(256)) % 512;
 
@@ -457,7 +539,7 @@ impl PPU {
                 (true, true) => 0x2c00,
             };
 
-            let addr = (((self.y / 8) * 32) + (self.x / 8)) as u16 + offset;
+            let addr = (((self.y / 8) * 32) + (self.x / 8)) + offset;
 
             let tile_val = self.peek_vram(addr);
             let tcol = tile_val & 0xF;
@@ -478,8 +560,9 @@ impl PPU {
             );
 
             let color = self.get_palette_color(&palette_segment, val as u16);
-            buffer[index] = color;
+            *pixel = color;
         }
+        self.character_data.restore_log(log);
         buffer
     }
 }
@@ -505,13 +588,31 @@ impl AddressSpace for PPU {
             0x2005 => self.ppuscroll,
             0x2006 => self.ppuaddr,
             0x2007 => {
+                let address = self.ppuaddr_address;
                 self.ppuaddr_address += 1;
-                self.peek_vram(self.ppuaddr_address - 1)
+                match address {
+                    0x0000..=0x1FFF => self.character_data.read(address),
+                    _ => self.peek_vram(address),
+                }
             }
             _ => 0,
         }
     }
 
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        match ptr {
+            0x2000 => self.ppuctrl,
+            0x2001 => self.ppumask,
+            0x2002 => self.ppustatus,
+            0x2003 => self.oamaddr,
+            0x2004 => self.oam_mem[self.oamaddr as usize],
+            0x2005 => self.ppuscroll,
+            0x2006 => self.ppuaddr,
+            0x2007 => self.peek_vram(self.ppuaddr_address),
+            _ => 0,
+        }
+    }
+
     fn poke(&mut self, ptr: u16, byte: u8) {
         match ptr {
             0x2000 => {
@@ -526,7 +627,7 @@ impl AddressSpace for PPU {
             0x2004 => {
                 //OAMDATA
                 self.oam_mem[self.oamaddr as usize] = byte;
-                self.oamaddr += 1;
+                self.oamaddr = self.oamaddr.wrapping_add(1);
             }
             0x2005 => {
                 if self.scroll_latch {
@@ -614,15 +715,15 @@ fn map_tile_address(
 }
 
 fn calculate_nametable_offset(x: u16, y: u16, scroll_x: u16, scroll_y: u16, base_offset: u16) -> u16 {
-    let sx = x as u16 + scroll_x as u16;
-    let sy = y as u16 + scroll_y as u16;
+    let sx = x + scroll_x;
+    let sy = y + scroll_y;
 
     (match (sx >= 256, sy >= 240) {
         (false, false) => 0x0,
         (true, false) => 0x400,
         (false, true) => 0x800,
         (true, true) => 0xc00,
-    } + base_offset) as u16
+    } + base_offset)
 }
 
 #[cfg(test)]
@@ -0,0 +1,439 @@
+//Cycle profiler. Every instruction's cycles are charged to its pc and to the routines
+//on the shadow call stack: exclusive to the innermost one, inclusive to all of them.
+//Routines are JSR targets and interrupt handlers, anything outside them is main. The
+//cycles of an interrupt sequence go to the handler it enters
+
+use crate::call_stack::{CallFrame, FrameKind};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write};
+
+const DEFAULT_FRAMES: usize = 600;
+const HOT_INSTRUCTIONS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Routine {
+    Main,
+    Subroutine(u16),
+    Nmi(u16),
+    Irq(u16),
+    Brk(u16),
+}
+
+impl From<&CallFrame> for Routine {
+    fn from(frame: &CallFrame) -> Self {
+        match frame.kind {
+            FrameKind::Subroutine => Routine::Subroutine(frame.target),
+            FrameKind::Nmi => Routine::Nmi(frame.target),
+            FrameKind::Irq => Routine::Irq(frame.target),
+            FrameKind::Brk => Routine::Brk(frame.target),
+        }
+    }
+}
+
+impl Routine {
+    pub fn entry(&self) -> Option<u16> {
+        match *self {
+            Routine::Main => None,
+            Routine::Subroutine(entry)
+            | Routine::Nmi(entry)
+            | Routine::Irq(entry)
+            | Routine::Brk(entry) => Some(entry),
+        }
+    }
+}
+
+//No spaces or semicolons, so the names work in collapsed stacks
+impl fmt::Display for Routine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Routine::Main => write!(f, "main"),
+            Routine::Subroutine(entry) => write!(f, "sub_{:04X}", entry),
+            Routine::Nmi(entry) => write!(f, "nmi_{:04X}", entry),
+            Routine::Irq(entry) => write!(f, "irq_{:04X}", entry),
+            Routine::Brk(entry) => write!(f, "brk_{:04X}", entry),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub routine: Routine,
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+//Where the cycles of one PPU frame went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameTime {
+    pub frame: u64,
+    pub nmi: u64,  //In the NMI handler and what it calls
+    pub irq: u64,  //In an IRQ handler outside NMI
+    pub main: u64, //Everything else
+}
+
+//The instruction whose cycles are still being counted
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    Instruction { pc: u16, cycle: u64, frame: u64 },
+    Interrupt { cycle: u64, frame: u64 },
+}
+
+pub struct Profiler {
+    by_pc: Vec<u64>,
+    routines: HashMap<Routine, RoutineStats>,
+    stacks: HashMap<Vec<Routine>, u64>,
+    names: HashMap<Routine, String>, //Labels of the routines that have them
+    frames: VecDeque<FrameTime>,
+    frame_history: usize,
+    total: u64,
+    stack: Vec<Routine>, //Routines the pending instruction runs in, outermost first
+    pending: Option<Pending>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            by_pc: vec![0; 0x10000],
+            routines: HashMap::new(),
+            stacks: HashMap::new(),
+            names: HashMap::new(),
+            frames: VecDeque::with_capacity(DEFAULT_FRAMES),
+            frame_history: DEFAULT_FRAMES,
+            total: 0,
+            stack: vec![Routine::Main],
+            pending: None,
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //How many frames of FrameTime are kept
+    pub fn set_frame_history(&mut self, frames: usize) {
+        self.frame_history = frames;
+        while self.frames.len() > frames {
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+
+    pub fn cycles_at(&self, pc: u16) -> u64 {
+        self.by_pc[pc as usize]
+    }
+
+    //Most inclusive cycles first
+    pub fn routines(&self) -> Vec<RoutineStats> {
+        let mut routines: Vec<RoutineStats> = self.routines.values().copied().collect();
+        routines.sort_by(|a, b| {
+            b.inclusive
+                .cmp(&a.inclusive)
+                .then(a.routine.cmp(&b.routine))
+        });
+        routines
+    }
+
+    //The routine's label if symbols were loaded, sub_C010 and the like if not
+    pub fn name(&self, routine: Routine) -> String {
+        match self.names.get(&routine) {
+            Some(name) => name.clone(),
+            None => routine.to_string(),
+        }
+    }
+
+    pub fn routine(&self, routine: Routine) -> Option<&RoutineStats> {
+        self.routines.get(&routine)
+    }
+
+    //Oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTime> {
+        self.frames.iter()
+    }
+
+    //Routines, the hottest instructions and the average frame as text
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.total.max(1) as f64;
+
+        let _ = writeln!(out, "{} cycles", self.total);
+        let _ = writeln!(
+            out,
+            "{:>12} {:>7} {:>12} {:>7} {:>8}  Routine",
+            "Inclusive", "", "Exclusive", "", "Calls"
+        );
+        for stats in self.routines() {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>8}  {}",
+                stats.inclusive,
+                percent(stats.inclusive),
+                stats.exclusive,
+                percent(stats.exclusive),
+                stats.calls,
+                self.name(stats.routine)
+            );
+        }
+
+        let mut hot: Vec<(u16, u64)> = self
+            .by_pc
+            .iter()
+            .enumerate()
+            .filter(|&(_, &cycles)| cycles > 0)
+            .map(|(pc, &cycles)| (pc as u16, cycles))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "Hottest instructions:");
+        for (pc, cycles) in hot.into_iter().take(HOT_INSTRUCTIONS) {
+            let _ = writeln!(
+                out,
+                "  ${:04X} {:>12} {:>6.2}%",
+                pc,
+                cycles,
+                percent(cycles)
+            );
+        }
+
+        if !self.frames.is_empty() {
+            let count = self.frames.len() as u64;
+            let (nmi, irq, main) = self.frames.iter().fold((0, 0, 0), |sum, frame| {
+                (sum.0 + frame.nmi, sum.1 + frame.irq, sum.2 + frame.main)
+            });
+            let _ = writeln!(
+                out,
+                "Average of the last {} frames: NMI {} IRQ {} main {} cycles",
+                count,
+                nmi / count,
+                irq / count,
+                main / count
+            );
+        }
+        out
+    }
+
+    //One line per call stack with the cycles spent in it, "main;nmi_C000;sub_C123 450",
+    //for flamegraph.pl, inferno and speedscope
+    pub fn collapsed_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|&routine| self.name(routine)).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    //The next instruction after a reset isn't a continuation of the last one
+    pub(crate) fn reset(&mut self) {
+        self.pending = None;
+    }
+
+    //Called before an interrupt sequence starts
+    pub(crate) fn interrupt(&mut self, cycle: u64, frame: u64) {
+        self.finish_pending(cycle);
+        self.pending = Some(Pending::Interrupt { cycle, frame });
+    }
+
+    //Called before each instruction runs, with the call stack as the instruction sees it
+    //label gives the symbol at an address, if there is one
+    pub(crate) fn instruction<'a>(
+        &mut self,
+        pc: u16,
+        cycle: u64,
+        frame: u64,
+        frames: &[CallFrame],
+        label: &dyn Fn(u16) -> Option<&'a str>,
+    ) {
+        self.finish_pending(cycle);
+
+        let depth = self.stack.len();
+        self.stack.truncate(1);
+        self.stack.extend(frames.iter().map(Routine::from));
+
+        if let Some(Pending::Interrupt {
+            cycle: start,
+            frame,
+        }) = self.pending
+        {
+            //The interrupt sequence belongs to the handler it jumped to
+            self.charge(pc, frame, cycle - start);
+        }
+        if self.stack.len() > depth || self.pending.is_none() {
+            let routine = self.stack[self.stack.len() - 1];
+            self.stats(routine).calls += 1;
+            if let Some(name) = routine.entry().and_then(label) {
+                self.names.insert(routine, name.to_string());
+            }
+        }
+
+        self.pending = Some(Pending::Instruction { pc, cycle, frame });
+    }
+
+    fn finish_pending(&mut self, cycle: u64) {
+        if let Some(Pending::Instruction {
+            pc,
+            cycle: start,
+            frame,
+        }) = self.pending
+        {
+            self.charge(pc, frame, cycle - start);
+        }
+    }
+
+    //Charges cycles to pc and the current stack
+    fn charge(&mut self, pc: u16, frame: u64, cycles: u64) {
+        if cycles == 0 {
+            return;
+        }
+        self.total += cycles;
+        self.by_pc[pc as usize] += cycles;
+
+        let stack = std::mem::take(&mut self.stack);
+        for (i, &routine) in stack.iter().enumerate() {
+            //Recursion would count the same cycles twice
+            if !stack[..i].contains(&routine) {
+                self.stats(routine).inclusive += cycles;
+            }
+        }
+        self.stats(stack[stack.len() - 1]).exclusive += cycles;
+
+        match self.stacks.get_mut(&stack) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(stack.clone(), cycles);
+            }
+        }
+
+        if self.frames.back().is_none_or(|time| time.frame != frame) {
+            if self.frames.len() == self.frame_history {
+                self.frames.pop_front();
+            }
+            if self.frame_history > 0 {
+                self.frames.push_back(FrameTime {
+                    frame,
+                    ..FrameTime::default()
+                });
+            }
+        }
+        if let Some(time) = self.frames.back_mut() {
+            let in_handler = |kind: fn(&Routine) -> bool| stack.iter().any(kind);
+            if in_handler(|routine| matches!(routine, Routine::Nmi(_))) {
+                time.nmi += cycles;
+            } else if in_handler(|routine| matches!(routine, Routine::Irq(_))) {
+                time.irq += cycles;
+            } else {
+                time.main += cycles;
+            }
+        }
+
+        self.stack = stack;
+    }
+
+    fn stats(&mut self, routine: Routine) -> &mut RoutineStats {
+        self.routines.entry(routine).or_insert(RoutineStats {
+            routine,
+            calls: 0,
+            inclusive: 0,
+            exclusive: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: FrameKind, target: u16) -> CallFrame {
+        CallFrame {
+            kind,
+            caller: 0,
+            target,
+            stack_pointer: 0xFD,
+        }
+    }
+
+    #[test]
+    fn test_inclusive_and_exclusive() {
+        let sub = [frame(FrameKind::Subroutine, 0xC100)];
+        let mut profiler = Profiler::new();
+        profiler.instruction(0xC000, 0, 0, &[], &|_| None); //JSR, 6 cycles
+        profiler.instruction(0xC100, 6, 0, &sub, &|_| None); //LDA #, 2 cycles
+        profiler.instruction(0xC102, 8, 0, &sub, &|_| None); //RTS, 6 cycles
+        profiler.instruction(0xC003, 14, 0, &[], &|_| None);
+
+        assert_eq!(profiler.total_cycles(), 14);
+        assert_eq!(profiler.cycles_at(0xC102), 6);
+
+        let main = profiler.routine(Routine::Main).unwrap();
+        assert_eq!((main.inclusive, main.exclusive), (14, 6));
+        let sub = profiler.routine(Routine::Subroutine(0xC100)).unwrap();
+        assert_eq!((sub.inclusive, sub.exclusive, sub.calls), (8, 8, 1));
+
+        assert_eq!(profiler.collapsed_stacks(), "main 6\nmain;sub_C100 8\n");
+    }
+
+    #[test]
+    fn test_interrupt_time_per_frame() {
+        let nmi = [frame(FrameKind::Nmi, 0x9000)];
+        let mut profiler = Profiler::new();
+        profiler.instruction(0xC000, 0, 1, &[], &|_| None);
+        profiler.interrupt(3, 1);
+        profiler.instruction(0x9000, 10, 1, &nmi, &|_| None);
+        profiler.instruction(0x9001, 12, 2, &nmi, &|_| None);
+        profiler.instruction(0xC000, 18, 2, &[], &|_| None);
+        profiler.instruction(0xC000, 21, 2, &[], &|_| None);
+
+        let frames: Vec<FrameTime> = profiler.frames().copied().collect();
+        assert_eq!(
+            frames,
+            vec![
+                FrameTime {
+                    frame: 1,
+                    nmi: 9,
+                    irq: 0,
+                    main: 3
+                },
+                FrameTime {
+                    frame: 2,
+                    nmi: 6,
+                    irq: 0,
+                    main: 3
+                },
+            ]
+        );
+        //The interrupt sequence is charged to the handler's first instruction
+        assert_eq!(profiler.cycles_at(0x9000), 9);
+        assert!(profiler
+            .report()
+            .contains("Average of the last 2 frames: NMI 7 IRQ 0 main 3 cycles"));
+    }
+
+    #[test]
+    fn test_profiles_a_frame() {
+        //C000 JSR $C010, C003 JMP $C000, C010 INX, C011 RTS
+        let mut program = vec![0x20, 0x10, 0xC0, 0x4C, 0x00, 0xC0];
+        program.resize(0x10, 0xEA);
+        program.extend(&[0xE8, 0x60]);
+        let mut emu = crate::Emulator::new(crate::tests::test_rom(&program));
+        emu.debugger().start_profiler(Profiler::new());
+        emu.run_frame();
+
+        let profiler = emu.debugger().stop_profiler().unwrap();
+        let sub = *profiler.routine(Routine::Subroutine(0xC010)).unwrap();
+        let main = *profiler.routine(Routine::Main).unwrap();
+        //JSR 6 and JMP 3 in main, INX 2 and RTS 6 in the subroutine, per loop
+        let loops = main.exclusive / 9;
+        assert!((sub.exclusive / 8).abs_diff(loops) <= 1);
+        assert_eq!(main.inclusive, profiler.total_cycles());
+        assert!(sub.calls > 1000);
+        assert!(profiler.collapsed_stacks().contains("main;sub_C010 "));
+    }
+}
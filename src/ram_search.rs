@@ -0,0 +1,259 @@
+//RAM search, like FCEUX's. Start with every address in system RAM and PRG-RAM, then
+//narrow them down by comparing what's there now against the last snapshot or a
+//constant. Each search takes a new snapshot, so "less than previous" after losing a
+//life and "equal to previous" while nothing happens quickly find the lives counter
+
+use crate::memory::Domain;
+use crate::Emulator;
+
+//The domains searched. PRG-RAM is empty unless the iNES header asks for it
+const DOMAINS: [Domain; 2] = [Domain::SystemRam, Domain::PrgRam];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    Word, //Little-endian, starting at the address
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    ChangedBy(i64), //Value minus the operand is exactly this
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Previous, //The value in the last snapshot
+    Value(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub domain: Domain,
+    pub address: usize, //Offset in the domain, PRG-RAM offsets start at $6000 on the cpu
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchResult {
+    pub candidate: Candidate,
+    pub value: i64,
+    pub previous: i64,
+}
+
+pub struct RamSearch {
+    size: Size,
+    signed: bool,
+    snapshot: Vec<Vec<u8>>, //One dump for each of DOMAINS
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    //Every address is a candidate until the first search
+    pub fn new(emu: &Emulator, size: Size, signed: bool) -> Self {
+        let mut search = Self {
+            size,
+            signed,
+            snapshot: Vec::new(),
+            candidates: Vec::new(),
+        };
+        search.reset(emu);
+        search
+    }
+
+    pub fn reset(&mut self, emu: &Emulator) {
+        self.snapshot = take_snapshot(emu);
+        let width = self.width();
+        self.candidates = DOMAINS
+            .iter()
+            .zip(&self.snapshot)
+            .flat_map(|(&domain, data)| {
+                (0..(data.len() + 1).saturating_sub(width))
+                    .map(move |address| Candidate { domain, address })
+            })
+            .collect();
+    }
+
+    //Keeps the candidates where the value now compares true against the operand, then
+    //takes a new snapshot. Returns how many are left
+    pub fn search(&mut self, emu: &Emulator, comparison: Comparison, operand: Operand) -> usize {
+        let current = take_snapshot(emu);
+        let previous = std::mem::replace(&mut self.snapshot, current);
+
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain(|&candidate| {
+            let value = self.value(&self.snapshot, candidate);
+            let operand = match operand {
+                Operand::Previous => self.value(&previous, candidate),
+                Operand::Value(value) => value,
+            };
+            match comparison {
+                Comparison::Equal => value == operand,
+                Comparison::NotEqual => value != operand,
+                Comparison::Greater => value > operand,
+                Comparison::Less => value < operand,
+                Comparison::ChangedBy(change) => value - operand == change,
+            }
+        });
+        self.candidates = candidates;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    //The candidates with their values now and in the last snapshot
+    pub fn results(&self, emu: &Emulator) -> Vec<SearchResult> {
+        let current = take_snapshot(emu);
+        self.candidates
+            .iter()
+            .map(|&candidate| SearchResult {
+                candidate,
+                value: self.value(&current, candidate),
+                previous: self.value(&self.snapshot, candidate),
+            })
+            .collect()
+    }
+
+    fn width(&self) -> usize {
+        match self.size {
+            Size::Byte => 1,
+            Size::Word => 2,
+        }
+    }
+
+    fn value(&self, snapshot: &[Vec<u8>], candidate: Candidate) -> i64 {
+        let index = DOMAINS
+            .iter()
+            .position(|&domain| domain == candidate.domain)
+            .unwrap();
+        let data = &snapshot[index];
+        let address = candidate.address;
+        match (self.size, self.signed) {
+            (Size::Byte, false) => data[address] as i64,
+            (Size::Byte, true) => data[address] as i8 as i64,
+            (Size::Word, false) => u16::from_le_bytes([data[address], data[address + 1]]) as i64,
+            (Size::Word, true) => i16::from_le_bytes([data[address], data[address + 1]]) as i64,
+        }
+    }
+}
+
+fn take_snapshot(emu: &Emulator) -> Vec<Vec<u8>> {
+    DOMAINS
+        .iter()
+        .map(|&domain| emu.dump_domain(domain))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_rom;
+
+    fn emulator() -> Emulator {
+        Emulator::new(test_rom(&[]))
+    }
+
+    #[test]
+    fn test_find_counter() {
+        let mut emu = emulator();
+        emu.write_domain(Domain::SystemRam, 0x0075, 3);
+        let mut search = RamSearch::new(&emu, Size::Byte, false);
+        assert_eq!(search.candidates().len(), 0x800);
+
+        search.search(&emu, Comparison::Equal, Operand::Value(3));
+        assert_eq!(search.candidates().len(), 1);
+
+        //Lost a life
+        emu.write_domain(Domain::SystemRam, 0x0075, 2);
+        emu.write_domain(Domain::SystemRam, 0x0076, 2);
+        let mut search = RamSearch::new(&emu, Size::Byte, false);
+        emu.write_domain(Domain::SystemRam, 0x0075, 1);
+        emu.write_domain(Domain::SystemRam, 0x0300, 0x10);
+        assert_eq!(
+            search.search(&emu, Comparison::NotEqual, Operand::Previous),
+            2
+        );
+        assert_eq!(search.search(&emu, Comparison::Equal, Operand::Previous), 2);
+        assert_eq!(search.search(&emu, Comparison::Less, Operand::Value(2)), 1);
+
+        emu.write_domain(Domain::SystemRam, 0x0075, 0);
+        let results = search.results(&emu);
+        assert_eq!(
+            results,
+            [SearchResult {
+                candidate: Candidate {
+                    domain: Domain::SystemRam,
+                    address: 0x0075
+                },
+                value: 0,
+                previous: 1,
+            }]
+        );
+        assert_eq!(
+            search.search(&emu, Comparison::ChangedBy(-1), Operand::Previous),
+            1
+        );
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut rom = test_rom(&[]);
+        rom[6] |= 0b00000010; //Battery
+        let mut emu = Emulator::new(rom);
+        let mut search = RamSearch::new(&emu, Size::Byte, false);
+        assert_eq!(search.candidates().len(), 0x800 + 0x2000);
+
+        //A saved high score at $6010
+        emu.write_domain(Domain::CpuBus, 0x6010, 0x42);
+        search.search(&emu, Comparison::Greater, Operand::Previous);
+        assert_eq!(
+            search.candidates(),
+            [Candidate {
+                domain: Domain::PrgRam,
+                address: 0x0010
+            }]
+        );
+    }
+
+    #[test]
+    fn test_signed_words() {
+        let mut emu = emulator();
+        //A timer at $0040 counting down through zero
+        emu.write_domain(Domain::SystemRam, 0x0040, 0x01);
+        let mut search = RamSearch::new(&emu, Size::Word, true);
+        assert_eq!(search.candidates().len(), 0x7FF);
+
+        emu.write_domain(Domain::SystemRam, 0x0040, 0xFF);
+        emu.write_domain(Domain::SystemRam, 0x0041, 0xFF);
+        search.search(&emu, Comparison::ChangedBy(-2), Operand::Previous);
+        assert_eq!(
+            search.candidates(),
+            [Candidate {
+                domain: Domain::SystemRam,
+                address: 0x0040
+            }]
+        );
+        assert_eq!(search.results(&emu)[0].value, -1);
+
+        let mut unsigned = RamSearch::new(&emu, Size::Word, false);
+        unsigned.search(&emu, Comparison::Greater, Operand::Value(0x8000));
+        //$003F reads $FF00 and $0040 reads $FFFF
+        assert_eq!(
+            unsigned.candidates(),
+            [
+                Candidate {
+                    domain: Domain::SystemRam,
+                    address: 0x003F
+                },
+                Candidate {
+                    domain: Domain::SystemRam,
+                    address: 0x0040
+                }
+            ]
+        );
+    }
+}
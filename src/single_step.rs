@@ -0,0 +1,304 @@
+//Runner for Tom Harte's per-opcode cpu tests (SingleStepTests/ProcessorTests)
+//https://github.com/SingleStepTests/65x02
+//
+//Every opcode has a json file of tests, each giving the state before and after one
+//instruction and every bus access it makes, cycle by cycle. The vectors aren't
+//distributed with the repo, point NES6502_TESTS, MOS6502_TESTS or WDC65C02_TESTS at
+//the matching directory (e.g. 65x02/nes6502/v1) and run cargo test -- --ignored.
+
+use crate::cpu::{Cpu, Registers, Variant};
+use crate::memory::{AddressSpace, FlatRam};
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+//Flat 64k that records every access the cpu makes
+struct RecordingBus {
+    ram: FlatRam,
+    accesses: Vec<Access>,
+}
+
+impl AddressSpace for RecordingBus {
+    fn peek(&mut self, ptr: u16) -> u8 {
+        let value = self.ram.peek(ptr);
+        self.accesses.push(Access::Read(ptr, value));
+        value
+    }
+
+    fn debug_peek(&self, ptr: u16) -> u8 {
+        self.ram.debug_peek(ptr)
+    }
+
+    fn poke(&mut self, ptr: u16, byte: u8) {
+        self.accesses.push(Access::Write(ptr, byte));
+        self.ram.poke(ptr, byte);
+    }
+}
+
+struct State {
+    registers: Registers,
+    ram: Vec<(u16, u8)>,
+}
+
+//The failing tests of one opcode file
+pub struct OpcodeReport {
+    pub opcode: String,
+    pub tests: usize,
+    pub failures: Vec<String>,
+}
+
+impl fmt::Display for OpcodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} failed, first: {}",
+            self.opcode,
+            self.failures.len(),
+            self.tests,
+            self.failures.first().map(String::as_str).unwrap_or("")
+        )
+    }
+}
+
+//Runs every opcode file in the directory. Returns the opcodes with failures
+pub fn run_directory(directory: &Path, variant: Variant, skip: &[u8]) -> Vec<OpcodeReport> {
+    let mut paths: Vec<_> = std::fs::read_dir(directory)
+        .unwrap_or_else(|e| panic!("Can't read {}: {}", directory.display(), e))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(
+        !paths.is_empty(),
+        "No opcode json files in {}",
+        directory.display()
+    );
+
+    paths
+        .iter()
+        .filter(|path| {
+            let stem = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("");
+            u8::from_str_radix(stem, 16).map_or(true, |opcode| !skip.contains(&opcode))
+        })
+        .map(|path| {
+            let json = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Can't read {}: {}", path.display(), e));
+            let opcode = path.file_stem().unwrap().to_string_lossy().to_uppercase();
+            run_file(&opcode, &json, variant)
+        })
+        .filter(|report| !report.failures.is_empty())
+        .collect()
+}
+
+pub fn run_file(opcode: &str, json: &str, variant: Variant) -> OpcodeReport {
+    let tests: Value = serde_json::from_str(json)
+        .unwrap_or_else(|e| panic!("Bad json for opcode {}: {}", opcode, e));
+    let tests = tests.as_array().expect("Test file isn't a list");
+
+    OpcodeReport {
+        opcode: opcode.to_string(),
+        tests: tests.len(),
+        failures: tests
+            .iter()
+            .filter_map(|test| run_test(test, variant).err())
+            .collect(),
+    }
+}
+
+//Runs one instruction from the initial state and compares it with the final one
+fn run_test(test: &Value, variant: Variant) -> Result<(), String> {
+    let name = test["name"].as_str().unwrap_or("?");
+    let initial = parse_state(&test["initial"]);
+    let expected = parse_state(&test["final"]);
+    let cycles = parse_cycles(&test["cycles"]);
+
+    let mut ram = FlatRam::new();
+    for &(address, value) in &initial.ram {
+        ram.poke(address, value);
+    }
+    let bus = RecordingBus {
+        ram,
+        accesses: Vec::with_capacity(cycles.len()),
+    };
+    let mut cpu = Cpu::with_variant(bus, variant);
+    cpu.set_registers(initial.registers);
+
+    loop {
+        cpu.step_cycle();
+        if cpu.instruction_finished() {
+            break;
+        }
+    }
+
+    let registers = cpu.registers();
+    if registers != expected.registers {
+        return Err(format!(
+            "\"{}\" registers {:X?}, expected {:X?}",
+            name, registers, expected.registers
+        ));
+    }
+
+    for &(address, value) in &expected.ram {
+        let actual = cpu.bus.debug_peek(address);
+        if actual != value {
+            return Err(format!(
+                "\"{}\" ${:04X} = {:02X}, expected {:02X}",
+                name, address, actual, value
+            ));
+        }
+    }
+
+    if cpu.bus.accesses != cycles {
+        return Err(format!(
+            "\"{}\" bus {:X?}, expected {:X?}",
+            name, cpu.bus.accesses, cycles
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_state(state: &Value) -> State {
+    let number = |key: &str| {
+        state[key]
+            .as_u64()
+            .unwrap_or_else(|| panic!("State is missing {}", key))
+    };
+
+    State {
+        registers: Registers {
+            pc: number("pc") as u16,
+            s: number("s") as u8,
+            p: number("p") as u8,
+            a: number("a") as u8,
+            x: number("x") as u8,
+            y: number("y") as u8,
+        },
+        ram: state["ram"]
+            .as_array()
+            .expect("State is missing ram")
+            .iter()
+            .map(|entry| {
+                (
+                    entry[0].as_u64().unwrap() as u16,
+                    entry[1].as_u64().unwrap() as u8,
+                )
+            })
+            .collect(),
+    }
+}
+
+//Each cycle is [address, value, "read" or "write"]
+fn parse_cycles(cycles: &Value) -> Vec<Access> {
+    cycles
+        .as_array()
+        .expect("Test is missing cycles")
+        .iter()
+        .map(|cycle| {
+            let address = cycle[0].as_u64().unwrap() as u16;
+            let value = cycle[1].as_u64().unwrap() as u8;
+            match cycle[2].as_str() {
+                Some("read") => Access::Read(address, value),
+                Some("write") => Access::Write(address, value),
+                other => panic!("Unknown cycle type {:?}", other),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //JAM halts the cpu, the vectors keep reading the bus for a fixed number of cycles
+    const NMOS_JAMS: [u8; 12] = [
+        0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+    ];
+
+    fn run_vectors(variable: &str, variant: Variant, skip: &[u8]) {
+        let directory = std::env::var(variable)
+            .unwrap_or_else(|_| panic!("Set {} to a directory of vectors", variable));
+
+        let failed = run_directory(Path::new(&directory), variant, skip);
+        if !failed.is_empty() {
+            let report: Vec<String> = failed.iter().map(|report| report.to_string()).collect();
+            panic!("{} opcodes failed\n{}", failed.len(), report.join("\n"));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the vectors, set NES6502_TESTS"]
+    fn test_nes6502_vectors() {
+        run_vectors("NES6502_TESTS", Variant::Ricoh2A03, &NMOS_JAMS);
+    }
+
+    #[test]
+    #[ignore = "needs the vectors, set MOS6502_TESTS"]
+    fn test_6502_vectors() {
+        run_vectors("MOS6502_TESTS", Variant::Nmos6502, &NMOS_JAMS);
+    }
+
+    //WAI and STP wait on the bus the same way
+    #[test]
+    #[ignore = "needs the vectors, set WDC65C02_TESTS"]
+    fn test_65c02_vectors() {
+        run_vectors("WDC65C02_TESTS", Variant::Wdc65C02, &[0xCB, 0xDB]);
+    }
+
+    //LDA #$7F then LDA ($10),Y crossing a page, in the vector format
+    const VECTORS: &str = r#"[
+        {
+            "name": "a9 7f 00",
+            "initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+                "ram": [[512, 169], [513, 127]]},
+            "final": {"pc": 514, "s": 253, "a": 127, "x": 0, "y": 0, "p": 36,
+                "ram": [[512, 169], [513, 127]]},
+            "cycles": [[512, 169, "read"], [513, 127, "read"]]
+        }
+    ]"#;
+
+    const PAGE_CROSS: &str = r#"[
+        {
+            "name": "b1 10",
+            "initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 255, "p": 36,
+                "ram": [[512, 177], [513, 16], [16, 1], [17, 3], [1024, 128]]},
+            "final": {"pc": 514, "s": 253, "a": 128, "x": 0, "y": 255, "p": 164,
+                "ram": [[1024, 128]]},
+            "cycles": [[512, 177, "read"], [513, 16, "read"], [16, 1, "read"],
+                [17, 3, "read"], [768, 0, "read"], [1024, 128, "read"]]
+        }
+    ]"#;
+
+    #[test]
+    fn test_run_file_passes() {
+        let report = run_file("A9", VECTORS, Variant::Ricoh2A03);
+        assert_eq!(report.tests, 1);
+        assert!(report.failures.is_empty(), "{}", report);
+
+        let report = run_file("B1", PAGE_CROSS, Variant::Ricoh2A03);
+        assert!(report.failures.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn test_run_file_reports_failures() {
+        //Claim LDA takes an extra cycle
+        let wrong = VECTORS.replace(
+            r#"[513, 127, "read"]]"#,
+            r#"[513, 127, "read"], [514, 0, "read"]]"#,
+        );
+        let report = run_file("A9", &wrong, Variant::Ricoh2A03);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report
+            .to_string()
+            .starts_with("A9: 1 of 1 failed, first: \"a9 7f 00\" bus"));
+    }
+}
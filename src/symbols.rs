@@ -0,0 +1,591 @@
+//Debug symbols: labels and source lines loaded from ld65 --dbgfile output, FCEUX .nl
+//name lists and Mesen .mlb label files. Anything in PRG-ROM is keyed by its offset in
+//the ROM, so the same cpu address in two banks gets two labels
+//https://cc65.github.io/doc/ld65.html#s7
+//http://fceux.com/web/help/Debugger.html
+//https://www.mesen.ca/docs/debugging/debuggerintegration.html
+
+use crate::cartridge::{ProgramData, PRG_BANK_SIZE};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+const INES_HEADER_SIZE: usize = 16;
+
+//Where a symbol lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Location {
+    Cpu(u16),   //RAM, registers and anything else that isn't banked
+    Prg(usize), //Offset into PRG-ROM, bank is offset / PRG_BANK_SIZE
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    Syntax { line: usize, message: String }, //Line is 1-based
+    Unknown(String),                         //No label or source line by that name
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(error) => write!(f, "{}", error),
+            SymbolError::Syntax { line, message } => write!(f, "{} on line {}", message, line),
+            SymbolError::Unknown(name) => write!(f, "No symbol or source line {}", name),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(error: io::Error) -> Self {
+        SymbolError::Io(error)
+    }
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    labels: HashMap<Location, String>,
+    names: HashMap<String, Vec<Location>>,
+    lines: HashMap<Location, SourceLine>, //Only the first byte of each line's code
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    //A later label at the same location replaces the earlier one
+    pub fn add_label(&mut self, location: Location, name: &str) {
+        if let Some(old) = self.labels.insert(location, name.to_string()) {
+            if let Some(locations) = self.names.get_mut(&old) {
+                locations.retain(|&other| other != location);
+            }
+        }
+        let locations = self.names.entry(name.to_string()).or_default();
+        if !locations.contains(&location) {
+            locations.push(location);
+        }
+    }
+
+    pub fn add_line(&mut self, location: Location, line: SourceLine) {
+        self.lines.insert(location, line);
+    }
+
+    pub fn label(&self, location: Location) -> Option<&str> {
+        self.labels.get(&location).map(String::as_str)
+    }
+
+    //Local labels can share a name, so there may be several
+    pub fn locations(&self, name: &str) -> &[Location] {
+        self.names.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn source_line(&self, location: Location) -> Option<&SourceLine> {
+        self.lines.get(&location)
+    }
+
+    //Start of the code for a source line. The file matches on its name alone too, so
+    //"main.s:10" finds "src/main.s:10"
+    pub fn line_locations(&self, file: &str, line: u32) -> Vec<Location> {
+        let mut locations: Vec<Location> = self
+            .lines
+            .iter()
+            .filter(|(_, source)| {
+                source.line == line
+                    && (source.file == file || source.file.ends_with(&format!("/{}", file)))
+            })
+            .map(|(&location, _)| location)
+            .collect();
+        locations.sort();
+        locations
+    }
+
+    //A label, or file:line
+    pub fn resolve(&self, name: &str) -> Result<Vec<Location>, SymbolError> {
+        let locations = match self.locations(name) {
+            [] => match name.rsplit_once(':') {
+                Some((file, line)) => match line.parse() {
+                    Ok(line) => self.line_locations(file, line),
+                    Err(_) => Vec::new(),
+                },
+                None => Vec::new(),
+            },
+            locations => locations.to_vec(),
+        };
+        if locations.is_empty() {
+            Err(SymbolError::Unknown(name.to_string()))
+        } else {
+            Ok(locations)
+        }
+    }
+
+    //Where a cpu address points with the banks mapped in now
+    pub(crate) fn location(cartridge: &ProgramData, address: u16) -> Location {
+        match cartridge.rom_offset(address) {
+            Some(offset) => Location::Prg(offset),
+            None => Location::Cpu(address),
+        }
+    }
+
+    //Label of a cpu address, falling back to one that wasn't given a bank
+    pub(crate) fn label_at(&self, cartridge: &ProgramData, address: u16) -> Option<&str> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        self.label(Self::location(cartridge, address))
+            .or_else(|| self.label(Location::Cpu(address)))
+    }
+
+    //Picks the format from the file name. FCEUX names its lists game.nes.0.nl for
+    //bank 0 and game.nes.ram.nl for RAM
+    pub fn load_file(&mut self, path: &Path) -> Result<(), SymbolError> {
+        let text = std::fs::read_to_string(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let bank = |name: &str| {
+            let stem = name.trim_end_matches(".nl");
+            match stem.rsplit_once('.') {
+                Some((_, "ram")) | None => None,
+                Some((_, bank)) => usize::from_str_radix(bank, 16).ok(),
+            }
+        };
+
+        if name.ends_with(".dbg") {
+            self.load_dbg(&text)
+        } else if name.ends_with(".nl") {
+            self.load_nl(&text, bank(&name))
+        } else if name.ends_with(".mlb") {
+            self.load_mlb(&text)
+        } else {
+            Err(SymbolError::Syntax {
+                line: 0,
+                message: format!("Unknown symbol file type {}", path.display()),
+            })
+        }
+    }
+
+    //FCEUX name list, one "$C000#Label#Comment" per line. Arrays are "$0300/10#Name#"
+    pub fn load_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), SymbolError> {
+        for (number, line) in numbered_lines(text) {
+            let mut parts = line.splitn(3, '#');
+            let address = parts.next().unwrap_or("");
+            let name = parts.next().unwrap_or("").trim();
+            let address = address
+                .strip_prefix('$')
+                .and_then(|address| address.split('/').next())
+                .and_then(|address| u16::from_str_radix(address, 16).ok())
+                .ok_or_else(|| syntax(number, "Expected $address#name#"))?;
+            if name.is_empty() {
+                continue;
+            }
+
+            let location = match bank {
+                Some(bank) if address >= 0x8000 => {
+                    Location::Prg(bank * PRG_BANK_SIZE + (address as usize % PRG_BANK_SIZE))
+                }
+                _ => Location::Cpu(address),
+            };
+            self.add_label(location, name);
+        }
+        Ok(())
+    }
+
+    //Mesen label file, one "P:1234:Label:Comment" per line. P is a PRG-ROM offset, R
+    //internal RAM, S and W save and work RAM at 0x6000, G a cpu address. Mesen 2 spells
+    //them NesPrgRom, NesInternalRam, NesSaveRam, NesWorkRam and NesMemory. Save and work
+    //RAM past the first 8k is in banks no supported mapper switches in, so it's skipped
+    pub fn load_mlb(&mut self, text: &str) -> Result<(), SymbolError> {
+        for (number, line) in numbered_lines(text) {
+            let parts: Vec<&str> = line.splitn(4, ':').collect();
+            if parts.len() < 3 {
+                return Err(syntax(number, "Expected type:address:label"));
+            }
+            let name = parts[2].trim();
+            let start = parts[1].split('-').next().unwrap_or("");
+            let address =
+                usize::from_str_radix(start, 16).map_err(|_| syntax(number, "Bad address"))?;
+            if name.is_empty() {
+                continue; //A comment on its own
+            }
+
+            let out_of_range = || syntax(number, "Address out of range");
+            let location = match parts[0] {
+                "P" | "NesPrgRom" => Location::Prg(address),
+                "R" | "NesInternalRam" if address < 0x0800 => Location::Cpu(address as u16),
+                "G" | "NesMemory" if address < 0x10000 => Location::Cpu(address as u16),
+                "R" | "NesInternalRam" | "G" | "NesMemory" => return Err(out_of_range()),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" if address < 0x2000 => {
+                    Location::Cpu(0x6000 + address as u16)
+                }
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" if address < 0x10000 => continue,
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => return Err(out_of_range()),
+                //CHR and the PPU's memory have no cpu address
+                _ => continue,
+            };
+            self.add_label(location, name);
+        }
+        Ok(())
+    }
+
+    //ld65 --dbgfile output. Labels come from the sym records, source lines from line
+    //records through their spans. Segments written to the ROM file have an offset in it
+    //(ooffs), which past the iNES header is the PRG-ROM offset
+    pub fn load_dbg(&mut self, text: &str) -> Result<(), SymbolError> {
+        #[derive(Default)]
+        struct Segment {
+            start: u32,
+            rom_offset: Option<usize>,
+        }
+
+        let mut files: HashMap<u32, String> = HashMap::new();
+        let mut segments: HashMap<u32, Segment> = HashMap::new();
+        let mut spans: HashMap<u32, (u32, u32)> = HashMap::new(); //Segment and offset in it
+        let mut symbols = Vec::new();
+        let mut lines = Vec::new();
+
+        for (number, line) in numbered_lines(text) {
+            let (kind, fields) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let fields = parse_fields(fields).map_err(|message| syntax(number, message))?;
+            let number_field = |key: &str| {
+                fields
+                    .get(key)
+                    .map(|value| parse_number(value))
+                    .unwrap_or(Ok(0))
+                    .map_err(|_| syntax(number, &format!("Bad number in {}", key)))
+            };
+            let id = number_field("id")?;
+
+            match kind {
+                "file" => {
+                    let name = fields.get("name").copied().unwrap_or("");
+                    files.insert(id, name.to_string());
+                }
+                "seg" => {
+                    let rom_offset = match fields.get("ooffs") {
+                        Some(_) => (number_field("ooffs")? as usize).checked_sub(INES_HEADER_SIZE),
+                        None => None,
+                    };
+                    segments.insert(
+                        id,
+                        Segment {
+                            start: number_field("start")?,
+                            rom_offset,
+                        },
+                    );
+                }
+                "span" => {
+                    spans.insert(id, (number_field("seg")?, number_field("start")?));
+                }
+                "sym" if fields.get("type") == Some(&"lab") => {
+                    let name = fields.get("name").copied().unwrap_or("").to_string();
+                    let segment = fields.get("seg").map(|_| number_field("seg")).transpose()?;
+                    symbols.push((name, number_field("val")?, segment));
+                }
+                //Macro expansions map the same bytes back to the macro
+                "line" if fields.get("type") != Some(&"2") => {
+                    let span_ids: Vec<u32> = match fields.get("span") {
+                        Some(ids) => ids
+                            .split('+')
+                            .map(parse_number)
+                            .collect::<Result<_, _>>()
+                            .map_err(|_| syntax(number, "Bad span list"))?,
+                        None => Vec::new(),
+                    };
+                    lines.push((number_field("file")?, number_field("line")?, span_ids));
+                }
+                _ => (),
+            }
+        }
+
+        //Segment start and offset into it, to a PRG-ROM offset or cpu address
+        let locate = |segment: &Segment, offset: u32| match segment.rom_offset {
+            Some(rom_offset) => Location::Prg(rom_offset + offset as usize),
+            None => Location::Cpu((segment.start + offset) as u16),
+        };
+
+        for (name, value, segment) in symbols {
+            let location = match segment.and_then(|segment| segments.get(&segment)) {
+                Some(segment) => locate(segment, value.wrapping_sub(segment.start)),
+                None => Location::Cpu(value as u16),
+            };
+            self.add_label(location, &name);
+        }
+
+        for (file, line, span_ids) in lines {
+            let file = match files.get(&file) {
+                Some(file) => file.clone(),
+                None => continue,
+            };
+            for span in span_ids {
+                let located = spans.get(&span).and_then(|&(segment, offset)| {
+                    segments
+                        .get(&segment)
+                        .map(|segment| locate(segment, offset))
+                });
+                if let Some(location) = located {
+                    self.add_line(
+                        location,
+                        SourceLine {
+                            file: file.clone(),
+                            line,
+                        },
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn syntax(line: usize, message: &str) -> SymbolError {
+    SymbolError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+//Non-blank lines with their 1-based numbers, comments (;) removed
+fn numbered_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+}
+
+//key=value,key="quoted, value"
+fn parse_fields(text: &str) -> Result<HashMap<&str, &str>, &'static str> {
+    let mut fields = HashMap::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=').ok_or("Expected key=value")?;
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').ok_or("Unterminated string")?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => match after.find(',') {
+                Some(end) => (&after[..end], &after[end..]),
+                None => (after, ""),
+            },
+        };
+        fields.insert(key.trim(), value);
+        rest = after.trim_start_matches(',').trim_start();
+    }
+    Ok(fields)
+}
+
+fn parse_number(text: &str) -> Result<u32, std::num::ParseIntError> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::debugger::StopReason;
+    use crate::profiler::{Profiler, Routine};
+    use crate::tests::test_rom;
+    use crate::Emulator;
+
+    fn cartridge(banks: u8) -> ProgramData {
+        let mut rom = vec![
+            0x4E, 0x45, 0x53, 0x1A, banks, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.extend(vec![0; banks as usize * PRG_BANK_SIZE + 0x2000]);
+        Cartridge::load(rom).take_program_data()
+    }
+
+    const DBG: &str = r#"version	major=2,minor=0
+info	csym=0,file=2,lib=0,line=3,mod=1,scope=1,seg=3,span=3,sym=4,type=4
+file	id=0,name="src/main.s",size=400,mtime=0x5F000000,mod=0
+file	id=1,name="src/nmi.s",size=100,mtime=0x5F000000,mod=0
+line	id=0,file=0,line=12,span=0
+line	id=1,file=1,line=3,span=1+2
+line	id=2,file=0,line=40,type=2,span=2
+seg	id=0,name="CODE",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname="game.nes",ooffs=16400
+seg	id=1,name="BSS",start=0x000300,size=0x0010,addrsize=absolute,type=rw
+seg	id=2,name="BANK0",start=0x008000,size=0x0100,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+span	id=0,seg=0,start=0,size=3
+span	id=1,seg=0,start=0x10,size=2
+span	id=2,seg=2,start=4,size=1
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=0,val=0xC000,seg=0,type=lab
+sym	id=1,name="nmi",addrsize=absolute,scope=0,def=1,val=0xC010,seg=0,type=lab
+sym	id=2,name="buffer",addrsize=absolute,scope=0,def=2,val=0x300,seg=1,type=lab
+sym	id=3,name="SPEED",addrsize=zeropage,scope=0,def=3,val=0x3,type=equ
+"#;
+
+    #[test]
+    fn test_load_dbg() {
+        let mut symbols = Symbols::new();
+        symbols.load_dbg(DBG).unwrap();
+
+        assert_eq!(symbols.label(Location::Prg(0x4000)), Some("reset"));
+        assert_eq!(symbols.label(Location::Prg(0x4010)), Some("nmi"));
+        assert_eq!(symbols.label(Location::Cpu(0x0300)), Some("buffer"));
+        assert_eq!(symbols.label(Location::Cpu(0x0003)), None);
+
+        assert_eq!(
+            symbols
+                .source_line(Location::Prg(0x4000))
+                .unwrap()
+                .to_string(),
+            "src/main.s:12"
+        );
+        //The macro line doesn't replace the source line
+        assert_eq!(
+            symbols
+                .source_line(Location::Prg(0x0004))
+                .unwrap()
+                .to_string(),
+            "src/nmi.s:3"
+        );
+        assert_eq!(
+            symbols.resolve("nmi.s:3").unwrap(),
+            vec![Location::Prg(0x0004), Location::Prg(0x4010)]
+        );
+        assert_eq!(
+            symbols.resolve("reset").unwrap(),
+            vec![Location::Prg(0x4000)]
+        );
+        assert!(matches!(
+            symbols.resolve("main.s:13"),
+            Err(SymbolError::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_nl() {
+        let mut symbols = Symbols::new();
+        symbols
+            .load_nl("$C004#Init#Sets up the PPU\n$0300/10#Buffer#\n", Some(1))
+            .unwrap();
+        assert_eq!(symbols.label(Location::Prg(0x4004)), Some("Init"));
+        assert_eq!(symbols.label(Location::Cpu(0x0300)), Some("Buffer"));
+
+        assert!(matches!(
+            symbols.load_nl("C004#Init#", None),
+            Err(SymbolError::Syntax { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_mlb() {
+        let mut symbols = Symbols::new();
+        symbols
+            .load_mlb("P:4004:Init:Sets up: the PPU\nR:0010:Temp\nNesWorkRam:0020-002F:Save\nP:10::Only a comment\n")
+            .unwrap();
+        assert_eq!(symbols.label(Location::Prg(0x4004)), Some("Init"));
+        assert_eq!(symbols.label(Location::Cpu(0x0010)), Some("Temp"));
+        assert_eq!(symbols.label(Location::Cpu(0x6020)), Some("Save"));
+        assert_eq!(symbols.label(Location::Prg(0x10)), None);
+
+        //A second 8k bank of save RAM, and offsets no RAM is that big
+        symbols
+            .load_mlb("S:A000:Banked\nNesSaveRam:2010:Banked\n")
+            .unwrap();
+        assert_eq!(symbols.label(Location::Cpu(0x6010)), None);
+        assert!(matches!(
+            symbols.load_mlb("S:10000:Huge"),
+            Err(SymbolError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            symbols.load_mlb("R:0800:Mirror"),
+            Err(SymbolError::Syntax { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_banks_resolve_separately() {
+        let mut symbols = Symbols::new();
+        symbols.add_label(Location::Prg(0x0010), "bank0");
+        symbols.add_label(Location::Prg(0x4010), "bank1");
+        symbols.add_label(Location::Cpu(0x2002), "PPUSTATUS");
+
+        //Two 16k banks, $C010 is in the second
+        let nrom_256 = cartridge(2);
+        assert_eq!(symbols.label_at(&nrom_256, 0xC010), Some("bank1"));
+        assert_eq!(symbols.label_at(&nrom_256, 0x8010), Some("bank0"));
+        assert_eq!(symbols.label_at(&nrom_256, 0x2002), Some("PPUSTATUS"));
+
+        //One bank mirrored at $C000
+        let nrom_128 = cartridge(1);
+        assert_eq!(symbols.label_at(&nrom_128, 0xC010), Some("bank0"));
+        assert_eq!(nrom_128.cpu_addresses(0x0010), vec![0x8010, 0xC010]);
+        assert_eq!(nrom_256.cpu_addresses(0x4010), vec![0xC010]);
+    }
+
+    #[test]
+    fn test_relabel() {
+        let mut symbols = Symbols::new();
+        symbols.add_label(Location::Cpu(0x10), "old");
+        symbols.add_label(Location::Cpu(0x10), "new");
+        assert!(symbols.locations("old").is_empty());
+        assert_eq!(symbols.locations("new"), &[Location::Cpu(0x10)]);
+    }
+
+    #[test]
+    fn test_emulator_uses_labels() {
+        //C000 JSR $C010, C003 JMP $C000, C010 LDA $0300, C013 RTS
+        let mut program = vec![0x20, 0x10, 0xC0, 0x4C, 0x00, 0xC0];
+        program.resize(0x10, 0xEA);
+        program.extend(&[0xAD, 0x00, 0x03, 0x60]);
+        let mut emu = Emulator::new(test_rom(&program));
+        emu.debugger()
+            .symbols()
+            .load_mlb("P:0010:update\nR:0300:buffer\n")
+            .unwrap();
+
+        let text: Vec<String> = emu
+            .disassemble(0xC000, 0xC003)
+            .into_iter()
+            .map(|instruction| instruction.text)
+            .collect();
+        assert_eq!(text, vec!["JSR update", "JMP $C000"]);
+        let lda = &emu.disassemble(0xC010, 0xC010)[0];
+        assert_eq!(lda.label.as_deref(), Some("update"));
+        assert_eq!(lda.text, "LDA buffer");
+
+        //NROM-128 maps the bank twice
+        assert_eq!(
+            emu.add_breakpoint_at("update").unwrap(),
+            vec![0x8010, 0xC010]
+        );
+        emu.debugger().start_profiler(Profiler::new());
+        assert_eq!(emu.run_frame(), StopReason::Breakpoint(0xC010));
+        assert_eq!(emu.label(0xC010), Some("update"));
+        assert!(matches!(
+            emu.add_breakpoint_at("nowhere"),
+            Err(SymbolError::Unknown(_))
+        ));
+
+        emu.debugger().clear();
+        emu.run_frame();
+        let profiler = emu.debugger().stop_profiler().unwrap();
+        assert_eq!(profiler.name(Routine::Subroutine(0xC010)), "update");
+        assert!(profiler.collapsed_stacks().contains("main;update "));
+    }
+}
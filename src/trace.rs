@@ -0,0 +1,351 @@
+//Instruction trace logging. Each instruction the cpu starts is written as one line,
+//with the effective address and the value there, to any Write
+
+use crate::cpu::{Cpu, Registers};
+use crate::disasm::{self, AddressingMode, Disassembly};
+use crate::memory::{AddressSpace, Bus};
+use crate::symbols::Symbols;
+use std::io::{self, Write};
+
+//Which instructions get logged. The default logs everything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    pub pc_range: Option<(u16, u16)>, //Inclusive
+    pub bank: Option<usize>,          //PRG bank, see ProgramData::bank
+    pub from_frame: u64,
+    pub max_lines: Option<usize>,
+}
+
+pub struct Tracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+    lines: usize,
+    error: Option<io::Error>, //First write error, tracing stops there
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Self {
+            output,
+            filter,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    //Flushes the output and hands back the first error, if writing failed
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.output.flush(),
+        }
+    }
+
+    fn wants(&self, cpu: &Cpu<Bus>) -> bool {
+        let pc = cpu.registers().pc;
+        let filter = &self.filter;
+        self.error.is_none()
+            && filter.max_lines.is_none_or(|max| self.lines < max)
+            && cpu.bus.ppu.frame() >= filter.from_frame
+            && filter
+                .pc_range
+                .is_none_or(|(start, end)| (start..=end).contains(&pc))
+            && filter
+                .bank
+                .is_none_or(|bank| cpu.bus.cartridge.bank(pc) == Some(bank))
+    }
+
+    //Called before each instruction
+    pub(crate) fn instruction(&mut self, cpu: &Cpu<Bus>, symbols: &Symbols) {
+        if !self.wants(cpu) {
+            return;
+        }
+        match writeln!(self.output, "{}", trace_line(cpu, symbols)) {
+            Ok(()) => self.lines += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+//One line per instruction in the nestest.log format, which Mesen's default trace
+//format follows too
+//C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//Labelled addresses are written as their label
+//PPU is the NTSC scanline and dot counted from power-on, 3 dots per cpu cycle on
+//341 dot lines like nestest.log, not this PPU's own 256 dot counters
+pub(crate) fn trace_line(cpu: &Cpu<Bus>, symbols: &Symbols) -> String {
+    let bus = &cpu.bus;
+    let regs = cpu.registers();
+
+    let decoded = disasm::decode(bus, regs.pc, cpu.variant());
+    let mut operand = operand_text(bus, &regs, &decoded);
+    if let Some(name) = decoded
+        .target()
+        .and_then(|target| symbols.label_at(&bus.cartridge, target))
+    {
+        operand = decoded.with_target_name(&operand, name);
+    }
+    let text = format!("{} {}", decoded.mnemonic, operand);
+
+    let bytes: Vec<String> = std::iter::once(&decoded.opcode)
+        .chain(&decoded.operands)
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    let dots = cpu.cycles() * 3;
+    let (scanline, dot) = ((dots / 341) % 262, dots % 341);
+
+    format!(
+        "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        regs.pc,
+        bytes.join(" "),
+        text.trim_end(),
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.p,
+        regs.s,
+        scanline,
+        dot,
+        cpu.cycles()
+    )
+}
+
+//The disassembler's operand, with the effective address and value nestest.log shows
+fn operand_text<T: AddressSpace>(bus: &T, regs: &Registers, decoded: &Disassembly) -> String {
+    let data = &decoded.operands;
+    let zero_page_16 = |ptr: u8| {
+        u16::from_le_bytes([
+            bus.debug_peek(ptr as u16),
+            bus.debug_peek(ptr.wrapping_add(1) as u16),
+        ])
+    };
+
+    match decoded.addressing_mode {
+        AddressingMode::ZeroPage => {
+            format!("${:02X} = {:02X}", data[0], bus.debug_peek(data[0] as u16))
+        }
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let (name, index) = match decoded.addressing_mode {
+                AddressingMode::ZeroPageX => ('X', regs.x),
+                _ => ('Y', regs.y),
+            };
+            let address = data[0].wrapping_add(index);
+            format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                data[0],
+                name,
+                address,
+                bus.debug_peek(address as u16)
+            )
+        }
+        AddressingMode::Absolute => {
+            let address = u16::from_le_bytes([data[0], data[1]]);
+            match decoded.mnemonic.as_str() {
+                "JMP" | "JSR" => format!("${:04X}", address),
+                _ => format!("${:04X} = {:02X}", address, bus.debug_peek(address)),
+            }
+        }
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let (name, index) = match decoded.addressing_mode {
+                AddressingMode::AbsoluteX => ('X', regs.x),
+                _ => ('Y', regs.y),
+            };
+            let base = u16::from_le_bytes([data[0], data[1]]);
+            let address = base.wrapping_add(index as u16);
+            format!(
+                "${:04X},{} @ {:04X} = {:02X}",
+                base,
+                name,
+                address,
+                bus.debug_peek(address)
+            )
+        }
+        AddressingMode::Indirect => {
+            let pointer = u16::from_le_bytes([data[0], data[1]]);
+            //The high byte is fetched without carrying into the page
+            let high = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+            let target = u16::from_le_bytes([bus.debug_peek(pointer), bus.debug_peek(high)]);
+            format!("(${:04X}) = {:04X}", pointer, target)
+        }
+        AddressingMode::IndirectX => {
+            let pointer = data[0].wrapping_add(regs.x);
+            let address = zero_page_16(pointer);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                data[0],
+                pointer,
+                address,
+                bus.debug_peek(address)
+            )
+        }
+        AddressingMode::ZeroPageIndirect => {
+            let address = zero_page_16(data[0]);
+            format!(
+                "(${:02X}) = {:04X} = {:02X}",
+                data[0],
+                address,
+                bus.debug_peek(address)
+            )
+        }
+        AddressingMode::IndirectY => {
+            let base = zero_page_16(data[0]);
+            let address = base.wrapping_add(regs.y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                data[0],
+                base,
+                address,
+                bus.debug_peek(address)
+            )
+        }
+        _ => decoded.operand().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::StopReason;
+    use crate::tests::test_rom;
+    use crate::Emulator;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    //Lets the test read back what the tracer wrote
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedOutput {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    //C000 LDX #$00, C002 STX $10, C004 INX, C005 JMP $C002
+    const PROGRAM: [u8; 8] = [0xA2, 0x00, 0x86, 0x10, 0xE8, 0x4C, 0x02, 0xC0];
+
+    fn trace(filter: TraceFilter, frames: usize) -> Vec<String> {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        let output = SharedOutput::default();
+        emu.start_trace(Box::new(output.clone()), filter);
+        for _ in 0..frames {
+            assert_eq!(emu.run_frame(), StopReason::FrameComplete);
+        }
+        emu.stop_trace().unwrap();
+        output.lines()
+    }
+
+    #[test]
+    fn test_trace_lines() {
+        let lines = trace(
+            TraceFilter {
+                max_lines: Some(4),
+                ..TraceFilter::default()
+            },
+            1,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                "C000  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "C002  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 27 CYC:9",
+                "C004  E8        INX                             A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+                "C005  4C 02 C0  JMP $C002                       A:00 X:01 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_ppu_wraps_at_341_dots() {
+        let lines = trace(
+            TraceFilter {
+                max_lines: Some(50),
+                ..TraceFilter::default()
+            },
+            1,
+        );
+        let line = lines
+            .iter()
+            .find(|line| line.ends_with(" CYC:116"))
+            .unwrap();
+        assert!(line.starts_with("C004  E8        INX "));
+        assert!(line.contains(" PPU:  1,  7 "));
+    }
+
+    #[test]
+    fn test_trace_labels() {
+        let mut emu = Emulator::new(test_rom(&PROGRAM));
+        emu.debugger()
+            .symbols()
+            .load_nl("$C002#loop#\n$0010#counter#\n", Some(0))
+            .unwrap();
+        let output = SharedOutput::default();
+        emu.start_trace(
+            Box::new(output.clone()),
+            TraceFilter {
+                max_lines: Some(4),
+                ..TraceFilter::default()
+            },
+        );
+        emu.run_frame();
+        emu.stop_trace().unwrap();
+
+        let lines = output.lines();
+        assert!(lines[1].starts_with("C002  86 10     STX counter = 00 "));
+        assert!(lines[3].starts_with("C005  4C 02 C0  JMP loop "));
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        let lines = trace(
+            TraceFilter {
+                pc_range: Some((0xC004, 0xC004)),
+                max_lines: Some(3),
+                ..TraceFilter::default()
+            },
+            1,
+        );
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.starts_with("C004  E8")));
+
+        let lines = trace(
+            TraceFilter {
+                bank: Some(1),
+                ..TraceFilter::default()
+            },
+            1,
+        );
+        assert!(lines.is_empty());
+
+        //Nothing before frame 1, then lines carry on from the second frame
+        let lines = trace(
+            TraceFilter {
+                from_frame: 1,
+                max_lines: Some(1),
+                ..TraceFilter::default()
+            },
+            2,
+        );
+        assert_eq!(lines.len(), 1);
+        assert!(!lines[0].ends_with("CYC:7"));
+    }
+}